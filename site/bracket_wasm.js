@@ -0,0 +1,43 @@
+// Runs a module made by `bracket compile --target wasm` (or compile_wasm) and
+// returns the value of its last statement. Errors caught by try-catch are
+// passed to `diagnostic`, an uncaught error is thrown with the interpreter's message.
+export async function runWasm(bytes, output, diagnostic) {
+    let memory = null;
+    let uncaught = null;
+
+    const message = (kind, code, ptr, len, a, b) => {
+        const text = new TextDecoder().decode(new Uint8Array(memory.buffer, ptr, len));
+        switch (kind) {
+            case 0: return "Uncaught error with code " + (code >>> 0);
+            case 1: return "Cannot decrement zero";
+            case 2: return "Variable " + text + " not defined";
+            case 3: return "Function " + text + " not defined";
+            case 4: return "Function " + text + " expects " + (a >>> 0) + " arguments, got " + (b >>> 0);
+            case 16: return "Cannot throw " + (a >>> 0) + ", codes 1 to " + (b >>> 0) + " are reserved for built-in errors";
+            default: return text;
+        }
+    };
+
+    const env = {
+        print_num: (x) => output((x >>> 0) + "\n"),
+        print_char: (x) => {
+            x = x >>> 0;
+            output(x > 0x10FFFF || (x >= 0xD800 && x <= 0xDFFF) ? "?" : String.fromCodePoint(x));
+        },
+        report: (caught, ...error) => {
+            if (caught) {
+                diagnostic("Warning!: Error caught in try block: " + message(...error) + "\n");
+            } else {
+                uncaught = message(...error);
+            }
+        },
+    };
+
+    const { instance } = await WebAssembly.instantiate(bytes, { env });
+    memory = instance.exports.memory;
+    const value = instance.exports.main();
+    if (uncaught !== null) {
+        throw new Error(uncaught);
+    }
+    return Number(value);
+}
@@ -1,22 +1,124 @@
-import initSync, { eval_unwrapped } from './node_modules/eso-lang/eso_lang.js';
+import initSync, { Session, Options, compile_js, compile_wasm } from './node_modules/eso-lang/eso_lang.js';
+import { runWasm } from './bracket_wasm.js';
+
+// Steps evaluated per animation frame, small enough to keep the page responsive.
+const STEPS_PER_FRAME = 200000;
+
+let session = null;
+let frame = null; // the pending requestAnimationFrame(runSlice)
 
 async function runCode() {
+    await initSync();
+    stopCode();
+    const code = document.getElementById('code').value;
+    const mode = document.getElementById('mode').value;
+    if (mode === 'js') {
+        runCompiled(code);
+        return;
+    }
+    if (mode === 'wasm') {
+        await runCompiledWasm(code);
+        return;
+    }
+    const options = new Options();
+    options.max_depth = 1000000;
+    session = new Session(code, options);
+    frame = requestAnimationFrame(runSlice);
+}
+
+function runSlice() {
+    frame = null;
+    if (session === null) {
+        return;
+    }
+    const output = document.getElementById('output');
+    try {
+        if (!session.run_slice(STEPS_PER_FRAME)) {
+            frame = requestAnimationFrame(runSlice);
+            return;
+        }
+        const res = session.result();
+        output.value += res.startsWith('Error') ? res + '\n' : "EXITED WITH VALUE: " + res + '\n';
+    }
+    catch (e) {
+        output.value += "Interpreter crashed: " + e.toString() + "\nPLEASE REFRESH THE PAGE!!\n";
+    }
+    session.free();
+    session = null;
+}
+
+// Compiles the program to JavaScript and runs it in one go, it cannot be stopped.
+function runCompiled(code) {
+    const output = document.getElementById('output');
+    const js = compile_js(code, new Options());
+    if (js.startsWith('Error')) {
+        output.value += js + '\n';
+        return;
+    }
+    try {
+        const res = eval(js)(text => output.value += text, text => console.warn(text.trimEnd()));
+        output.value += "EXITED WITH VALUE: " + res + '\n';
+    }
+    catch (e) {
+        output.value += "Error: " + e.message + '\n';
+    }
+}
+
+// Compiles the program to a WebAssembly module and runs it in one go, it cannot be stopped either.
+async function runCompiledWasm(code) {
+    const output = document.getElementById('output');
+    try {
+        const bytes = compile_wasm(code, new Options());
+        const res = await runWasm(bytes, text => output.value += text, text => console.warn(text.trimEnd()));
+        output.value += "EXITED WITH VALUE: " + res + '\n';
+    }
+    catch (e) {
+        output.value += "Error: " + (e instanceof Error ? e.message : e) + '\n';
+    }
+}
+
+async function downloadWasm() {
     await initSync();
     const code = document.getElementById('code').value;
+    let bytes;
     try {
-        const output = document.getElementById('output');
-        let res = eval_unwrapped(code);
-        output.value += "EXITED WITH VALUE: "+ res + '\n';
+        bytes = compile_wasm(code, new Options());
     }
     catch (e) {
-        const output = document.getElementById('output');
-        output.value += "Stack overflow: Due to the reliance on recursion + small stack size of wasm, sadly the stack overflows too fast ;(\nPLEASE REFRESH THE PAGE!!\n";
-        // output.value += e.toString() + '\n';
+        document.getElementById('output').value += "Error: " + e + '\n';
+        return;
+    }
+    const link = document.createElement('a');
+    link.href = URL.createObjectURL(new Blob([bytes], { type: 'application/wasm' }));
+    link.download = 'program.wasm';
+    link.click();
+    setTimeout(() => URL.revokeObjectURL(link.href), 0);
+}
+
+async function showCompiled() {
+    await initSync();
+    const code = document.getElementById('code').value;
+    document.getElementById('output').value += compile_js(code, new Options()) + '\n';
+}
+
+function stopCode() {
+    if (frame !== null) {
+        cancelAnimationFrame(frame);
+        frame = null;
+    }
+    if (session === null) {
         return;
     }
+    session.cancel();
+    document.getElementById('output').value += "STOPPED after " + session.steps() + " steps\n";
+    session.free();
+    session = null;
 }
 
 document.getElementById('run').addEventListener('click', runCode);
+document.getElementById('stop').addEventListener('click', stopCode);
+document.getElementById('show-js').addEventListener('click', showCompiled);
+document.getElementById('download-wasm').addEventListener('click', downloadWasm);
 
 document.getElementById('clear').addEventListener('click', () => {
     const output = document.getElementById('output');
@@ -162,7 +264,7 @@ let examples = {
     {(()()()()())} [ {()}[] [()] ] /fac_help(x,1)/
 ]
 
-{((()()))} [ [()()()()()()] ] /fac(6) !!DON'T do more than 6 the lang is too unoptimized/
+{((()()))} [ [()()()()()()] ] /fac(6) !!bigger inputs take a while, use Stop if you get bored/
 `
 
 };
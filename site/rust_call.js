@@ -1,4 +1,8 @@
 export function logOutput(message) {
     let output = document.getElementById('output');
     output.value += message;
-}
\ No newline at end of file
+}
+
+export function logDiagnostic(message) {
+    console.warn(message.trimEnd());
+}
@@ -0,0 +1,476 @@
+use crate::parse::{Expression, Var};
+use std::collections::HashMap;
+
+// Compiles a tiny conventional language into Bracket expressions, so nobody
+// has to count parens by hand. printer.rs turns the result into source.
+//
+//   // comments run to the end of the line
+//   fn add(x, y) {
+//       if y { add(x + 1, y - 1) } else { x }
+//   }
+//   let n = add(2, 3);
+//   print n;                      // prints a number
+//   putc 'A'; putc "hi\n";        // prints characters
+//   try { throw 42 } catch (code) { code }
+//   assert n;                     // fails when n is 0
+//
+// Expressions are separated by `;` (optional after a `}`), the value of a
+// block is its last expression and `if` without `else` is 0 when the
+// condition is 0. Only numbers can be added to or subtracted from (`x + 2`),
+// those become `&`/`*` chains. Like in Bracket, variables can be defined once
+// per function and a function only sees its parameters and its own variables.
+// Functions without parameters get an unused one, Bracket has no such functions.
+//
+// With `compact`, numbers are printed in the compact notation (see
+// parse::get_num). With `shortest`, a number literal becomes an `&` chain
+// whenever that is shorter: `&&&[]` beats `[()()()]`, but not `[((((()))))]`
+// (16 in the compact notation).
+
+const KEYWORDS: [&str; 10] = ["fn", "let", "if", "else", "print", "putc", "try", "catch", "throw", "assert"];
+
+pub fn assemble(source: &str, shortest: bool, compact: bool) -> Result<Vec<Expression>, String> {
+    let tokens = tokenize(source)?;
+    let functions = declare_functions(&tokens)?;
+    let mut assembler = Assembler { tokens, index: 0, shortest, compact, functions, frame: Frame::default() };
+    assembler.program()
+}
+
+// Bracket source for `source`, with the original names of functions in comments.
+pub fn to_bracket(source: &str, shortest: bool, compact: bool) -> Result<String, String> {
+    let statements = assemble(source, shortest, compact)?;
+    let names: HashMap<Var, String> = declare_functions(&tokenize(source)?)?
+        .into_iter()
+        .map(|(name, (var, _))| (var, name))
+        .collect();
+    let mut out = String::new();
+    for exp in &statements {
+        if let Expression::FunctionDef(var, args, _) = exp
+            && !args.is_empty()
+            && let Some(name) = names.get(var)
+        {
+            out.push_str(&format!("/fn {}/\n", name));
+        }
+        out.push_str(&crate::printer::expression(exp, compact)?);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Num(u32),
+    Str(String),
+    Punct(char),
+}
+
+// Tokens with the line they start on.
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.peek().is_some_and(|c| *c != '\n') {
+                    chars.next();
+                }
+            }
+            c if c.is_ascii_digit() => {
+                let mut digits = c.to_string();
+                while let Some(d) = chars.peek().filter(|d| d.is_ascii_digit()) {
+                    digits.push(*d);
+                    chars.next();
+                }
+                let num = digits.parse().map_err(|_| format!("line {}: {} does not fit in 32 bits", line, digits))?;
+                tokens.push((Token::Num(num), line));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut ident = c.to_string();
+                while let Some(d) = chars.peek().filter(|d| d.is_alphanumeric() || **d == '_') {
+                    ident.push(*d);
+                    chars.next();
+                }
+                tokens.push((Token::Ident(ident), line));
+            }
+            '\'' => {
+                let c = unescape(&mut chars, '\'', line)?.ok_or_else(|| format!("line {}: empty character literal", line))?;
+                if chars.next() != Some('\'') {
+                    return Err(format!("line {}: expected ' after character literal", line));
+                }
+                tokens.push((Token::Num(c as u32), line));
+            }
+            '"' => {
+                let mut text = String::new();
+                while let Some(c) = unescape(&mut chars, '"', line)? {
+                    text.push(c);
+                }
+                tokens.push((Token::Str(text), line));
+            }
+            '(' | ')' | '{' | '}' | ',' | ';' | '=' | '+' | '-' => tokens.push((Token::Punct(c), line)),
+            c => return Err(format!("line {}: unexpected character {}", line, c)),
+        }
+    }
+    Ok(tokens)
+}
+
+// Next character of a literal, None at the closing quote.
+fn unescape(chars: &mut std::iter::Peekable<std::str::Chars>, quote: char, line: usize) -> Result<Option<char>, String> {
+    match chars.next() {
+        Some(c) if c == quote => Ok(None),
+        Some('\\') => match chars.next() {
+            Some('n') => Ok(Some('\n')),
+            Some('t') => Ok(Some('\t')),
+            Some('0') => Ok(Some('\0')),
+            Some(c @ ('\\' | '\'' | '"')) => Ok(Some(c)),
+            Some(c) => Err(format!("line {}: unknown escape \\{}", line, c)),
+            None => Err(format!("line {}: unterminated literal", line)),
+        },
+        Some(c) => Ok(Some(c)),
+        None => Err(format!("line {}: unterminated literal", line)),
+    }
+}
+
+// Bracket names and arities of all functions, so calls may come before definitions.
+fn declare_functions(tokens: &[(Token, usize)]) -> Result<HashMap<String, (Var, usize)>, String> {
+    let mut functions = HashMap::new();
+    let mut names = Names::default();
+    for (i, (token, line)) in tokens.iter().enumerate() {
+        if *token != Token::Ident("fn".to_string()) {
+            continue;
+        }
+        let Some((Token::Ident(name), _)) = tokens.get(i + 1) else {
+            return Err(format!("line {}: expected a function name after fn", line));
+        };
+        let params = tokens[i + 2..]
+            .iter()
+            .take_while(|(token, _)| *token != Token::Punct(')'))
+            .filter(|(token, _)| matches!(token, Token::Ident(_)))
+            .count();
+        if functions.insert(name.clone(), (names.next(), params)).is_some() {
+            return Err(format!("line {}: function {} is defined twice", line, name));
+        }
+    }
+    Ok(functions)
+}
+
+// Hands out Bracket names, shortest first: (), (()), (()()), ((())), ...
+#[derive(Default)]
+struct Names {
+    pending: Vec<String>,
+    length: usize,
+}
+
+impl Names {
+    fn next(&mut self) -> Var {
+        while self.pending.is_empty() {
+            let mut inner = balanced(self.length);
+            inner.sort();
+            inner.reverse();
+            self.pending = inner.into_iter().map(|s| format!("({})", s)).collect();
+            self.length += 2;
+        }
+        Var(self.pending.pop().unwrap())
+    }
+}
+
+// All balanced paren strings of the given (even) length.
+fn balanced(length: usize) -> Vec<String> {
+    if length == 0 {
+        return vec![String::new()];
+    }
+    let mut all = Vec::new();
+    for inner in (0..length - 1).step_by(2) {
+        for a in balanced(inner) {
+            for b in balanced(length - 2 - inner) {
+                all.push(format!("({}){}", a, b));
+            }
+        }
+    }
+    all
+}
+
+// Variables of the function (or top level) being assembled.
+#[derive(Default)]
+struct Frame {
+    vars: HashMap<String, Var>,
+    names: Names,
+}
+
+impl Frame {
+    fn define(&mut self, name: &str) -> Var {
+        let var = self.names.next();
+        self.vars.insert(name.to_string(), var.clone());
+        var
+    }
+}
+
+struct Assembler {
+    tokens: Vec<(Token, usize)>,
+    index: usize,
+    shortest: bool,
+    compact: bool,
+    functions: HashMap<String, (Var, usize)>,
+    frame: Frame,
+}
+
+impl Assembler {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index).map(|(token, _)| token)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens.get(self.index).or(self.tokens.last()).map_or(1, |(_, line)| *line)
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, String> {
+        match self.peek() {
+            Some(_) => Err(format!("line {}: {}", self.line(), message)),
+            None => Err(format!("line {}: {} at the end of the program", self.line(), message)),
+        }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.index += 1;
+        token
+    }
+
+    fn at(&self, c: char) -> bool {
+        self.peek() == Some(&Token::Punct(c))
+    }
+
+    fn at_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(ident)) if ident == keyword)
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if !self.at(c) {
+            return self.error(&format!("expected {}", c));
+        }
+        self.index += 1;
+        Ok(())
+    }
+
+    fn ident(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some(Token::Ident(ident)) if !KEYWORDS.contains(&ident.as_str()) => {
+                let ident = ident.clone();
+                self.index += 1;
+                Ok(ident)
+            }
+            _ => self.error("expected a name"),
+        }
+    }
+
+    fn number(&self, num: u32) -> Expression {
+        // `&` chains take num + 2 characters
+        if !self.shortest || crate::printer::number(num, self.compact).len() <= num as usize + 2 {
+            return Expression::Val(num);
+        }
+        (0..num).fold(Expression::Val(0), |exp, _| apply("&", exp))
+    }
+
+    fn program(&mut self) -> Result<Vec<Expression>, String> {
+        let mut statements = Vec::new();
+        while self.peek().is_some() {
+            if self.at_keyword("fn") {
+                statements.push(self.function()?);
+            } else {
+                statements.push(self.expression()?);
+                self.separator()?;
+            }
+        }
+        Ok(statements)
+    }
+
+    // After an expression: `;`, or nothing before a `}`, the end or after a block.
+    fn separator(&mut self) -> Result<(), String> {
+        if self.at(';') {
+            self.index += 1;
+            return Ok(());
+        }
+        let after_block = self.index > 0 && self.tokens[self.index - 1].0 == Token::Punct('}');
+        if self.peek().is_none() || self.at('}') || after_block {
+            return Ok(());
+        }
+        self.error("expected ;")
+    }
+
+    fn function(&mut self) -> Result<Expression, String> {
+        self.index += 1;
+        let name = self.ident()?;
+        let var = self.functions[&name].0.clone();
+        let outer = std::mem::take(&mut self.frame);
+        self.expect('(')?;
+        let mut args = Vec::new();
+        while !self.at(')') {
+            if !args.is_empty() {
+                self.expect(',')?;
+            }
+            let param = self.ident()?;
+            if self.frame.vars.contains_key(&param) {
+                return self.error(&format!("parameter {} appears twice", param));
+            }
+            args.push(self.frame.define(&param));
+        }
+        self.index += 1;
+        if args.is_empty() {
+            args.push(self.frame.names.next());
+        }
+        let body = self.block()?;
+        self.frame = outer;
+        Ok(Expression::FunctionDef(var, args, Box::new(body)))
+    }
+
+    // `{ a; b; c }`, a group of the expressions.
+    fn block(&mut self) -> Result<Expression, String> {
+        self.expect('{')?;
+        let mut items = Vec::new();
+        while !self.at('}') {
+            if self.peek().is_none() {
+                return self.error("expected }");
+            }
+            items.push(self.expression()?);
+            self.separator()?;
+        }
+        self.index += 1;
+        Ok(Expression::ListExp(items))
+    }
+
+    fn expression(&mut self) -> Result<Expression, String> {
+        let keyword = match self.peek() {
+            Some(Token::Ident(ident)) if KEYWORDS.contains(&ident.as_str()) => ident.clone(),
+            _ => return self.sum(),
+        };
+        self.index += 1;
+        match keyword.as_str() {
+            "let" => {
+                let name = self.ident()?;
+                if self.frame.vars.contains_key(&name) {
+                    return self.error(&format!("{} is already defined, variables cannot change", name));
+                }
+                self.expect('=')?;
+                let value = self.expression()?;
+                let var = self.frame.define(&name);
+                Ok(Expression::FunctionDef(var, vec![], Box::new(Expression::ListExp(vec![value]))))
+            }
+            "if" => {
+                let cond = self.expression()?;
+                let then_block = self.block()?;
+                let else_block = if self.at_keyword("else") {
+                    self.index += 1;
+                    if self.at_keyword("if") {
+                        Expression::ListExp(vec![self.expression()?])
+                    } else {
+                        self.block()?
+                    }
+                } else {
+                    Expression::ListExp(vec![])
+                };
+                Ok(Expression::If(Box::new(Expression::ListExp(vec![cond])), Box::new(then_block), Box::new(else_block)))
+            }
+            "print" => Ok(Expression::PrintNum(Box::new(self.expression()?))),
+            "putc" => {
+                if let Some(Token::Str(text)) = self.peek() {
+                    let chars = text.chars().map(|c| Expression::PrintChar(Box::new(self.number(c as u32)))).collect();
+                    self.index += 1;
+                    return Ok(Expression::ListExp(chars));
+                }
+                Ok(Expression::PrintChar(Box::new(self.expression()?)))
+            }
+            "throw" => Ok(Expression::Throw(Box::new(self.expression()?))),
+            "assert" => Ok(Expression::Assert(Box::new(self.expression()?), None)),
+            "try" => {
+                let try_block = self.block()?;
+                if !self.at_keyword("catch") {
+                    return self.error("expected catch");
+                }
+                self.index += 1;
+                let mut binding = None;
+                if self.at('(') {
+                    self.index += 1;
+                    let name = self.ident()?;
+                    binding = Some(match self.frame.vars.get(&name) {
+                        Some(var) => var.clone(),
+                        None => self.frame.define(&name),
+                    });
+                    self.expect(')')?;
+                }
+                let catch_block = self.block()?;
+                Ok(Expression::TryCatch(Box::new(try_block), binding, Box::new(catch_block)))
+            }
+            _ => self.error(&format!("unexpected {}", keyword)),
+        }
+    }
+
+    // `atom + 1 - 2 ...`
+    fn sum(&mut self) -> Result<Expression, String> {
+        let mut exp = self.atom()?;
+        while self.at('+') || self.at('-') {
+            let op = if self.at('+') { "&" } else { "*" };
+            self.index += 1;
+            let Some(Token::Num(num)) = self.next() else {
+                self.index -= 1;
+                return self.error("only numbers can be added or subtracted");
+            };
+            for _ in 0..num {
+                exp = apply(op, exp);
+            }
+        }
+        Ok(exp)
+    }
+
+    fn atom(&mut self) -> Result<Expression, String> {
+        match self.peek().cloned() {
+            Some(Token::Num(num)) => {
+                self.index += 1;
+                Ok(self.number(num))
+            }
+            Some(Token::Punct('(')) => {
+                self.index += 1;
+                let exp = self.expression()?;
+                self.expect(')')?;
+                Ok(exp)
+            }
+            Some(Token::Punct('{')) => self.block(),
+            Some(Token::Ident(_)) => {
+                let name = self.ident()?;
+                if !self.at('(') {
+                    return match self.frame.vars.get(&name) {
+                        Some(var) => Ok(Expression::FunctionCall(var.clone(), Box::new(Expression::ListExp(vec![])))),
+                        None => self.error(&format!("{} is not defined here", name)),
+                    };
+                }
+                self.index += 1;
+                let mut params = Vec::new();
+                while !self.at(')') {
+                    if !params.is_empty() {
+                        self.expect(',')?;
+                    }
+                    params.push(self.expression()?);
+                }
+                self.index += 1;
+                let Some((var, arity)) = self.functions.get(&name).cloned() else {
+                    return self.error(&format!("function {} is not defined", name));
+                };
+                if params.len() != arity {
+                    return self.error(&format!("{} expects {} arguments, got {}", name, arity, params.len()));
+                }
+                if params.is_empty() {
+                    params.push(Expression::Val(0));
+                }
+                Ok(Expression::FunctionCall(var, Box::new(Expression::ListExp(params))))
+            }
+            Some(Token::Str(_)) => self.error("strings can only be printed with putc"),
+            _ => self.error("expected an expression"),
+        }
+    }
+}
+
+// `&x` or `*x`
+fn apply(op: &str, exp: Expression) -> Expression {
+    Expression::FunctionCall(Var(op.to_string()), Box::new(Expression::ListExp(vec![exp])))
+}
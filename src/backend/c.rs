@@ -0,0 +1,315 @@
+use crate::resolve::{Function, Node};
+use crate::Options;
+use super::escape;
+use std::fmt::Write;
+
+// Translates a program into a standalone C99 file. Errors are raised with
+// longjmp to the innermost try-catch (set up with setjmp), or end the program
+// with "Error: ..." on stderr like the command line interpreter does. The
+// compiled program prints the value of its last statement at the end too.
+
+const RUNTIME: &str = r#"#include <setjmp.h>
+#include <stdarg.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+typedef uint32_t (*br_fn)(const uint32_t *args);
+
+typedef struct br_handler {
+    jmp_buf buf;
+    struct br_handler *prev;
+} br_handler;
+
+static br_handler *br_top = NULL;
+static uint32_t br_code;
+static char br_message[256];
+
+static void br_fail(uint32_t code, const char *format, ...) {
+    va_list args;
+    va_start(args, format);
+    vsnprintf(br_message, sizeof br_message, format, args);
+    va_end(args);
+    br_code = code;
+    br_handler *handler = br_top;
+    if (handler == NULL) {
+        fflush(stdout);
+        fprintf(stderr, "Error: %s\n", br_message);
+        exit(1);
+    }
+    br_top = handler->prev;
+    longjmp(handler->buf, 1);
+}
+
+/* codes 1 to 15 are reserved for built-in errors (eval::RESERVED_CODES) */
+static void br_throw(uint32_t code) {
+    if (code >= 1 && code <= 15) br_fail(5, "Cannot throw %u, codes 1 to 15 are reserved for built-in errors", code);
+    br_fail(code, "Uncaught error with code %u", code);
+}
+
+static inline void br_caught(void) {
+    fflush(stdout);
+    fprintf(stderr, "Warning!: Error caught in try block: %s\n", br_message);
+}
+
+static inline uint32_t br_inc(uint32_t x) {
+    return x == UINT32_MAX ? x : x + 1;
+}
+
+static inline uint32_t br_dec(uint32_t x) {
+    if (x == 0) br_fail(1, "Cannot decrement zero");
+    return x - 1;
+}
+
+static inline uint32_t br_add(uint32_t x, uint32_t y) {
+    return x > UINT32_MAX - y ? UINT32_MAX : x + y;
+}
+
+static inline uint32_t br_sub(uint32_t x, uint32_t y) {
+    if (x < y) br_fail(1, "Cannot decrement zero");
+    return x - y;
+}
+
+static inline uint32_t br_mul(uint32_t x, uint32_t y) {
+    uint64_t product = (uint64_t)x * y;
+    return product > UINT32_MAX ? UINT32_MAX : (uint32_t)product;
+}
+
+static inline uint32_t br_print_num(uint32_t x) {
+    printf("%u\n", x);
+    return x;
+}
+
+/* Prints x as a UTF-8 character, '?' if it is not a valid code point. */
+static inline uint32_t br_print_char(uint32_t x) {
+    uint32_t c = (x > 0x10FFFF || (x >= 0xD800 && x <= 0xDFFF)) ? '?' : x;
+    if (c < 0x80) {
+        putchar(c);
+    } else if (c < 0x800) {
+        putchar(0xC0 | (c >> 6));
+        putchar(0x80 | (c & 0x3F));
+    } else if (c < 0x10000) {
+        putchar(0xE0 | (c >> 12));
+        putchar(0x80 | ((c >> 6) & 0x3F));
+        putchar(0x80 | (c & 0x3F));
+    } else {
+        putchar(0xF0 | (c >> 18));
+        putchar(0x80 | ((c >> 12) & 0x3F));
+        putchar(0x80 | ((c >> 6) & 0x3F));
+        putchar(0x80 | (c & 0x3F));
+    }
+    return x;
+}
+"#;
+
+pub fn compile(code: &str, options: &Options) -> Result<String, String> {
+    let (statements, resolver) = super::resolve_program(code, options)?;
+    let mut generator = Generator::default();
+
+    let mut main = String::new();
+    let globals = resolver.globals.len().max(1);
+    writeln!(main, "int main(void) {{").unwrap();
+    writeln!(main, "    static volatile uint32_t v[{}];", globals).unwrap();
+    writeln!(main, "    static volatile unsigned char d[{}];", globals).unwrap();
+    writeln!(main, "    uint32_t last = 0;").unwrap();
+    for node in &statements {
+        let val = generator.emit(node, &mut main, 1);
+        writeln!(main, "    last = {};", val).unwrap();
+    }
+    writeln!(main, "    printf(\"%u\\n\", last);").unwrap();
+    writeln!(main, "    return 0;").unwrap();
+    writeln!(main, "}}").unwrap();
+
+    let mut out = String::new();
+    out.push_str("/* Generated from a Bracket program */\n");
+    out.push_str(RUNTIME);
+    let table_size = resolver.function_count().max(1);
+    writeln!(out, "\nstatic br_fn fn_table[{}];", table_size).unwrap();
+    writeln!(out, "static unsigned fn_arity[{}];\n", table_size).unwrap();
+    for k in 0..generator.functions.len() {
+        writeln!(out, "static uint32_t fn_{}(const uint32_t *args);", k).unwrap();
+    }
+    for function in &generator.functions {
+        out.push('\n');
+        out.push_str(function);
+    }
+    out.push('\n');
+    out.push_str(&main);
+    Ok(out)
+}
+
+#[derive(Default)]
+struct Generator {
+    functions: Vec<String>,
+    temps: usize,
+}
+
+impl Generator {
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        format!("t{}", self.temps)
+    }
+
+    // Emits a C function for a definition, returns its number.
+    fn function(&mut self, function: &Function) -> usize {
+        let k = self.functions.len();
+        self.functions.push(String::new());
+        let size = function.frame_size.max(1);
+        let mut out = String::new();
+        writeln!(out, "/* {} */", function.name).unwrap();
+        writeln!(out, "static uint32_t fn_{}(const uint32_t *args) {{", k).unwrap();
+        writeln!(out, "    volatile uint32_t v[{}];", size).unwrap();
+        writeln!(out, "    volatile unsigned char d[{}] = {{0}};", size).unwrap();
+        for (i, slot) in function.args.iter().enumerate() {
+            writeln!(out, "    v[{}] = args[{}];", slot, i).unwrap();
+            writeln!(out, "    d[{}] = 1;", slot).unwrap();
+        }
+        let val = self.emit(&function.body, &mut out, 1);
+        writeln!(out, "    return {};", val).unwrap();
+        writeln!(out, "}}").unwrap();
+        self.functions[k] = out;
+        k
+    }
+
+    // Emits statements computing `node`, returns a C expression for its value
+    // that stays valid afterwards (a constant or a fresh temporary).
+    fn emit(&mut self, node: &Node, out: &mut String, depth: usize) -> String {
+        let pad = "    ".repeat(depth);
+        match node {
+            Node::Val(num) => format!("{}u", num),
+
+            Node::If(cond, then_node, else_node) => {
+                let cond = self.emit(cond, out, depth);
+                let result = self.temp();
+                writeln!(out, "{}uint32_t {};", pad, result).unwrap();
+                writeln!(out, "{}if ({}) {{", pad, cond).unwrap();
+                let val = self.emit(then_node, out, depth + 1);
+                writeln!(out, "{}    {} = {};", pad, result, val).unwrap();
+                writeln!(out, "{}}} else {{", pad).unwrap();
+                let val = self.emit(else_node, out, depth + 1);
+                writeln!(out, "{}    {} = {};", pad, result, val).unwrap();
+                writeln!(out, "{}}}", pad).unwrap();
+                result
+            }
+
+            Node::DefineVar(slot, body) => {
+                let val = self.emit(body, out, depth);
+                writeln!(out, "{}if (!d[{}]) {{ v[{}] = {}; d[{}] = 1; }}", pad, slot, slot, val, slot).unwrap();
+                val
+            }
+
+            Node::DefineFunction(index, function) => {
+                let k = self.function(function);
+                writeln!(out, "{}fn_table[{}] = fn_{};", pad, index, k).unwrap();
+                writeln!(out, "{}fn_arity[{}] = {};", pad, index, function.args.len()).unwrap();
+                "0u".to_string()
+            }
+
+            Node::Read(slot, var) => {
+                let result = self.temp();
+                writeln!(out, "{}if (!d[{}]) br_fail(2, \"Variable %s not defined\", \"{}\");", pad, slot, escape(&var.0)).unwrap();
+                writeln!(out, "{}uint32_t {} = v[{}];", pad, result, slot).unwrap();
+                result
+            }
+
+            Node::Inc(arg) => {
+                let val = self.emit(arg, out, depth);
+                let result = self.temp();
+                writeln!(out, "{}uint32_t {} = br_inc({});", pad, result, val).unwrap();
+                result
+            }
+
+            Node::Dec(arg) => {
+                let val = self.emit(arg, out, depth);
+                let result = self.temp();
+                writeln!(out, "{}uint32_t {} = br_dec({});", pad, result, val).unwrap();
+                result
+            }
+
+            Node::Call(index, var, params) => {
+                let callee = self.temp();
+                let name = escape(&var.0);
+                writeln!(out, "{}br_fn {} = fn_table[{}];", pad, callee, index).unwrap();
+                writeln!(out, "{}if (!{}) br_fail(3, \"Function %s not defined\", \"{}\");", pad, callee, name).unwrap();
+                writeln!(out, "{}if (fn_arity[{}] != {}) br_fail(4, \"Function %s expects %u arguments, got %u\", \"{}\", fn_arity[{}], {}u);", pad, index, params.len(), name, index, params.len()).unwrap();
+                let vals: Vec<String> = params.iter().map(|param| self.emit(param, out, depth)).collect();
+                let args = self.temp();
+                let result = self.temp();
+                writeln!(out, "{}uint32_t {}[] = {{ {} }};", pad, args, vals.join(", ")).unwrap();
+                writeln!(out, "{}uint32_t {} = {}({});", pad, result, callee, args).unwrap();
+                result
+            }
+
+            Node::List(items) => {
+                let mut val = "0u".to_string();
+                for item in items {
+                    val = self.emit(item, out, depth);
+                }
+                val
+            }
+
+            Node::TryCatch(try_node, binding, catch_node) => {
+                let result = self.temp();
+                let handler = self.temp();
+                writeln!(out, "{}volatile uint32_t {};", pad, result).unwrap();
+                writeln!(out, "{}br_handler {};", pad, handler).unwrap();
+                writeln!(out, "{}{}.prev = br_top;", pad, handler).unwrap();
+                writeln!(out, "{}br_top = &{};", pad, handler).unwrap();
+                writeln!(out, "{}if (setjmp({}.buf) == 0) {{", pad, handler).unwrap();
+                let val = self.emit(try_node, out, depth + 1);
+                writeln!(out, "{}    {} = {};", pad, result, val).unwrap();
+                writeln!(out, "{}    br_top = {}.prev;", pad, handler).unwrap();
+                writeln!(out, "{}}} else {{", pad).unwrap();
+                writeln!(out, "{}    br_caught();", pad).unwrap();
+                if let Some(slot) = binding {
+                    writeln!(out, "{}    v[{}] = br_code;", pad, slot).unwrap();
+                    writeln!(out, "{}    d[{}] = 1;", pad, slot).unwrap();
+                }
+                let val = self.emit(catch_node, out, depth + 1);
+                writeln!(out, "{}    {} = {};", pad, result, val).unwrap();
+                writeln!(out, "{}}}", pad).unwrap();
+                let copy = self.temp();
+                writeln!(out, "{}uint32_t {} = {};", pad, copy, result).unwrap();
+                copy
+            }
+
+            Node::Throw(code) => {
+                let val = self.emit(code, out, depth);
+                writeln!(out, "{}br_throw({});", pad, val).unwrap();
+                "0u".to_string()
+            }
+
+            Node::Assert(arg, error) => {
+                let val = self.emit(arg, out, depth);
+                writeln!(out, "{}if (!{}) br_fail(7, \"%s\", \"{}\");", pad, val, escape(&error.message)).unwrap();
+                val
+            }
+
+            Node::PrintNum(arg) => {
+                let val = self.emit(arg, out, depth);
+                writeln!(out, "{}br_print_num({});", pad, val).unwrap();
+                val
+            }
+
+            Node::PrintChar(arg) => {
+                let val = self.emit(arg, out, depth);
+                writeln!(out, "{}br_print_char({});", pad, val).unwrap();
+                val
+            }
+
+            Node::Arith(op, x, y) => {
+                let (x, y) = (self.emit(x, out, depth), self.emit(y, out, depth));
+                let result = self.temp();
+                writeln!(out, "{}uint32_t {} = br_{}({}, {});", pad, result, op, x, y).unwrap();
+                result
+            }
+
+            Node::Invalid(message) => {
+                writeln!(out, "{}br_fail(5, \"%s\", \"{}\");", pad, escape(message)).unwrap();
+                "0u".to_string()
+            }
+
+            Node::At(_, _, node) => self.emit(node, out, depth),
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use crate::resolve::{Function, Node};
+use crate::Options;
+use super::escape;
+use std::fmt::Write;
+use wasm_bindgen::prelude::*;
+
+// Translates a program into JavaScript. The result is a function expression
+// taking an `output` and a `diagnostic` callback (both receive strings) and
+// returning the value of the last statement:
+//
+//   const run = eval(compile_js(code, options));
+//   run(text => ..., text => ...);
+//
+// Every Bracket expression becomes a JS expression: conditionals are ternaries,
+// groups use the comma operator and try-catch is an arrow function around a
+// try/catch statement. Errors are thrown as BracketError with the same codes
+// and messages as the interpreter; anything else (like running out of stack)
+// is not caught by Bracket's try-catch.
+
+const RUNTIME: &str = r#"    "use strict";
+    class BracketError extends Error {
+        constructor(code, message) {
+            super(message);
+            this.code = code;
+        }
+    }
+    const fail = (code, message) => { throw new BracketError(code, message); };
+    const caught = (err) => {
+        if (!(err instanceof BracketError)) throw err;
+        diagnostic("Warning!: Error caught in try block: " + err.message + "\n");
+        return err.code;
+    };
+    const MAX = 0xFFFFFFFF;
+    const inc = (x) => x === MAX ? x : x + 1;
+    const dec = (x) => x === 0 ? fail(1, "Cannot decrement zero") : x - 1;
+    const add = (x, y) => Math.min(x + y, MAX);
+    const sub = (x, y) => x < y ? fail(1, "Cannot decrement zero") : x - y;
+    const mul = (x, y) => Math.min(x * y, MAX);
+    // codes 1 to 15 are reserved for built-in errors (eval::RESERVED_CODES)
+    const raise = (x) => x >= 1 && x <= 15
+        ? fail(5, "Cannot throw " + x + ", codes 1 to 15 are reserved for built-in errors")
+        : fail(x, "Uncaught error with code " + x);
+    const assert = (x, message) => x === 0 ? fail(7, message) : x;
+    const printNum = (x) => (output(x + "\n"), x);
+    const printChar = (x) => (output(x > 0x10FFFF || (x >= 0xD800 && x <= 0xDFFF) ? "?" : String.fromCodePoint(x)), x);
+    const define = (v, slot, x) => (v[slot] === undefined && (v[slot] = x), x);
+    const read = (v, slot, name) => v[slot] === undefined ? fail(2, "Variable " + name + " not defined") : v[slot];
+    const fns = [];
+    const callee = (index, name, argc) => {
+        const f = fns[index];
+        if (f === undefined) fail(3, "Function " + name + " not defined");
+        if (f.length !== argc) fail(4, "Function " + name + " expects " + f.length + " arguments, got " + argc);
+        return f;
+    };
+"#;
+
+pub fn compile(code: &str, options: &Options) -> Result<String, String> {
+    let (statements, _) = super::resolve_program(code, options)?;
+    let mut generator = Generator::default();
+    let body: Vec<String> = statements.iter().map(|node| generator.emit(node)).collect();
+
+    let mut out = String::new();
+    out.push_str("// Generated from a Bracket program\n");
+    out.push_str("(function (output, diagnostic) {\n");
+    out.push_str(RUNTIME);
+    for function in &generator.functions {
+        out.push('\n');
+        out.push_str(function);
+    }
+    out.push_str("\n    const v = [];\n");
+    out.push_str("    let last = 0;\n");
+    for statement in body {
+        writeln!(out, "    last = {};", statement).unwrap();
+    }
+    out.push_str("    return last;\n");
+    out.push_str("})\n");
+    Ok(out)
+}
+
+#[wasm_bindgen]
+pub fn compile_js(code: String, options: &Options) -> String {
+    compile(&code, options).unwrap_or_else(|err| format!("Error: {}", err))
+}
+
+#[derive(Default)]
+struct Generator {
+    functions: Vec<String>,
+}
+
+impl Generator {
+    // Emits a JS function for a definition, returns its number.
+    fn function(&mut self, function: &Function) -> usize {
+        let k = self.functions.len();
+        self.functions.push(String::new());
+        let params: Vec<String> = (0..function.args.len()).map(|i| format!("a{}", i)).collect();
+        let mut out = String::new();
+        writeln!(out, "    // {}", function.name).unwrap();
+        writeln!(out, "    function fn_{}({}) {{", k, params.join(", ")).unwrap();
+        writeln!(out, "        const v = [];").unwrap();
+        for (slot, param) in function.args.iter().zip(&params) {
+            writeln!(out, "        v[{}] = {};", slot, param).unwrap();
+        }
+        let body = self.emit(&function.body);
+        writeln!(out, "        return {};", body).unwrap();
+        writeln!(out, "    }}").unwrap();
+        self.functions[k] = out;
+        k
+    }
+
+    fn emit(&mut self, node: &Node) -> String {
+        match node {
+            Node::Val(num) => num.to_string(),
+            Node::If(cond, then_node, else_node) => {
+                format!("({} ? {} : {})", self.emit(cond), self.emit(then_node), self.emit(else_node))
+            }
+            Node::DefineVar(slot, body) => format!("define(v, {}, {})", slot, self.emit(body)),
+            Node::DefineFunction(index, function) => format!("(fns[{}] = fn_{}, 0)", index, self.function(function)),
+            Node::Read(slot, var) => format!("read(v, {}, \"{}\")", slot, escape(&var.0)),
+            Node::Inc(arg) => format!("inc({})", self.emit(arg)),
+            Node::Dec(arg) => format!("dec({})", self.emit(arg)),
+            // the callee is looked up and checked before the arguments are evaluated
+            Node::Call(index, var, params) => {
+                let args: Vec<String> = params.iter().map(|param| self.emit(param)).collect();
+                format!("callee({}, \"{}\", {})({})", index, escape(&var.0), params.len(), args.join(", "))
+            }
+            Node::List(items) => match items.as_slice() {
+                [] => "0".to_string(),
+                [item] => self.emit(item),
+                items => format!("({})", items.iter().map(|item| self.emit(item)).collect::<Vec<_>>().join(", ")),
+            },
+            Node::TryCatch(try_node, binding, catch_node) => {
+                let bind = match binding {
+                    Some(slot) => format!("v[{}] = code; ", slot),
+                    None => String::new(),
+                };
+                format!(
+                    "(() => {{ try {{ return {}; }} catch (err) {{ const code = caught(err); {}return {}; }} }})()",
+                    self.emit(try_node),
+                    bind,
+                    self.emit(catch_node)
+                )
+            }
+            Node::Throw(code) => format!("raise({})", self.emit(code)),
+            Node::Assert(arg, error) => format!("assert({}, \"{}\")", self.emit(arg), escape(&error.message)),
+            Node::PrintNum(arg) => format!("printNum({})", self.emit(arg)),
+            Node::PrintChar(arg) => format!("printChar({})", self.emit(arg)),
+            Node::Arith(op, x, y) => format!("{}({}, {})", op, self.emit(x), self.emit(y)),
+            Node::Invalid(message) => format!("fail(5, \"{}\")", escape(message)),
+            Node::At(_, _, node) => self.emit(node),
+        }
+    }
+}
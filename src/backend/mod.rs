@@ -0,0 +1,37 @@
+// Translators from a parsed Bracket program to other languages. They run the
+// same passes as the interpreter (see crate::prepare) and keep its semantics:
+// u32 numbers, saturating `&`, failing `*` on zero, catchable errors.
+
+use crate::resolve::{Node, Resolver};
+use crate::Options;
+
+pub mod c;
+pub mod js;
+pub mod rust;
+pub mod wasm;
+
+// Parses and resolves a whole program. Unlike running it, a parse error
+// anywhere means there is nothing to translate.
+pub fn resolve_program(code: &str, options: &Options) -> Result<(Vec<Node>, Resolver), String> {
+    let (statements, parse_error) = crate::prepare(code, options)?;
+    if let Some(err) = parse_error {
+        return Err(err);
+    }
+    let mut resolver = Resolver::new();
+    let nodes = statements.iter().map(|exp| resolver.resolve_statement(exp)).collect();
+    Ok((nodes, resolver))
+}
+
+// Escapes text for a double quoted string literal in C-like languages.
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,239 @@
+use crate::resolve::{Function, Node};
+use crate::Options;
+use super::escape;
+use std::fmt::Write;
+
+// Translates a program into Rust source. The result can be built on its own
+// (`rustc -O program.rs`) or included as a module, whose `run` function takes
+// an output and a diagnostic callback and returns Result<Num, Error> like
+// eval::eval_expression does: errors carry the interpreter's codes and
+// messages, propagate with `?` and are caught by try-catch, which calls
+// `diagnostic` with the usual warning.
+//
+// Numbers are the unsigned type `Num` (u32 unless another one is chosen), `&`
+// and arithmetic saturate at Num::MAX like they do at u32::MAX in the
+// interpreter.
+
+pub const INT_TYPES: [&str; 3] = ["u32", "u64", "u128"];
+
+const RUNTIME: &str = r#"
+use std::convert::TryFrom;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Error {
+    pub code: Num,
+    pub message: String,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+fn fail(code: Num, message: String) -> Result<Num, Error> {
+    Err(Error { code, message })
+}
+
+type Func = fn(&mut Program, &[Num]) -> Result<Num, Error>;
+
+pub struct Program<'a> {
+    functions: Vec<Option<(Func, usize)>>,
+    output: &'a mut dyn FnMut(&str),
+    diagnostic: &'a mut dyn FnMut(&str),
+}
+
+impl Program<'_> {
+    fn callee(&self, index: usize, name: &str, argc: usize) -> Result<Func, Error> {
+        match self.functions[index] {
+            None => Err(Error { code: 3, message: format!("Function {} not defined", name) }),
+            Some((_, arity)) if arity != argc => {
+                Err(Error { code: 4, message: format!("Function {} expects {} arguments, got {}", name, arity, argc) })
+            }
+            Some((f, _)) => Ok(f),
+        }
+    }
+
+    fn caught(&mut self, err: &Error) {
+        (self.diagnostic)(&format!("Warning!: Error caught in try block: {}\n", err));
+    }
+
+    fn print_num(&mut self, x: Num) -> Num {
+        (self.output)(&format!("{}\n", x));
+        x
+    }
+
+    fn print_char(&mut self, x: Num) -> Num {
+        let c = u32::try_from(x).ok().and_then(char::from_u32).unwrap_or('?');
+        (self.output)(&c.to_string());
+        x
+    }
+}
+
+fn read(v: &[Option<Num>], slot: usize, name: &str) -> Result<Num, Error> {
+    v[slot].ok_or_else(|| Error { code: 2, message: format!("Variable {} not defined", name) })
+}
+
+fn define(v: &mut [Option<Num>], slot: usize, x: Num) -> Num {
+    v[slot].get_or_insert(x);
+    x
+}
+
+fn dec(x: Num) -> Result<Num, Error> {
+    sub(x, 1)
+}
+
+fn sub(x: Num, y: Num) -> Result<Num, Error> {
+    x.checked_sub(y).map_or_else(|| fail(1, "Cannot decrement zero".to_string()), Ok)
+}
+
+// codes 1 to 15 are reserved for built-in errors (eval::RESERVED_CODES)
+fn throw(code: Num) -> Result<Num, Error> {
+    if (1..=15).contains(&code) {
+        return fail(5, format!("Cannot throw {}, codes 1 to 15 are reserved for built-in errors", code));
+    }
+    fail(code, format!("Uncaught error with code {}", code))
+}
+
+fn assert(x: Num, message: &str) -> Result<Num, Error> {
+    if x == 0 { fail(7, message.to_string()) } else { Ok(x) }
+}
+"#;
+
+const MAIN: &str = r#"
+#[allow(dead_code)]
+fn main() {
+    use std::io::Write;
+    // deep recursion in Bracket is deep recursion here
+    let thread = std::thread::Builder::new().stack_size(1 << 30).spawn(|| {
+        let mut output = |s: &str| {
+            print!("{}", s);
+            std::io::stdout().flush().unwrap();
+        };
+        run(&mut output, &mut |s| eprint!("{}", s))
+    });
+    match thread.unwrap().join().unwrap() {
+        Ok(value) => println!("{}", value),
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+"#;
+
+pub fn compile(code: &str, options: &Options, int_type: &str) -> Result<String, String> {
+    if !INT_TYPES.contains(&int_type) {
+        return Err(format!("Unknown integer type {}, expected one of {}", int_type, INT_TYPES.join(", ")));
+    }
+    // simplify folds constants as u32, saturating where a wider Num would not
+    let mut options = *options;
+    if int_type != "u32" {
+        options.simplify = false;
+    }
+    let (statements, resolver) = super::resolve_program(code, &options)?;
+    let mut generator = Generator::default();
+    let body: Vec<String> = statements.iter().map(|node| generator.emit(node)).collect();
+
+    let mut out = String::new();
+    out.push_str("// Generated from a Bracket program\n");
+    out.push_str("#![allow(unused, clippy::all)]\n\n");
+    writeln!(out, "pub type Num = {};", int_type).unwrap();
+    out.push_str(RUNTIME);
+    for function in &generator.functions {
+        out.push('\n');
+        out.push_str(function);
+    }
+    out.push_str("\npub fn run(output: &mut dyn FnMut(&str), diagnostic: &mut dyn FnMut(&str)) -> Result<Num, Error> {\n");
+    writeln!(out, "    let p = &mut Program {{ functions: vec![None; {}], output, diagnostic }};", resolver.function_count()).unwrap();
+    writeln!(out, "    let mut v: Vec<Option<Num>> = vec![None; {}];", resolver.globals.len()).unwrap();
+    out.push_str("    let mut last = 0;\n");
+    for statement in body {
+        writeln!(out, "    last = {};", statement).unwrap();
+    }
+    out.push_str("    Ok(last)\n}\n");
+    out.push_str(MAIN);
+    Ok(out)
+}
+
+#[derive(Default)]
+struct Generator {
+    functions: Vec<String>,
+}
+
+impl Generator {
+    // Emits a Rust function for a definition, returns its number.
+    fn function(&mut self, function: &Function) -> usize {
+        let k = self.functions.len();
+        self.functions.push(String::new());
+        let mut out = String::new();
+        writeln!(out, "// {}", function.name).unwrap();
+        writeln!(out, "fn fn_{}(p: &mut Program, args: &[Num]) -> Result<Num, Error> {{", k).unwrap();
+        writeln!(out, "    let mut v: Vec<Option<Num>> = vec![None; {}];", function.frame_size).unwrap();
+        for (i, slot) in function.args.iter().enumerate() {
+            writeln!(out, "    v[{}] = Some(args[{}]);", slot, i).unwrap();
+        }
+        let body = self.emit(&function.body);
+        writeln!(out, "    Ok({})", body).unwrap();
+        writeln!(out, "}}").unwrap();
+        self.functions[k] = out;
+        k
+    }
+
+    fn emit(&mut self, node: &Node) -> String {
+        match node {
+            Node::Val(num) => format!("({} as Num)", num),
+            Node::If(cond, then_node, else_node) => {
+                format!("(if ({}) != 0 {{ {} }} else {{ {} }})", self.emit(cond), self.emit(then_node), self.emit(else_node))
+            }
+            Node::DefineVar(slot, body) => format!("{{ let x = {}; define(&mut v, {}, x) }}", self.emit(body), slot),
+            Node::DefineFunction(index, function) => {
+                let k = self.function(function);
+                format!("{{ p.functions[{}] = Some((fn_{} as Func, {})); 0 }}", index, k, function.args.len())
+            }
+            Node::Read(slot, var) => format!("read(&v, {}, \"{}\")?", slot, escape(&var.0)),
+            Node::Inc(arg) => format!("({}).saturating_add(1)", self.emit(arg)),
+            Node::Dec(arg) => format!("dec({})?", self.emit(arg)),
+            // the callee is looked up and checked before the arguments are evaluated
+            Node::Call(index, var, params) => {
+                let args: Vec<String> = params.iter().map(|param| self.emit(param)).collect();
+                format!("{{ let f = p.callee({}, \"{}\", {})?; let args = [{}]; f(p, &args)? }}", index, escape(&var.0), params.len(), args.join(", "))
+            }
+            Node::List(items) => match items.as_slice() {
+                [] => "(0 as Num)".to_string(),
+                [item] => self.emit(item),
+                [init @ .., last] => {
+                    let mut out = "{ ".to_string();
+                    for item in init {
+                        write!(out, "let _ = {}; ", self.emit(item)).unwrap();
+                    }
+                    write!(out, "{} }}", self.emit(last)).unwrap();
+                    out
+                }
+            },
+            Node::TryCatch(try_node, binding, catch_node) => {
+                let bind = match binding {
+                    Some(slot) => format!("v[{}] = Some(err.code); ", slot),
+                    None => String::new(),
+                };
+                format!(
+                    "(match (|| -> Result<Num, Error> {{ Ok({}) }})() {{ Ok(x) => x, Err(err) => {{ p.caught(&err); {}{} }} }})",
+                    self.emit(try_node),
+                    bind,
+                    self.emit(catch_node)
+                )
+            }
+            Node::Throw(code) => format!("throw({})?", self.emit(code)),
+            Node::Assert(arg, error) => format!("assert({}, \"{}\")?", self.emit(arg), escape(&error.message)),
+            Node::PrintNum(arg) => format!("{{ let x = {}; p.print_num(x) }}", self.emit(arg)),
+            Node::PrintChar(arg) => format!("{{ let x = {}; p.print_char(x) }}", self.emit(arg)),
+            Node::Arith(op, x, y) => match op {
+                crate::parse::Arith::Add => format!("({}).saturating_add({})", self.emit(x), self.emit(y)),
+                crate::parse::Arith::Sub => format!("sub({}, {})?", self.emit(x), self.emit(y)),
+                crate::parse::Arith::Mul => format!("({}).saturating_mul({})", self.emit(x), self.emit(y)),
+            },
+            Node::Invalid(message) => format!("fail(5, \"{}\".to_string())?", escape(message)),
+            Node::At(_, _, node) => self.emit(node),
+        }
+    }
+}
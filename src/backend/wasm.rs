@@ -0,0 +1,635 @@
+use crate::eval::RESERVED_CODES;
+use crate::resolve::{Function, Node};
+use crate::Options;
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+
+// Translates a program into a WebAssembly module, encoded by hand.
+//
+// Every definition becomes a wasm function taking its arguments as i32 and
+// returning an i64: the low 32 bits are the value, anything in the high bits
+// is an error status. Errors store their details in globals (kind, code,
+// string pointer and length, two numbers) and branch to the innermost
+// handler: the catch block of a try-catch or the end of the function, which
+// returns the status to the caller. Variables are locals with a "defined"
+// flag each, the function table lives at the start of the memory as
+// (table element + 1, arity) pairs and calls go through call_indirect.
+//
+// The module imports from "env":
+//   print_num(x), print_char(x)
+//   report(caught, kind, code, ptr, len, a, b)  an error was caught (caught = 1)
+//                                               or ends the program (caught = 0)
+// and exports "memory" and "main", which returns the value of the last
+// statement as an i64, or -1 after an uncaught error. Error kinds are the
+// interpreter's error codes (1-5, 7), 0 for thrown errors and RESERVED_THROW
+// for a throw of a reserved code; site/bracket_wasm.js turns them into the
+// interpreter's messages.
+
+const I32: u8 = 0x7F;
+const I64: u8 = 0x7E;
+const EMPTY: u8 = 0x40;
+
+const BLOCK: u8 = 0x02;
+const IF: u8 = 0x04;
+const ELSE: u8 = 0x05;
+const END: u8 = 0x0B;
+const BR: u8 = 0x0C;
+const BR_IF: u8 = 0x0D;
+const RETURN: u8 = 0x0F;
+const CALL: u8 = 0x10;
+const CALL_INDIRECT: u8 = 0x11;
+const DROP: u8 = 0x1A;
+const SELECT: u8 = 0x1B;
+const LOCAL_GET: u8 = 0x20;
+const LOCAL_SET: u8 = 0x21;
+const LOCAL_TEE: u8 = 0x22;
+const GLOBAL_GET: u8 = 0x23;
+const GLOBAL_SET: u8 = 0x24;
+const I32_LOAD: u8 = 0x28;
+const I32_STORE: u8 = 0x36;
+const I32_CONST: u8 = 0x41;
+const I64_CONST: u8 = 0x42;
+const I32_EQZ: u8 = 0x45;
+const I32_NE: u8 = 0x47;
+const I32_LT_U: u8 = 0x49;
+const I64_EQZ: u8 = 0x50;
+const I64_GT_U: u8 = 0x56;
+const I32_ADD: u8 = 0x6A;
+const I32_SUB: u8 = 0x6B;
+const I64_ADD: u8 = 0x7C;
+const I64_MUL: u8 = 0x7E;
+const I64_SHR_U: u8 = 0x88;
+const I32_WRAP_I64: u8 = 0xA7;
+const I64_EXTEND_I32_U: u8 = 0xAD;
+
+// Function indices: the imports, then the helpers, then main, then one per definition.
+const PRINT_NUM: u32 = 0;
+const PRINT_CHAR: u32 = 1;
+const REPORT: u32 = 2;
+const ADD: u32 = 3;
+const MUL: u32 = 4;
+const RAISE: u32 = 5;
+const MAIN: u32 = 6;
+const FIRST_DEFINITION: u32 = 7;
+
+// Error kind of <|x> with x in 1..=eval::RESERVED_CODES, raised with code 5.
+const RESERVED_THROW: u32 = 16;
+
+// Globals set by RAISE, in the order of its parameters.
+const ERROR_GLOBALS: u32 = 6;
+const CODE: u32 = 1;
+
+pub fn compile(code: &str, options: &Options) -> Result<Vec<u8>, String> {
+    let (statements, resolver) = super::resolve_program(code, options)?;
+    let mut generator = Generator {
+        types: Vec::new(),
+        definitions: Vec::new(),
+        strings: Vec::new(),
+        interned: HashMap::new(),
+        string_base: 8 * resolver.function_count() as u32,
+    };
+
+    let mut main = Body::new(0, resolver.globals.len() as u32);
+    let last = main.temp();
+    main.begin(BLOCK, EMPTY, true);
+    for node in &statements {
+        generator.emit(&mut main, node);
+        main.op_u(LOCAL_SET, last);
+    }
+    main.op_u(LOCAL_GET, last);
+    main.op(I64_EXTEND_I32_U);
+    main.op(RETURN);
+    main.end();
+    main.const_i32(0);
+    for global in 0..ERROR_GLOBALS {
+        main.op_u(GLOBAL_GET, global);
+    }
+    main.op_u(CALL, REPORT);
+    main.const_i64(-1);
+    main.op(END);
+
+    Ok(generator.module(main))
+}
+
+#[wasm_bindgen]
+pub fn compile_wasm(code: String, options: &Options) -> Result<Vec<u8>, String> {
+    compile(&code, options)
+}
+
+// Code of one wasm function. Locals are the parameters, an i64 scratch, the
+// values of the frame's slots, their "defined" flags, then temporaries.
+struct Body {
+    code: Vec<u8>,
+    params: u32,
+    size: u32,
+    temps: u32,
+    labels: Vec<bool>, // enclosing blocks, true for those errors branch to
+}
+
+impl Body {
+    fn new(params: u32, size: u32) -> Body {
+        Body { code: Vec::new(), params, size, temps: 0, labels: Vec::new() }
+    }
+
+    fn scratch(&self) -> u32 {
+        self.params
+    }
+
+    fn value(&self, slot: usize) -> u32 {
+        self.params + 1 + slot as u32
+    }
+
+    fn defined(&self, slot: usize) -> u32 {
+        self.params + 1 + self.size + slot as u32
+    }
+
+    fn temp(&mut self) -> u32 {
+        self.temps += 1;
+        self.params + 2 * self.size + self.temps
+    }
+
+    fn op(&mut self, op: u8) {
+        self.code.push(op);
+    }
+
+    fn op_u(&mut self, op: u8, x: u32) {
+        self.code.push(op);
+        uleb(&mut self.code, x as u64);
+    }
+
+    fn const_i32(&mut self, x: u32) {
+        self.code.push(I32_CONST);
+        sleb(&mut self.code, x as i32 as i64);
+    }
+
+    fn const_i64(&mut self, x: i64) {
+        self.code.push(I64_CONST);
+        sleb(&mut self.code, x);
+    }
+
+    fn memory(&mut self, op: u8) {
+        self.code.extend([op, 2, 0]); // 4 byte alignment, no offset
+    }
+
+    fn begin(&mut self, op: u8, block_type: u8, handler: bool) {
+        self.code.extend([op, block_type]);
+        self.labels.push(handler);
+    }
+
+    fn end(&mut self) {
+        self.labels.pop();
+        self.code.push(END);
+    }
+
+    // Relative depth of the innermost handler.
+    fn handler(&self) -> u32 {
+        let index = self.labels.iter().rposition(|handler| *handler).unwrap();
+        (self.labels.len() - 1 - index) as u32
+    }
+
+    fn locals(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        uleb(&mut out, 2);
+        uleb(&mut out, 1);
+        out.push(I64);
+        uleb(&mut out, (2 * self.size + self.temps) as u64);
+        out.push(I32);
+        out
+    }
+}
+
+struct Generator {
+    types: Vec<(Vec<u8>, Vec<u8>)>,
+    definitions: Vec<(u32, Body)>,
+    strings: Vec<u8>,
+    interned: HashMap<String, (u32, u32)>,
+    string_base: u32,
+}
+
+impl Generator {
+    fn type_index(&mut self, params: Vec<u8>, results: Vec<u8>) -> u32 {
+        let signature = (params, results);
+        match self.types.iter().position(|t| *t == signature) {
+            Some(index) => index as u32,
+            None => {
+                self.types.push(signature);
+                self.types.len() as u32 - 1
+            }
+        }
+    }
+
+    // Type of a definition taking `argc` arguments.
+    fn definition_type(&mut self, argc: usize) -> u32 {
+        self.type_index(vec![I32; argc], vec![I64])
+    }
+
+    // Address and length of `s` in the memory.
+    fn string(&mut self, s: &str) -> (u32, u32) {
+        if let Some(at) = self.interned.get(s) {
+            return *at;
+        }
+        let at = (self.string_base + self.strings.len() as u32, s.len() as u32);
+        self.strings.extend(s.as_bytes());
+        self.interned.insert(s.to_string(), at);
+        at
+    }
+
+    // Emits a wasm function for a definition, returns its table element.
+    fn function(&mut self, function: &Function) -> u32 {
+        let k = self.definitions.len();
+        let type_index = self.definition_type(function.args.len());
+        self.definitions.push((type_index, Body::new(0, 0)));
+        let mut body = Body::new(function.args.len() as u32, function.frame_size as u32);
+        body.begin(BLOCK, EMPTY, true);
+        for (i, slot) in function.args.iter().enumerate() {
+            body.op_u(LOCAL_GET, i as u32);
+            body.op_u(LOCAL_SET, body.value(*slot));
+            body.const_i32(1);
+            body.op_u(LOCAL_SET, body.defined(*slot));
+        }
+        self.emit(&mut body, &function.body);
+        body.op(I64_EXTEND_I32_U);
+        body.op(RETURN);
+        body.end();
+        body.const_i64(-1);
+        body.op(END);
+        self.definitions[k].1 = body;
+        k as u32
+    }
+
+    // Stores an error and branches to the innermost handler. The code is
+    // taken from the local `code` if given, otherwise it is the kind.
+    fn raise(&mut self, body: &mut Body, kind: u32, code: Option<u32>, text: &str, a: Option<u32>, b: u32) {
+        let (ptr, len) = self.string(text);
+        body.const_i32(kind);
+        match code {
+            Some(local) => body.op_u(LOCAL_GET, local),
+            None => body.const_i32(kind),
+        }
+        body.const_i32(ptr);
+        body.const_i32(len);
+        match a {
+            Some(local) => body.op_u(LOCAL_GET, local),
+            None => body.const_i32(0),
+        }
+        body.const_i32(b);
+        body.op_u(CALL, RAISE);
+        body.op_u(BR, body.handler());
+    }
+
+    // Emits code leaving the value of `node` on the stack as an i32.
+    fn emit(&mut self, body: &mut Body, node: &Node) {
+        match node {
+            Node::Val(num) => body.const_i32(*num),
+
+            Node::If(cond, then_node, else_node) => {
+                self.emit(body, cond);
+                body.begin(IF, I32, false);
+                self.emit(body, then_node);
+                body.op(ELSE);
+                self.emit(body, else_node);
+                body.end();
+            }
+
+            Node::DefineVar(slot, value) => {
+                self.emit(body, value);
+                let t = body.temp();
+                body.op_u(LOCAL_SET, t);
+                body.op_u(LOCAL_GET, body.defined(*slot));
+                body.op(I32_EQZ);
+                body.begin(IF, EMPTY, false);
+                body.op_u(LOCAL_GET, t);
+                body.op_u(LOCAL_SET, body.value(*slot));
+                body.const_i32(1);
+                body.op_u(LOCAL_SET, body.defined(*slot));
+                body.end();
+                body.op_u(LOCAL_GET, t);
+            }
+
+            Node::DefineFunction(index, function) => {
+                let element = self.function(function);
+                let address = 8 * *index as u32;
+                body.const_i32(address);
+                body.const_i32(element + 1);
+                body.memory(I32_STORE);
+                body.const_i32(address + 4);
+                body.const_i32(function.args.len() as u32);
+                body.memory(I32_STORE);
+                body.const_i32(0);
+            }
+
+            Node::Read(slot, var) => {
+                body.op_u(LOCAL_GET, body.defined(*slot));
+                body.op(I32_EQZ);
+                body.begin(IF, EMPTY, false);
+                self.raise(body, 2, None, &var.0, None, 0);
+                body.end();
+                body.op_u(LOCAL_GET, body.value(*slot));
+            }
+
+            Node::Inc(arg) => {
+                self.emit(body, arg);
+                let t = body.temp();
+                body.op_u(LOCAL_TEE, t);
+                body.op_u(LOCAL_GET, t);
+                body.const_i32(u32::MAX);
+                body.op(I32_NE);
+                body.op(I32_ADD);
+            }
+
+            Node::Dec(arg) => {
+                self.emit(body, arg);
+                let t = body.temp();
+                body.op_u(LOCAL_TEE, t);
+                body.op(I32_EQZ);
+                body.begin(IF, EMPTY, false);
+                self.raise(body, 1, None, "", None, 0);
+                body.end();
+                body.op_u(LOCAL_GET, t);
+                body.const_i32(1);
+                body.op(I32_SUB);
+            }
+
+            // the callee is looked up and checked before the arguments are evaluated
+            Node::Call(index, var, params) => {
+                let address = 8 * *index as u32;
+                let element = body.temp();
+                let arity = body.temp();
+                body.const_i32(address);
+                body.memory(I32_LOAD);
+                body.op_u(LOCAL_TEE, element);
+                body.op(I32_EQZ);
+                body.begin(IF, EMPTY, false);
+                self.raise(body, 3, None, &var.0, None, 0);
+                body.end();
+                body.const_i32(address + 4);
+                body.memory(I32_LOAD);
+                body.op_u(LOCAL_TEE, arity);
+                body.const_i32(params.len() as u32);
+                body.op(I32_NE);
+                body.begin(IF, EMPTY, false);
+                self.raise(body, 4, None, &var.0, Some(arity), params.len() as u32);
+                body.end();
+                for param in params {
+                    self.emit(body, param);
+                }
+                body.op_u(LOCAL_GET, element);
+                body.const_i32(1);
+                body.op(I32_SUB);
+                let type_index = self.definition_type(params.len());
+                body.op_u(CALL_INDIRECT, type_index);
+                body.op(0); // table 0
+                body.op_u(LOCAL_TEE, body.scratch());
+                body.const_i64(32);
+                body.op(I64_SHR_U);
+                body.op(I64_EQZ);
+                body.op(I32_EQZ);
+                body.op_u(BR_IF, body.handler());
+                body.op_u(LOCAL_GET, body.scratch());
+                body.op(I32_WRAP_I64);
+            }
+
+            Node::List(items) => {
+                if items.is_empty() {
+                    body.const_i32(0);
+                }
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        body.op(DROP);
+                    }
+                    self.emit(body, item);
+                }
+            }
+
+            Node::TryCatch(try_node, binding, catch_node) => {
+                body.begin(BLOCK, I32, false);
+                body.begin(BLOCK, EMPTY, true);
+                self.emit(body, try_node);
+                body.op_u(BR, 1);
+                body.end();
+                body.const_i32(1);
+                for global in 0..ERROR_GLOBALS {
+                    body.op_u(GLOBAL_GET, global);
+                }
+                body.op_u(CALL, REPORT);
+                if let Some(slot) = binding {
+                    body.op_u(GLOBAL_GET, CODE);
+                    body.op_u(LOCAL_SET, body.value(*slot));
+                    body.const_i32(1);
+                    body.op_u(LOCAL_SET, body.defined(*slot));
+                }
+                self.emit(body, catch_node);
+                body.end();
+            }
+
+            Node::Throw(code) => {
+                self.emit(body, code);
+                let t = body.temp();
+                body.op_u(LOCAL_SET, t);
+                // 1 <= t <= RESERVED_CODES, as t - 1 < RESERVED_CODES unsigned
+                body.op_u(LOCAL_GET, t);
+                body.const_i32(1);
+                body.op(I32_SUB);
+                body.const_i32(RESERVED_CODES);
+                body.op(I32_LT_U);
+                body.begin(IF, EMPTY, false);
+                let code = body.temp();
+                body.const_i32(5);
+                body.op_u(LOCAL_SET, code);
+                self.raise(body, RESERVED_THROW, Some(code), "", Some(t), RESERVED_CODES);
+                body.end();
+                self.raise(body, 0, Some(t), "", None, 0);
+            }
+
+            Node::Assert(arg, error) => {
+                self.emit(body, arg);
+                let t = body.temp();
+                body.op_u(LOCAL_TEE, t);
+                body.op(I32_EQZ);
+                body.begin(IF, EMPTY, false);
+                self.raise(body, 7, None, &error.message, None, 0);
+                body.end();
+                body.op_u(LOCAL_GET, t);
+            }
+
+            Node::PrintNum(arg) | Node::PrintChar(arg) => {
+                self.emit(body, arg);
+                let t = body.temp();
+                body.op_u(LOCAL_TEE, t);
+                body.op_u(CALL, if let Node::PrintNum(_) = node { PRINT_NUM } else { PRINT_CHAR });
+                body.op_u(LOCAL_GET, t);
+            }
+
+            Node::Arith(op, x, y) => {
+                self.emit(body, x);
+                let tx = body.temp();
+                body.op_u(LOCAL_SET, tx);
+                self.emit(body, y);
+                let ty = body.temp();
+                body.op_u(LOCAL_SET, ty);
+                body.op_u(LOCAL_GET, tx);
+                body.op_u(LOCAL_GET, ty);
+                match op {
+                    crate::parse::Arith::Add => body.op_u(CALL, ADD),
+                    crate::parse::Arith::Mul => body.op_u(CALL, MUL),
+                    crate::parse::Arith::Sub => {
+                        body.op(I32_LT_U);
+                        body.begin(IF, EMPTY, false);
+                        self.raise(body, 1, None, "", None, 0);
+                        body.end();
+                        body.op_u(LOCAL_GET, tx);
+                        body.op_u(LOCAL_GET, ty);
+                        body.op(I32_SUB);
+                    }
+                }
+            }
+
+            Node::Invalid(message) => self.raise(body, 5, None, message, None, 0),
+
+            Node::At(_, _, node) => self.emit(body, node),
+        }
+    }
+
+    fn module(mut self, main: Body) -> Vec<u8> {
+        let print = self.type_index(vec![I32], vec![]);
+        let report = self.type_index(vec![I32; 7], vec![]);
+        let binary = self.type_index(vec![I32; 2], vec![I32]);
+        let raise = self.type_index(vec![I32; ERROR_GLOBALS as usize], vec![]);
+        let main_type = self.definition_type(0);
+
+        let mut out = b"\0asm".to_vec();
+        out.extend([1, 0, 0, 0]);
+
+        let mut types = Vec::new();
+        uleb(&mut types, self.types.len() as u64);
+        for (params, results) in &self.types {
+            types.push(0x60);
+            bytes(&mut types, params);
+            bytes(&mut types, results);
+        }
+        section(&mut out, 1, types);
+
+        let mut imports = Vec::new();
+        uleb(&mut imports, 3);
+        for (name, type_index) in [("print_num", print), ("print_char", print), ("report", report)] {
+            bytes(&mut imports, b"env");
+            bytes(&mut imports, name.as_bytes());
+            imports.push(0);
+            uleb(&mut imports, type_index as u64);
+        }
+        section(&mut out, 2, imports);
+
+        let mut functions = Vec::new();
+        uleb(&mut functions, 4 + self.definitions.len() as u64);
+        for type_index in [binary, binary, raise, main_type] {
+            uleb(&mut functions, type_index as u64);
+        }
+        for (type_index, _) in &self.definitions {
+            uleb(&mut functions, *type_index as u64);
+        }
+        section(&mut out, 3, functions);
+
+        let mut table = vec![1, 0x70, 0];
+        uleb(&mut table, self.definitions.len() as u64);
+        section(&mut out, 4, table);
+
+        let size = self.string_base as u64 + self.strings.len() as u64;
+        let mut memory = vec![1, 0];
+        uleb(&mut memory, size.div_ceil(65536).max(1));
+        section(&mut out, 5, memory);
+
+        let mut globals = Vec::new();
+        uleb(&mut globals, ERROR_GLOBALS as u64);
+        for _ in 0..ERROR_GLOBALS {
+            globals.extend([I32, 1, I32_CONST, 0, END]);
+        }
+        section(&mut out, 6, globals);
+
+        let mut exports = Vec::new();
+        uleb(&mut exports, 2);
+        bytes(&mut exports, b"main");
+        exports.push(0);
+        uleb(&mut exports, MAIN as u64);
+        bytes(&mut exports, b"memory");
+        exports.extend([2, 0]);
+        section(&mut out, 7, exports);
+
+        if !self.definitions.is_empty() {
+            let mut elements = vec![1, 0, I32_CONST, 0, END];
+            uleb(&mut elements, self.definitions.len() as u64);
+            for k in 0..self.definitions.len() {
+                uleb(&mut elements, FIRST_DEFINITION as u64 + k as u64);
+            }
+            section(&mut out, 9, elements);
+        }
+
+        let mut code = Vec::new();
+        uleb(&mut code, 4 + self.definitions.len() as u64);
+        for helper in [ADD, MUL] {
+            let op = if helper == ADD { I64_ADD } else { I64_MUL };
+            let mut body = vec![1, 1, I64];
+            body.extend([LOCAL_GET, 0, I64_EXTEND_I32_U, LOCAL_GET, 1, I64_EXTEND_I32_U, op, LOCAL_SET, 2]);
+            body.push(I64_CONST);
+            sleb(&mut body, u32::MAX as i64);
+            body.extend([LOCAL_GET, 2, LOCAL_GET, 2, I64_CONST]);
+            sleb(&mut body, u32::MAX as i64);
+            body.extend([I64_GT_U, SELECT, I32_WRAP_I64, END]);
+            bytes(&mut code, &body);
+        }
+        let mut body = vec![0];
+        for global in 0..ERROR_GLOBALS as u8 {
+            body.extend([LOCAL_GET, global, GLOBAL_SET, global]);
+        }
+        body.push(END);
+        bytes(&mut code, &body);
+        for function in std::iter::once(&main).chain(self.definitions.iter().map(|(_, body)| body)) {
+            let mut body = function.locals();
+            body.extend(&function.code);
+            bytes(&mut code, &body);
+        }
+        section(&mut out, 10, code);
+
+        let mut data = vec![1, 0, I32_CONST];
+        sleb(&mut data, self.string_base as i64);
+        data.push(END);
+        bytes(&mut data, &self.strings);
+        section(&mut out, 11, data);
+
+        out
+    }
+}
+
+fn uleb(out: &mut Vec<u8>, mut x: u64) {
+    loop {
+        let byte = (x & 0x7F) as u8;
+        x >>= 7;
+        if x == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn sleb(out: &mut Vec<u8>, mut x: i64) {
+    loop {
+        let byte = (x & 0x7F) as u8;
+        x >>= 7;
+        if (x == 0 && byte & 0x40 == 0) || (x == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+// A length prefixed byte string.
+fn bytes(out: &mut Vec<u8>, content: &[u8]) {
+    uleb(out, content.len() as u64);
+    out.extend(content);
+}
+
+fn section(out: &mut Vec<u8>, id: u8, content: Vec<u8>) {
+    out.push(id);
+    bytes(out, &content);
+}
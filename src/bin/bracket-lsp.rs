@@ -0,0 +1,35 @@
+use eso_lang::lsp;
+use std::{env, io, process};
+
+const USAGE: &str = "Usage: bracket-lsp [--compact-numbers]
+
+Language server for Bracket, speaking the Language Server Protocol on stdin and stdout.
+
+Options:
+    --compact-numbers                  accept numbers like [((((((())))))) ()] for 65";
+
+fn main() {
+    let mut compact = false;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--compact-numbers" => compact = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => {
+                eprintln!("Error: Unknown option {}\n{}", arg, USAGE);
+                process::exit(1);
+            }
+        }
+    }
+    match lsp::serve(io::stdin().lock(), io::stdout().lock(), compact) {
+        Ok(true) => {}
+        // exit without shutdown, or stdin closed
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    }
+}
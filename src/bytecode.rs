@@ -0,0 +1,220 @@
+use crate::eval::RuntimeError;
+use crate::parse::{Arith, Expression, Var};
+use std::{collections::HashMap, fmt};
+
+// Compiles parse::Expression into a flat instruction list for vm.rs.
+// Every expression leaves exactly one value on the value stack, the same value
+// eval::eval_expression would return for it.
+
+pub type NameId = u32;
+pub type FunctionId = u32;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Instruction {
+    Push(u32),
+    Pop,
+    Load(NameId),                     // push a variable of the current frame
+    Define(NameId),                   // set a variable (if unset) to the top of the stack
+    DefineFunction(NameId, FunctionId), // bind a name to a compiled function, push 0
+    Inc,
+    Dec,
+    Arith(Arith),                     // pops y and x, pushes x op y
+    Jump(usize),
+    JumpIfZero(usize),                // pops the condition
+    Prepare(NameId, u32),             // look up a function and check its arity before the arguments run
+    Call(NameId, u32),                // call the prepared function with that many arguments
+    Ret,
+    TryBegin(usize, Option<NameId>),  // catch block address and error code binding
+    TryEnd(usize),                    // try block succeeded, jump past the catch block
+    Throw,
+    Assert(u32),                      // raise the error stored in Program::assertions if the top is 0
+    PrintNum,
+    PrintChar,
+    Fail(u32),                        // raise the InvalidCall error stored in Program::messages
+    Halt,
+}
+
+pub struct CompiledFunction {
+    pub args: Vec<NameId>,
+    pub entry: usize,
+}
+
+#[derive(Default)]
+pub struct Program {
+    pub code: Vec<Instruction>,
+    pub names: Vec<Var>,
+    pub functions: Vec<CompiledFunction>,
+    pub messages: Vec<String>,
+    pub assertions: Vec<RuntimeError>,
+    name_ids: HashMap<Var, NameId>,
+}
+
+impl Program {
+    pub fn new() -> Program {
+        Program::default()
+    }
+
+    pub fn name(&self, id: NameId) -> &Var {
+        &self.names[id as usize]
+    }
+
+    pub fn intern(&mut self, var: &Var) -> NameId {
+        if let Some(id) = self.name_ids.get(var) {
+            return *id;
+        }
+        let id = self.names.len() as NameId;
+        self.names.push(var.clone());
+        self.name_ids.insert(var.clone(), id);
+        id
+    }
+
+    // Appends a top level statement followed by Halt, returns its entry address.
+    pub fn compile_statement(&mut self, exp: &Expression) -> usize {
+        let start = self.code.len();
+        self.compile(exp);
+        self.emit(Instruction::Halt);
+        start
+    }
+
+    fn emit(&mut self, instruction: Instruction) -> usize {
+        self.code.push(instruction);
+        self.code.len() - 1
+    }
+
+    fn patch(&mut self, at: usize) {
+        let target = self.code.len();
+        match &mut self.code[at] {
+            Instruction::Jump(addr) | Instruction::JumpIfZero(addr) | Instruction::TryEnd(addr) | Instruction::TryBegin(addr, _) => *addr = target,
+            _ => unreachable!(),
+        }
+    }
+
+    fn fail(&mut self, message: String) {
+        self.messages.push(message);
+        self.emit(Instruction::Fail(self.messages.len() as u32 - 1));
+    }
+
+    fn compile(&mut self, exp: &Expression) {
+        match exp {
+            Expression::Val(num) => {
+                self.emit(Instruction::Push(*num));
+            }
+
+            Expression::If(cond, then_exp, else_exp) => {
+                self.compile(cond);
+                let to_else = self.emit(Instruction::JumpIfZero(0));
+                self.compile(then_exp);
+                let to_end = self.emit(Instruction::Jump(0));
+                self.patch(to_else);
+                self.compile(else_exp);
+                self.patch(to_end);
+            }
+
+            Expression::FunctionDef(var, args, body) => {
+                let name = self.intern(var);
+                if args.is_empty() {
+                    self.compile(body);
+                    self.emit(Instruction::Define(name));
+                } else {
+                    let skip = self.emit(Instruction::Jump(0));
+                    let entry = self.code.len();
+                    self.compile(body);
+                    self.emit(Instruction::Ret);
+                    self.patch(skip);
+                    let args = args.iter().map(|arg| self.intern(arg)).collect();
+                    self.functions.push(CompiledFunction { args, entry });
+                    self.emit(Instruction::DefineFunction(name, self.functions.len() as FunctionId - 1));
+                }
+            }
+
+            Expression::FunctionCall(var, args) => {
+                let Expression::ListExp(params) = args.as_ref() else {
+                    self.fail(format!("Expected a list of arguments for function call, got {}", args));
+                    return;
+                };
+                if var.0 == "*" || var.0 == "&" {
+                    if params.len() != 1 {
+                        self.fail(format!("{} operator expects exactly one argument, got {}", var.0, params.len()));
+                        return;
+                    }
+                    self.compile(&params[0]);
+                    self.emit(if var.0 == "*" { Instruction::Dec } else { Instruction::Inc });
+                } else if params.is_empty() {
+                    let name = self.intern(var);
+                    self.emit(Instruction::Load(name));
+                } else {
+                    let name = self.intern(var);
+                    self.emit(Instruction::Prepare(name, params.len() as u32));
+                    for param in params {
+                        self.compile(param);
+                    }
+                    self.emit(Instruction::Call(name, params.len() as u32));
+                }
+            }
+
+            Expression::ListExp(list) => {
+                if list.is_empty() {
+                    self.emit(Instruction::Push(0));
+                }
+                for (i, item) in list.iter().enumerate() {
+                    if i > 0 {
+                        self.emit(Instruction::Pop);
+                    }
+                    self.compile(item);
+                }
+            }
+
+            Expression::TryCatch(try_block, binding, catch_block) => {
+                let binding = binding.as_ref().map(|var| self.intern(var));
+                let begin = self.emit(Instruction::TryBegin(0, binding));
+                self.compile(try_block);
+                let end = self.emit(Instruction::TryEnd(0));
+                self.patch(begin);
+                self.compile(catch_block);
+                self.patch(end);
+            }
+
+            Expression::Throw(exp) => {
+                self.compile(exp);
+                self.emit(Instruction::Throw);
+            }
+
+            Expression::Assert(exp, asserted) => {
+                self.compile(exp);
+                self.assertions.push(crate::eval::assertion(asserted));
+                self.emit(Instruction::Assert(self.assertions.len() as u32 - 1));
+            }
+
+            Expression::PrintNum(exp) => {
+                self.compile(exp);
+                self.emit(Instruction::PrintNum);
+            }
+
+            Expression::PrintChar(exp) => {
+                self.compile(exp);
+                self.emit(Instruction::PrintChar);
+            }
+
+            Expression::Arith(op, x, y) => {
+                self.compile(x);
+                self.compile(y);
+                self.emit(Instruction::Arith(*op));
+            }
+
+            Expression::At(_, exp) => self.compile(exp),
+        }
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (addr, instruction) in self.code.iter().enumerate() {
+            match instruction {
+                Instruction::Load(name) | Instruction::Define(name) => writeln!(f, "{:5} {:?} {}", addr, instruction, self.name(*name))?,
+                Instruction::Prepare(name, _) | Instruction::Call(name, _) | Instruction::DefineFunction(name, _) => writeln!(f, "{:5} {:?} {}", addr, instruction, self.name(*name))?,
+                _ => writeln!(f, "{:5} {:?}", addr, instruction)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,205 @@
+use crate::eval::{Interpreter, Options};
+use crate::parse::{self, Expression, Span, Var};
+use crate::trace::{parse_json, string};
+use std::{collections::HashMap, fmt::Write};
+use wasm_bindgen::prelude::*;
+
+// Records which expressions of a program ran and how often, which way every
+// conditional went and whether every try-catch finished its try block or ran
+// its catch block. The program is parsed with spans and, like for the tracer,
+// runs as written: simplify and optimize would remove the very branches that
+// are measured.
+//
+// Lines count as run as often as the most run expression starting on them,
+// lines where no expression starts (comments, closing brackets) are not code.
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Branch {
+    If,       // then, else
+    TryCatch, // try block finished, catch block ran
+}
+
+#[derive(Default)]
+pub struct Coverage {
+    pub hits: HashMap<Span, u64>,
+    pub branches: HashMap<Span, [u64; 2]>,
+    pub calls: HashMap<Var, u64>,
+    expressions: Vec<Span>,
+    branch_points: Vec<(Span, Branch)>,
+    functions: Vec<(Span, Var)>,
+}
+
+impl Coverage {
+    pub(crate) fn new(statements: &[Expression]) -> Coverage {
+        let mut coverage = Coverage::default();
+        for exp in statements {
+            coverage.collect(exp, None);
+        }
+        coverage
+    }
+
+    // Every expression with a span, `at` is the span of `exp` if it has one.
+    fn collect(&mut self, exp: &Expression, at: Option<Span>) {
+        match exp {
+            Expression::Val(_) => {}
+            Expression::If(cond, then_exp, else_exp) => {
+                if let Some(span) = at {
+                    self.branch_points.push((span, Branch::If));
+                }
+                self.collect(cond, None);
+                self.collect(then_exp, None);
+                self.collect(else_exp, None);
+            }
+            Expression::FunctionDef(var, args, body) => {
+                if let Some(span) = at && !args.is_empty() {
+                    self.functions.push((span, var.clone()));
+                }
+                self.collect(body, None);
+            }
+            Expression::FunctionCall(_, args) => self.collect(args, None),
+            Expression::ListExp(list) => list.iter().for_each(|item| self.collect(item, None)),
+            Expression::TryCatch(try_block, _, catch_block) => {
+                if let Some(span) = at {
+                    self.branch_points.push((span, Branch::TryCatch));
+                }
+                self.collect(try_block, None);
+                self.collect(catch_block, None);
+            }
+            Expression::Throw(exp) | Expression::Assert(exp, _) | Expression::PrintNum(exp) | Expression::PrintChar(exp) => self.collect(exp, None),
+            Expression::Arith(_, x, y) => {
+                self.collect(x, None);
+                self.collect(y, None);
+            }
+            Expression::At(span, exp) => {
+                self.expressions.push(*span);
+                self.collect(exp, Some(*span));
+            }
+        }
+    }
+
+    pub fn hit(&mut self, span: Span) {
+        *self.hits.entry(span).or_insert(0) += 1;
+    }
+
+    pub fn branch(&mut self, span: Span, taken: usize) {
+        self.branches.entry(span).or_default()[taken] += 1;
+    }
+
+    pub fn call(&mut self, var: &Var) {
+        *self.calls.entry(var.clone()).or_insert(0) += 1;
+    }
+
+    // Run count of every line of code, None for lines without code.
+    pub fn lines(&self, code: &str) -> Vec<Option<u64>> {
+        let mut lines = vec![None; code.split('\n').count()];
+        for span in &self.expressions {
+            let (line, _) = parse::line_col(code, span.start);
+            let hits = self.hits.get(span).copied().unwrap_or(0);
+            let count = lines[line - 1].get_or_insert(0);
+            *count = (*count).max(hits);
+        }
+        lines
+    }
+
+    // The source with the run count of every line in front of it, `#####` for
+    // code that never ran, and a line per conditional or try-catch below the
+    // line it starts on.
+    pub fn annotate(&self, code: &str) -> String {
+        let mut branches: Vec<Vec<String>> = vec![Vec::new(); code.split('\n').count()];
+        for (span, branch) in &self.branch_points {
+            let (line, col) = parse::line_col(code, span.start);
+            let taken = self.branches.get(span).copied().unwrap_or_default();
+            let text = match (branch, self.hits.get(span)) {
+                (_, None) => "never reached".to_string(),
+                (Branch::If, Some(_)) => format!("then taken {}, else taken {}", taken[0], taken[1]),
+                (Branch::TryCatch, Some(_)) => format!("try finished {}, catch ran {}", taken[0], taken[1]),
+            };
+            let kind = if *branch == Branch::If { "conditional" } else { "try-catch" };
+            branches[line - 1].push(format!("{:>9}  {} at {}:{}: {}", "", kind, line, col, text));
+        }
+
+        let mut out = String::new();
+        for ((i, text), count) in code.split('\n').enumerate().zip(self.lines(code)) {
+            let count = match count {
+                Some(0) => "#####".to_string(),
+                Some(count) => count.to_string(),
+                None => "-".to_string(),
+            };
+            writeln!(out, "{:>9}:{:>5}:{}", count, i + 1, text).unwrap();
+            for branch in &branches[i] {
+                writeln!(out, "{}", branch).unwrap();
+            }
+        }
+        out
+    }
+
+    // Coverage in the LCOV tracefile format, `path` names the source file.
+    pub fn lcov(&self, code: &str, path: &str) -> String {
+        let mut out = String::new();
+        writeln!(out, "TN:").unwrap();
+        writeln!(out, "SF:{}", path).unwrap();
+
+        for (span, var) in &self.functions {
+            writeln!(out, "FN:{},{}", parse::line_col(code, span.start).0, var).unwrap();
+        }
+        let mut called = 0;
+        for (_, var) in &self.functions {
+            let calls = self.calls.get(var).copied().unwrap_or(0);
+            called += (calls > 0) as usize;
+            writeln!(out, "FNDA:{},{}", calls, var).unwrap();
+        }
+        writeln!(out, "FNF:{}", self.functions.len()).unwrap();
+        writeln!(out, "FNH:{}", called).unwrap();
+
+        let mut taken_branches = 0;
+        for (block, (span, _)) in self.branch_points.iter().enumerate() {
+            let line = parse::line_col(code, span.start).0;
+            let taken = self.branches.get(span).copied().unwrap_or_default();
+            for (branch, count) in taken.iter().enumerate() {
+                let count = match self.hits.contains_key(span) {
+                    true => count.to_string(),
+                    false => "-".to_string(),
+                };
+                taken_branches += (count != "-" && count != "0") as usize;
+                writeln!(out, "BRDA:{},{},{},{}", line, block, branch, count).unwrap();
+            }
+        }
+        writeln!(out, "BRF:{}", self.branch_points.len() * 2).unwrap();
+        writeln!(out, "BRH:{}", taken_branches).unwrap();
+
+        let (mut found, mut hit) = (0, 0);
+        for (i, count) in self.lines(code).iter().enumerate() {
+            if let Some(count) = count {
+                found += 1;
+                hit += (*count > 0) as usize;
+                writeln!(out, "DA:{},{}", i + 1, count).unwrap();
+            }
+        }
+        writeln!(out, "LF:{}", found).unwrap();
+        writeln!(out, "LH:{}", hit).unwrap();
+        writeln!(out, "end_of_record").unwrap();
+        out
+    }
+}
+
+// Runs a program on the tree evaluator, recording into interp.coverage.
+pub fn coverage_program(code: &str, interp: &mut Interpreter) -> Result<u32, String> {
+    interp.coverage.get_or_insert_with(Coverage::default);
+    Ok(crate::run_instrumented(code, interp)?)
+}
+
+// Runs a program and returns {result, lines, annotated, lcov}, `lines` has the
+// run count of every line or null for lines without code.
+#[wasm_bindgen]
+pub fn coverage_report(code: String, options: &Options) -> JsValue {
+    let mut interp = Interpreter::new(*options);
+    let result = coverage_program(&code, &mut interp)
+        .map(|val| val.to_string())
+        .unwrap_or_else(|err| format!("Error: {}", err));
+    let coverage = interp.coverage.unwrap_or_default();
+    let lines: Vec<String> = coverage.lines(&code).iter().map(|count| count.map_or("null".to_string(), |count| count.to_string())).collect();
+    parse_json(&format!(
+        "{{\"result\":{},\"lines\":[{}],\"annotated\":{},\"lcov\":{}}}",
+        string(&result), lines.join(","), string(&coverage.annotate(&code)), string(&coverage.lcov(&code, "program.bkt"))
+    ))
+}
@@ -0,0 +1,229 @@
+use crate::eval::{Interpreter, Options};
+use crate::parse::{self, Span, Var};
+use crate::session::{Session, Status};
+use wasm_bindgen::prelude::*;
+
+// Step-through debugging. eval::eval_expression cannot stop halfway through a
+// program, so the debugger drives session.rs, which evaluates the same way but
+// can pause between any two steps. The program is parsed with spans and run
+// without simplify and optimize, so what runs is what was written.
+//
+// The debugger stops before an expression is evaluated: after a step, at a
+// breakpoint, or when the first expression of a function with a breakpoint
+// runs. A span breakpoint stops at the outermost expressions starting inside
+// it, so a breakpoint on a line stops once per statement on that line rather
+// than at every bracket in it.
+
+#[derive(Clone, PartialEq)]
+pub enum Breakpoint {
+    Function(Var),
+    Span(Span),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Step {
+    Into,
+    Over(usize), // pending tasks when the step started
+    Out(usize),  // call depth when the step started
+}
+
+#[wasm_bindgen]
+pub struct Debugger {
+    code: String,
+    session: Session,
+    breakpoints: Vec<Breakpoint>,
+    step: Option<Step>,
+    location: Option<Span>,
+    paused: bool,
+    depth: usize, // call depth at the last expression reached
+}
+
+#[wasm_bindgen]
+impl Debugger {
+    // Starts paused before the first expression of the program.
+    #[wasm_bindgen(constructor)]
+    pub fn new(code: String, options: &Options) -> Debugger {
+        Debugger::with_interpreter(code, Interpreter::new(*options))
+    }
+
+    pub fn break_at_function(&mut self, name: String) {
+        self.add_breakpoint(Breakpoint::Function(Var(name)));
+    }
+
+    // 1-based, like the lines shown by location().
+    pub fn break_at_line(&mut self, line: u32) {
+        let span = self.line_span(line as usize);
+        self.add_breakpoint(Breakpoint::Span(span));
+    }
+
+    // Byte offsets into the program, `end` exclusive.
+    pub fn break_at_span(&mut self, start: u32, end: u32) {
+        self.add_breakpoint(Breakpoint::Span(Span { start: start as usize, end: end as usize }));
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn step_into(&mut self) {
+        self.step = Some(Step::Into);
+    }
+
+    // Stops at the next expression once the current one has been evaluated.
+    pub fn step_over(&mut self) {
+        self.step = Some(Step::Over(self.session.pending()));
+    }
+
+    // Stops at the next expression once the current function has returned.
+    pub fn step_out(&mut self) {
+        self.step = Some(Step::Out(self.session.interpreter().call_stack.len()));
+    }
+
+    // Runs to the next breakpoint.
+    pub fn resume(&mut self) {
+        self.step = None;
+    }
+
+    // Runs at most `steps` evaluation steps, returns true once the debugger is
+    // paused or the program is over.
+    pub fn run(&mut self, steps: u32) -> bool {
+        self.paused = false;
+        let limit = self.session.interpreter().steps + steps as u64;
+        while self.session.status() == &Status::Running {
+            let left = limit.saturating_sub(self.session.interpreter().steps);
+            if left == 0 {
+                return false;
+            }
+            if let Some((span, outer)) = self.session.run_to_span(left) && self.should_stop(span, outer) {
+                self.location = Some(span);
+                self.paused = true;
+                return true;
+            }
+        }
+        self.location = None;
+        true
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.session.is_finished()
+    }
+
+    pub fn result(&self) -> String {
+        self.session.result()
+    }
+
+    // Span of the expression the debugger is paused at.
+    pub fn location_start(&self) -> Option<u32> {
+        self.location.map(|span| span.start as u32)
+    }
+
+    pub fn location_end(&self) -> Option<u32> {
+        self.location.map(|span| span.end as u32)
+    }
+
+    // `line:col` of the expression the debugger is paused at.
+    pub fn location(&self) -> String {
+        match self.location {
+            Some(span) => {
+                let (line, col) = parse::line_col(&self.code, span.start);
+                format!("{}:{}", line, col)
+            }
+            None => String::new(),
+        }
+    }
+
+    // `name = value` for every variable of the current frame.
+    pub fn variables(&self) -> Vec<String> {
+        self.session.variables().iter().map(|(var, val)| format!("{} = {}", var, val)).collect()
+    }
+
+    // Running functions, innermost last.
+    pub fn call_stack(&self) -> Vec<String> {
+        self.session.interpreter().call_stack.iter().map(|var| var.0.clone()).collect()
+    }
+}
+
+impl Debugger {
+    pub fn with_interpreter(code: String, mut interp: Interpreter) -> Debugger {
+        interp.options.simplify = false;
+        interp.options.optimize = false;
+        let mut statements = parse::parse_spanned(&code, interp.options.compact_numbers);
+        if interp.options.strict && let Ok((parsed, _)) = &statements && let Err(err) = crate::lint::check_statements(parsed, &code) {
+            statements = Err(err);
+        }
+        Debugger {
+            code,
+            session: Session::with_statements(statements, interp),
+            breakpoints: Vec::new(),
+            step: Some(Step::Into),
+            location: None,
+            paused: false,
+            depth: 0,
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        self.location
+    }
+
+    pub fn status(&self) -> &Status {
+        self.session.status()
+    }
+
+    pub fn frame(&self) -> Vec<(Var, u32)> {
+        self.session.variables()
+    }
+
+    // The whole line, without its line break. Past the end for lines that do
+    // not exist, so nothing stops there.
+    pub fn line_span(&self, line: usize) -> Span {
+        let mut start = 0;
+        for (i, text) in self.code.split('\n').enumerate() {
+            if i + 1 == line {
+                return Span { start, end: start + text.len() };
+            }
+            start += text.len() + 1;
+        }
+        Span { start: self.code.len(), end: self.code.len() }
+    }
+
+    fn should_stop(&mut self, span: Span, outer: Option<Span>) -> bool {
+        let call_stack = &self.session.interpreter().call_stack;
+        let entered = call_stack.len() > self.depth;
+        self.depth = call_stack.len();
+
+        let stepped = match self.step {
+            Some(Step::Into) => true,
+            Some(Step::Over(pending)) => self.session.pending() <= pending,
+            Some(Step::Out(depth)) => call_stack.len() < depth,
+            None => false,
+        };
+        let at_breakpoint = self.breakpoints.iter().any(|breakpoint| match breakpoint {
+            Breakpoint::Function(var) => entered && call_stack.last() == Some(var),
+            Breakpoint::Span(bp) => bp.contains(span.start) && !outer.is_some_and(|outer| bp.contains(outer.start)),
+        });
+        if stepped || at_breakpoint {
+            self.step = None;
+            return true;
+        }
+        false
+    }
+}
@@ -1,18 +1,315 @@
-use crate::parse::{Expression, Var};
-use std::{collections::HashMap};
+use crate::parse::{Arith, Span, Var};
+use crate::resolve::{Function, FunctionIndex, Node, Slot};
+use crate::coverage::Coverage;
+use crate::profile::Profile;
+use crate::trace::Event;
+use std::{collections::HashMap, fmt, rc::Rc};
+use wasm_bindgen::prelude::*;
 
+// One frame, indexed by the slots handed out by resolve::Scope.
 pub struct VarTable {
-    pub variables: HashMap<Var, u32>,
+    pub variables: Vec<Option<u32>>,
 }
 
-#[derive(Clone)]
-pub struct Function {
-    args: Vec<Var>,
-    body: Expression,
+// Indexed by the function indices handed out by resolve::Resolver.
+pub struct FunctionTable {
+    pub functions: Vec<Option<Rc<Function>>>,
 }
 
-pub struct FunctionTable {
-    pub functions: HashMap<Var, Function>,
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Verbosity {
+    Silent, // no diagnostics at all
+    Warn,   // errors swallowed by try-catch
+    Trace,  // additionally every function definition and call
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq)]
+pub enum Engine {
+    Tree,     // eval_expression walking the parsed expressions
+    Bytecode, // vm.rs running the program compiled by bytecode.rs
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct Options {
+    pub engine: Engine,
+    pub verbosity: Verbosity,
+    pub max_steps: Option<u32>, // evaluated expressions before the run is stopped
+    pub max_depth: Option<u32>, // nested function calls before the run is stopped
+    pub simplify: bool,         // fold constants and drop branches that can never run
+    pub optimize: bool,         // replace recognised arithmetic functions by native arithmetic
+    pub memoize: bool,          // remember results of pure functions (tree engine only)
+    pub memo_capacity: u32,     // remembered results before the cache is emptied
+    pub compact_numbers: bool,  // accept the compact number notation, see parse::get_num
+    pub strict: bool,           // refuse to run programs lint.rs finds errors in
+}
+
+#[wasm_bindgen]
+impl Options {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Options {
+        Options {
+            engine: Engine::Tree,
+            verbosity: Verbosity::Warn,
+            max_steps: None,
+            max_depth: None,
+            simplify: true,
+            optimize: true,
+            memoize: false,
+            memo_capacity: 100_000,
+            compact_numbers: false,
+            strict: false,
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options::new()
+    }
+}
+
+pub type Sink = Box<dyn FnMut(&str)>;
+
+// Receives trace events as they happen, see trace.rs.
+pub type EventSink = Box<dyn FnMut(&Event)>;
+
+// Results of calls to pure functions, keyed by function and arguments.
+#[derive(Default)]
+pub struct Memo {
+    results: HashMap<(FunctionIndex, Vec<u32>), u32>,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+impl Memo {
+    pub fn len(&self) -> usize {
+        self.results.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.results.is_empty()
+    }
+
+    // A remembered result, counted as a hit or a miss.
+    pub(crate) fn lookup(&mut self, key: &(FunctionIndex, Vec<u32>)) -> Option<u32> {
+        let result = self.results.get(key).copied();
+        match result {
+            Some(_) => self.hits += 1,
+            None => self.misses += 1,
+        }
+        result
+    }
+
+    pub(crate) fn insert(&mut self, key: (FunctionIndex, Vec<u32>), val: u32, capacity: u32) {
+        if self.results.len() >= capacity as usize {
+            self.evictions += self.results.len() as u64;
+            self.results.clear();
+        }
+        if capacity > 0 {
+            self.results.insert(key, val);
+        }
+    }
+}
+
+// State shared by every frame of a run: the global function table, the
+// options and where program output and diagnostics are written to.
+pub struct Interpreter {
+    pub funcs: FunctionTable,
+    pub options: Options,
+    pub steps: u64,
+    pub call_stack: Vec<Var>,
+    pub memo: Memo,
+    pub span: Option<Span>,         // innermost expression with a span being evaluated
+    pub trace: Option<EventSink>,   // see trace.rs
+    pub profile: Option<Profile>,   // see profile.rs
+    pub coverage: Option<Coverage>, // see coverage.rs
+    output: Sink,
+    diagnostics: Sink,
+}
+
+impl Interpreter {
+    pub fn new(options: Options) -> Interpreter {
+        Interpreter {
+            funcs: FunctionTable { functions: Vec::new() },
+            options,
+            steps: 0,
+            call_stack: Vec::new(),
+            memo: Memo::default(),
+            span: None,
+            trace: None,
+            profile: None,
+            coverage: None,
+            output: default_output(),
+            diagnostics: default_diagnostics(),
+        }
+    }
+
+    // Counts one evaluation step against options.max_steps.
+    pub fn step(&mut self) -> Result<(), RuntimeError> {
+        self.steps += 1;
+        match self.options.max_steps {
+            Some(max) if self.steps > max as u64 => Err(self.limit_exceeded(Limit::Steps, max)),
+            _ => Ok(()),
+        }
+    }
+
+    // Pushes a call frame, checked against options.max_depth.
+    pub fn enter(&mut self, var: &Var) -> Result<(), RuntimeError> {
+        if let Some(max) = self.options.max_depth && self.call_stack.len() >= max as usize {
+            return Err(self.limit_exceeded(Limit::Depth, max));
+        }
+        self.call_stack.push(var.clone());
+        Ok(())
+    }
+
+    pub fn leave(&mut self) {
+        self.call_stack.pop();
+    }
+
+    pub fn record(&mut self, event: Event) {
+        if let Some(trace) = &mut self.trace {
+            trace(&event);
+        }
+    }
+
+    fn limit_exceeded(&self, limit: Limit, max: u32) -> RuntimeError {
+        let function = self.call_stack.last().cloned();
+        let location = match &function {
+            Some(var) => format!("while running function {}", var.0),
+            None => "at top level".to_string(),
+        };
+        let message = match limit {
+            Limit::Steps => format!("Step limit of {} exceeded {}", max, location),
+            Limit::Depth => format!("Call depth limit of {} exceeded {}", max, location),
+        };
+        RuntimeError::new(ErrorKind::LimitExceeded { limit, function }, message)
+    }
+
+    pub fn with_output(mut self, output: Sink) -> Interpreter {
+        self.output = output;
+        self
+    }
+
+    pub fn with_diagnostics(mut self, diagnostics: Sink) -> Interpreter {
+        self.diagnostics = diagnostics;
+        self
+    }
+
+    pub fn print(&mut self, s: &str) {
+        (self.output)(s);
+    }
+
+    pub fn diagnose(&mut self, level: Verbosity, s: &str) {
+        if self.options.verbosity >= level {
+            (self.diagnostics)(s);
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn default_output() -> Sink {
+    Box::new(|s| crate::logOutput(s))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn default_diagnostics() -> Sink {
+    Box::new(|s| crate::logDiagnostic(s))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn default_output() -> Sink {
+    Box::new(|s| {
+        use std::io::Write;
+        let mut stdout = std::io::stdout();
+        let _ = stdout.write_all(s.as_bytes());
+        let _ = stdout.flush();
+    })
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn default_diagnostics() -> Sink {
+    Box::new(|s| eprint!("{}", s))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ErrorKind {
+    DecrementZero,
+    UndefinedVariable,
+    UndefinedFunction,
+    ArityMismatch,
+    InvalidCall,
+    LimitExceeded { limit: Limit, function: Option<Var> }, // cannot be caught by try-catch
+    Thrown(u32), // raised by <|x>, carries the user's code
+    AssertionFailed(Option<Span>), // raised by <||x> when x is 0, with where x is when known
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Limit {
+    Steps,
+    Depth,
+}
+
+// Codes 1 to RESERVED_CODES belong to built-in errors, so a catch can tell
+// them from user errors; <|x> cannot throw them.
+pub const RESERVED_CODES: u32 = 15;
+
+impl ErrorKind {
+    // Number bound by <try|(x)|catch>. Built-in errors use small fixed numbers,
+    // user errors pass their own code through unchanged.
+    pub fn code(&self) -> u32 {
+        match self {
+            ErrorKind::DecrementZero => 1,
+            ErrorKind::UndefinedVariable => 2,
+            ErrorKind::UndefinedFunction => 3,
+            ErrorKind::ArityMismatch => 4,
+            ErrorKind::InvalidCall => 5,
+            ErrorKind::LimitExceeded { .. } => 6,
+            ErrorKind::Thrown(code) => *code,
+            ErrorKind::AssertionFailed(_) => 7,
+        }
+    }
+}
+
+// The error raised by <|code>, an invalid call for a reserved code.
+pub fn thrown(code: u32) -> RuntimeError {
+    match code {
+        1..=RESERVED_CODES => RuntimeError::new(
+            ErrorKind::InvalidCall,
+            format!("Cannot throw {}, codes 1 to {} are reserved for built-in errors", code, RESERVED_CODES),
+        ),
+        _ => RuntimeError::new(ErrorKind::Thrown(code), format!("Uncaught error with code {}", code)),
+    }
+}
+
+// The error of a failed <||x>, `asserted` is where x is and its source, like
+// `3:5: {(())}[]` (see parse::Expression::Assert).
+pub fn assertion(asserted: &Option<(Span, String)>) -> RuntimeError {
+    match asserted {
+        Some((span, asserted)) => RuntimeError::new(ErrorKind::AssertionFailed(Some(*span)), format!("Assertion failed at {} is 0", asserted)),
+        None => RuntimeError::new(ErrorKind::AssertionFailed(None), "Assertion failed, the value is 0".to_string()),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl RuntimeError {
+    pub fn new(kind: ErrorKind, message: String) -> RuntimeError {
+        RuntimeError { kind, message }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
 }
 
 // pub struct CallStack {
@@ -20,114 +317,231 @@ pub struct FunctionTable {
 // }
 
 pub fn eval_expression(
-    exp: &Expression, 
-    funcs: &mut FunctionTable, 
+    exp: &Node, 
+    interp: &mut Interpreter, 
     vars: &mut VarTable, 
-    // call_stack: &mut CallStack 
-) -> Result<u32, String> {
+) -> Result<u32, RuntimeError> {
+    if let Node::At(span, _, node) = exp {
+        if let Some(coverage) = &mut interp.coverage {
+            coverage.hit(*span);
+        }
+        let outer = interp.span.replace(*span);
+        let result = eval_expression(node, interp, vars);
+        interp.span = outer;
+        return result;
+    }
+    interp.step()?;
     match exp {
-        Expression::Val(num) => Ok(*num),
+        Node::Val(num) => Ok(*num),
 
-        Expression::If(cond_exp, then_exp, else_exp) => {
-            let cond_val = eval_expression(cond_exp.as_ref(), funcs, vars)?;
+        Node::If(cond_exp, then_exp, else_exp) => {
+            let span = interp.span;
+            let cond_val = eval_expression(cond_exp.as_ref(), interp, vars)?;
+            if interp.trace.is_some() {
+                interp.record(Event::Branch { taken: cond_val != 0, condition: cond_val, span });
+            }
+            if let Some(coverage) = &mut interp.coverage && let Some(span) = span {
+                coverage.branch(span, (cond_val == 0) as usize);
+            }
             if cond_val != 0 {
-                eval_expression(then_exp.as_ref(), funcs, vars)
+                eval_expression(then_exp.as_ref(), interp, vars)
             } else {
-                eval_expression(else_exp.as_ref(), funcs, vars)
+                eval_expression(else_exp.as_ref(), interp, vars)
             }
         }
 
-        Expression::FunctionDef(var, args, body) => {
-            if args.len() == 0 {
-                let result = eval_expression(body, funcs, vars)?;
-                vars.variables.entry(var.clone()).or_insert_with(|| { result });
-                return Ok(result)
-            }
-            else {
-                funcs.functions.insert(var.clone(), Function {
-                    args: args.clone(),
-                    body: *(*body).clone(),
-                });
-                return Ok(0)
-            }
+        Node::DefineVar(slot, body) => {
+            let result = eval_expression(body, interp, vars)?;
+            vars.variables[*slot].get_or_insert(result);
+            return Ok(result)
         }
 
-        Expression::FunctionCall(var, args) => {
-            if (var.0 == "*" || var.0 == "&") && let Expression::ListExp(args) = *args.clone() {
-                if args.len() != 1 {
-                    return Err(format!("{} operator expects exactly one argument, got {}", var.0, args.len()));
-                }
-                let arg_val = eval_expression(&args[0], funcs, vars)?;
-                if var.0 == "*" {
-                    if arg_val == 0 {
-                        return Err("Cannot decrement zero".to_string());
-                    }
-                    return Ok(arg_val.saturating_sub(1));
-                } else {
-                    return Ok(arg_val.saturating_add(1));
+        Node::DefineFunction(index, func) => {
+            define_function(interp, *index, func.clone());
+            return Ok(0)
+        }
+
+        Node::Inc(arg) => {
+            let arg_val = eval_expression(arg, interp, vars)?;
+            return Ok(arg_val.saturating_add(1));
+        }
+
+        Node::Dec(arg) => {
+            let arg_val = eval_expression(arg, interp, vars)?;
+            return decrement(arg_val);
+        }
+
+        Node::Read(slot, var) => read(vars, *slot, var),
+
+        Node::Call(index, var, params) => {
+            let func = callee(interp, *index, var, params.len())?;
+
+            let span = interp.span;
+            let tracing = interp.options.verbosity >= Verbosity::Trace;
+            let recording = interp.trace.is_some();
+            let memoize = interp.options.memoize && func.pure;
+            let mut local_vars = VarTable { variables: vec![None; func.frame_size] };
+            let mut arg_vals = Vec::new();
+            for (i, arg) in params.iter().enumerate() {
+                let arg_val = eval_expression(arg, interp, vars)?;
+                local_vars.variables[func.args[i]] = Some(arg_val);
+                if tracing || recording || memoize {
+                    arg_vals.push(arg_val);
                 }
             }
-            
-            if *(*args) == Expression::ListExp(vec![]) {
-                let val = vars.variables.get(&var);
-                if val.is_none() {
-                    return Err(format!("Variable {} not defined", var.0));
-                }
-                return Ok(*val.unwrap());
-            } else {
-                let func = funcs.functions.get(var).cloned();
-                if func.is_none() {
-                    return Err(format!("Function {} not defined", var.0));
-                } 
-                let func = func.unwrap();
-                if let Expression::ListExp(params) = *args.clone() {
-
-                    if params.len() != func.args.len() {
-                        return Err(format!("Function {} expects {} arguments, got {}", var.0, func.args.len(), params.len()));
-                    }
+            if tracing {
+                trace_call(interp, var, &arg_vals);
+            }
 
-                    let mut local_vars = Box::new(VarTable { variables: HashMap::new() });
-                    for (i, arg) in params.iter().enumerate() {
-                        let arg_val = eval_expression(arg, funcs, vars)?;
-                        local_vars.variables.insert(func.args[i].clone(), arg_val);
-                    }
+            let key = (*index, arg_vals);
+            if memoize && let Some(val) = interp.memo.lookup(&key) {
+                return Ok(val);
+            }
 
-                    return eval_expression(&func.body, funcs, local_vars.as_mut());
-                } else {
-                    return Err(format!("Expected a list of arguments for function call, got {}", args));
+            let depth = interp.call_stack.len() + 1;
+            if recording {
+                interp.record(Event::Call { function: var.clone(), args: key.1.clone(), depth, span });
+            }
+            if let Err(err) = interp.enter(var) {
+                if recording {
+                    interp.record(Event::Return { function: var.clone(), result: Err(err.clone()), depth });
                 }
+                return Err(err);
             }
+            if let Some(profile) = &mut interp.profile {
+                profile.enter(*index, var, interp.steps);
+            }
+            if let Some(coverage) = &mut interp.coverage {
+                coverage.call(var);
+            }
+            let result = eval_expression(&func.body, interp, &mut local_vars);
+            interp.leave();
+            if let Some(profile) = &mut interp.profile {
+                profile.leave(interp.steps);
+            }
+            if recording {
+                interp.record(Event::Return { function: var.clone(), result: result.clone(), depth });
+            }
+            if memoize && let Ok(val) = result {
+                let capacity = interp.options.memo_capacity;
+                interp.memo.insert(key, val, capacity);
+            }
+            return result;
         }
 
-        Expression::ListExp(list) => {
+        Node::List(list) => {
             let mut result = 0;
             for item in list {
-                result = eval_expression(item, funcs, vars)?;
+                result = eval_expression(item, interp, vars)?;
             }
             Ok(result)
         }
 
-        Expression::TryCatch(try_block, catch_block) => {
-            match eval_expression(try_block, funcs, vars) {
+        Node::TryCatch(try_block, binding, catch_block) => {
+            let span = interp.span;
+            let result = eval_expression(try_block, interp, vars);
+            if let Some(coverage) = &mut interp.coverage && let Some(span) = span && !matches!(result, Err(RuntimeError { kind: ErrorKind::LimitExceeded { .. }, .. })) {
+                coverage.branch(span, result.is_err() as usize);
+            }
+            match result {
                 Ok(result) => Ok(result),
+                Err(err @ RuntimeError { kind: ErrorKind::LimitExceeded { .. }, .. }) => Err(err),
                 Err(err) => {
-                    crate::logOutput(format!("Warning!: Error caught in try block: {}\n", err).as_str());
-                    eval_expression(&catch_block, funcs, vars)
+                    caught(interp, &err, span);
+                    if let Some(slot) = binding {
+                        vars.variables[*slot] = Some(err.kind.code());
+                    }
+                    eval_expression(catch_block, interp, vars)
                 }
             }
         }
 
-        Expression::PrintChar(exp) => {
-            let val = eval_expression(exp, funcs, vars)?;
-            crate::logOutput(format!("{}", char::from_u32(val).unwrap_or('?')).as_str());
+        Node::Throw(exp) => {
+            let code = eval_expression(exp, interp, vars)?;
+            Err(thrown(code))
+        }
+
+        Node::Assert(exp, error) => match eval_expression(exp, interp, vars)? {
+            0 => Err(error.clone()),
+            val => Ok(val),
+        },
+
+        Node::PrintChar(exp) => {
+            let val = eval_expression(exp, interp, vars)?;
+            print_char(interp, val);
             Ok(val)
         }
 
-        Expression::PrintNum(exp) => {
-            let val = eval_expression(exp, funcs, vars)?;
-            crate::logOutput(format!("{}\n", val).as_str());
+        Node::PrintNum(exp) => {
+            let val = eval_expression(exp, interp, vars)?;
+            print_num(interp, val);
             Ok(val)
         }
+
+        Node::Arith(op, x, y) => {
+            let x = eval_expression(x, interp, vars)?;
+            let y = eval_expression(y, interp, vars)?;
+            arith(*op, x, y)
+        }
+
+        Node::Invalid(message) => Err(RuntimeError::new(ErrorKind::InvalidCall, message.clone())),
+
+        Node::At(..) => unreachable!(),
+    }
+}
+
+// The pieces below are shared with session.rs, which evaluates the same nodes
+// from a task stack.
+
+pub(crate) fn define_function(interp: &mut Interpreter, index: FunctionIndex, func: Rc<Function>) {
+    interp.diagnose(Verbosity::Trace, format!("Trace: defined function {} with {} arguments\n", func.name.0, func.args.len()).as_str());
+    let funcs = &mut interp.funcs.functions;
+    if funcs.len() <= index {
+        funcs.resize(index + 1, None);
+    }
+    funcs[index] = Some(func);
+}
+
+// The function a call with `arity` arguments runs.
+pub(crate) fn callee(interp: &Interpreter, index: FunctionIndex, var: &Var, arity: usize) -> Result<Rc<Function>, RuntimeError> {
+    let Some(func) = interp.funcs.functions.get(index).cloned().flatten() else {
+        return Err(RuntimeError::new(ErrorKind::UndefinedFunction, format!("Function {} not defined", var.0)));
+    };
+    if arity != func.args.len() {
+        return Err(RuntimeError::new(ErrorKind::ArityMismatch, format!("Function {} expects {} arguments, got {}", var.0, func.args.len(), arity)));
+    }
+    Ok(func)
+}
+
+pub(crate) fn trace_call(interp: &mut Interpreter, var: &Var, args: &[u32]) {
+    let args: Vec<String> = args.iter().map(|val| val.to_string()).collect();
+    interp.diagnose(Verbosity::Trace, format!("Trace: calling {}({})\n", var.0, args.join(", ")).as_str());
+}
+
+pub(crate) fn read(vars: &VarTable, slot: Slot, var: &Var) -> Result<u32, RuntimeError> {
+    vars.variables[slot].ok_or_else(|| RuntimeError::new(ErrorKind::UndefinedVariable, format!("Variable {} not defined", var.0)))
+}
+
+pub(crate) fn decrement(val: u32) -> Result<u32, RuntimeError> {
+    val.checked_sub(1).ok_or_else(|| RuntimeError::new(ErrorKind::DecrementZero, "Cannot decrement zero".to_string()))
+}
+
+pub(crate) fn arith(op: Arith, x: u32, y: u32) -> Result<u32, RuntimeError> {
+    op.apply(x, y).ok_or_else(|| RuntimeError::new(ErrorKind::DecrementZero, "Cannot decrement zero".to_string()))
+}
+
+pub(crate) fn print_num(interp: &mut Interpreter, val: u32) {
+    interp.print(format!("{}\n", val).as_str());
+}
+
+pub(crate) fn print_char(interp: &mut Interpreter, val: u32) {
+    interp.print(format!("{}", char::from_u32(val).unwrap_or('?')).as_str());
+}
+
+// Reports an error a try block caught, before its catch block runs.
+pub(crate) fn caught(interp: &mut Interpreter, err: &RuntimeError, span: Option<Span>) {
+    interp.diagnose(Verbosity::Warn, format!("Warning!: Error caught in try block: {}\n", err).as_str());
+    if interp.trace.is_some() {
+        interp.record(Event::Caught { error: err.clone(), span });
     }
 }
-                       
\ No newline at end of file
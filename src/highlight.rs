@@ -0,0 +1,217 @@
+use crate::eval::Options;
+use crate::parse::{self, Expression, Span, Token};
+use crate::symbols::{self, Kind, Symbols};
+use crate::trace::parse_json;
+use wasm_bindgen::prelude::*;
+
+// Semantic classes of the parts of a program, for colouring it. Everything is
+// brackets, so what a bracket means depends on where it is: `[()()]` is a
+// number, `{(()())}` a name and `|` either separates the branches of a
+// conditional or belongs to a try-catch or a print. Names are classed by what
+// they refer to (see symbols.rs), so a read of a parameter is a parameter.
+//
+// Brackets that only group statements and the brackets around parameter lists
+// have no class.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Class {
+    Number,
+    Variable,
+    Function,
+    Parameter,
+    Operator,    // & and *
+    Conditional, // the pipes of a conditional
+    Print,       // the <, | and > of a print
+    TryCatch,    // the <, | and > of a try-catch, throw or assertion
+    Comment,
+}
+
+impl Class {
+    pub const ALL: [Class; 9] = [
+        Class::Number,
+        Class::Variable,
+        Class::Function,
+        Class::Parameter,
+        Class::Operator,
+        Class::Conditional,
+        Class::Print,
+        Class::TryCatch,
+        Class::Comment,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Class::Number => "number",
+            Class::Variable => "variable",
+            Class::Function => "function",
+            Class::Parameter => "parameter",
+            Class::Operator => "operator",
+            Class::Conditional => "conditional",
+            Class::Print => "print",
+            Class::TryCatch => "tryCatch",
+            Class::Comment => "comment",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SemanticToken {
+    pub span: Span,
+    pub class: Class,
+}
+
+// Tokens in source order, up to a parse error; comments are always there.
+pub fn semantic_tokens(code: &str, compact: bool) -> Vec<SemanticToken> {
+    let mut highlighter = Highlighter { tokens: Vec::new(), offsets: Vec::new(), out: Vec::new() };
+    for comment in symbols::comments(code) {
+        highlighter.out.push(SemanticToken { span: comment, class: Class::Comment });
+    }
+    if let (Ok((tokens, offsets)), Ok((statements, _))) = (parse::tokenize_with_offsets(code), parse::parse_spanned(code, compact)) {
+        highlighter.tokens = tokens;
+        highlighter.offsets = offsets;
+        for exp in &statements {
+            highlighter.walk(exp, None);
+        }
+        highlighter.names(&symbols::symbols(code, compact));
+    }
+    let mut tokens = highlighter.out;
+    tokens.sort_by_key(|token| token.span.start);
+    tokens
+}
+
+// Returns the tokens as {class, start, end, line, column, endLine, endColumn}
+// objects, lines and columns counted from 1.
+#[wasm_bindgen]
+pub fn highlight_program(code: String, options: &Options) -> JsValue {
+    let tokens: Vec<String> = semantic_tokens(&code, options.compact_numbers)
+        .iter()
+        .map(|token| {
+            let (line, col) = parse::line_col(&code, token.span.start);
+            let (end_line, end_col) = parse::line_col(&code, token.span.end);
+            format!(
+                "{{\"class\":\"{}\",\"start\":{},\"end\":{},\"line\":{},\"column\":{},\"endLine\":{},\"endColumn\":{}}}",
+                token.class.name(), token.span.start, token.span.end, line, col, end_line, end_col
+            )
+        })
+        .collect();
+    parse_json(&format!("[{}]", tokens.join(",")))
+}
+
+struct Highlighter {
+    tokens: Vec<Token>,
+    offsets: Vec<usize>,
+    out: Vec<SemanticToken>,
+}
+
+impl Highlighter {
+    fn push(&mut self, index: usize, class: Class) {
+        let start = self.offsets[index];
+        self.out.push(SemanticToken { span: Span { start, end: start + 1 }, class });
+    }
+
+    fn token(&self, offset: usize) -> usize {
+        self.offsets.partition_point(|start| *start < offset)
+    }
+
+    // Tokens of `span` nested `depth` brackets deep that are one of `wanted`.
+    fn inside(&self, span: Span, depth: i32, wanted: &[Token]) -> Vec<usize> {
+        let mut found = Vec::new();
+        let mut level = 0;
+        for index in self.token(span.start)..self.token(span.end) {
+            let token = self.tokens[index];
+            if matches!(token, Token::CloseParen | Token::CloseSquare | Token::CloseCurly | Token::CloseAngle) {
+                level -= 1;
+            }
+            if level == depth && wanted.contains(&token) {
+                found.push(index);
+            }
+            if matches!(token, Token::OpenParen | Token::OpenSquare | Token::OpenCurly | Token::OpenAngle) {
+                level += 1;
+            }
+        }
+        found
+    }
+
+    fn walk(&mut self, exp: &Expression, at: Option<Span>) {
+        match exp {
+            Expression::At(span, exp) => self.walk(exp, Some(*span)),
+            Expression::Val(_) => {
+                if let Some(span) = at {
+                    self.out.push(SemanticToken { span, class: Class::Number });
+                }
+            }
+            Expression::FunctionCall(var, args) => {
+                if let Some(span) = at && (var.0 == "&" || var.0 == "*") {
+                    self.push(self.token(span.start), Class::Operator);
+                }
+                self.walk(args, None);
+            }
+            Expression::If(cond, then_exp, else_exp) => {
+                if let Some(span) = at {
+                    for index in self.inside(span, 0, &[Token::Pipe]) {
+                        self.push(index, Class::Conditional);
+                    }
+                }
+                self.walk(cond, None);
+                self.walk(then_exp, None);
+                self.walk(else_exp, None);
+            }
+            Expression::TryCatch(try_block, _, catch_block) => {
+                self.delimiters(at, Class::TryCatch);
+                self.walk(try_block, None);
+                self.walk(catch_block, None);
+            }
+            Expression::Throw(exp) | Expression::Assert(exp, _) => {
+                self.delimiters(at, Class::TryCatch);
+                self.walk(exp, None);
+            }
+            Expression::PrintNum(exp) | Expression::PrintChar(exp) => {
+                self.delimiters(at, Class::Print);
+                self.walk(exp, None);
+            }
+            Expression::FunctionDef(_, _, body) => self.walk(body, None),
+            Expression::ListExp(list) => list.iter().for_each(|item| self.walk(item, None)),
+            Expression::Arith(_, x, y) => {
+                self.walk(x, None);
+                self.walk(y, None);
+            }
+        }
+    }
+
+    // The angle brackets around `span` and the pipes right inside them.
+    fn delimiters(&mut self, at: Option<Span>, class: Class) {
+        let Some(span) = at else { return };
+        let mut delimiters = self.inside(span, 0, &[Token::OpenAngle, Token::CloseAngle]);
+        delimiters.extend(self.inside(span, 1, &[Token::Pipe]));
+        for index in delimiters {
+            self.push(index, class);
+        }
+    }
+
+    // Names of definitions and uses, and the braces around them.
+    fn names(&mut self, symbols: &Symbols) {
+        let kinds = symbols.definitions.iter().map(|definition| (definition.name, definition.kind, definition.kind != Kind::Parameter && definition.kind != Kind::Binding));
+        let references = symbols.references.iter().map(|reference| {
+            let kind = match reference.definition {
+                Some(d) => symbols.definitions[d].kind,
+                None if reference.argc > 0 => Kind::Function,
+                None => Kind::Variable,
+            };
+            (reference.name, kind, true)
+        });
+        let names: Vec<(Span, Kind, bool)> = kinds.chain(references).collect();
+        for (name, kind, braced) in names {
+            let class = match kind {
+                Kind::Function => Class::Function,
+                Kind::Parameter => Class::Parameter,
+                Kind::Variable | Kind::Binding => Class::Variable,
+            };
+            self.out.push(SemanticToken { span: name, class });
+            if braced {
+                let open = self.token(name.start) - 1;
+                self.push(open, class);
+                self.push(self.token(name.end), class);
+            }
+        }
+    }
+}
@@ -1,12 +1,37 @@
+#![allow(clippy::needless_return)]
+
 use wasm_bindgen::prelude::*;
-use std::collections::HashMap;
 
-mod parse;
-mod eval;
+pub mod parse;
+pub mod eval;
+pub mod resolve;
+pub mod session;
+pub mod debug;
+pub mod trace;
+pub mod profile;
+pub mod coverage;
+pub mod lint;
+pub mod symbols;
+pub mod lsp;
+pub mod highlight;
+pub mod testing;
+pub mod bytecode;
+pub mod vm;
+pub mod optimize;
+pub mod purity;
+pub mod simplify;
+pub mod backend;
+pub mod printer;
+pub mod assembler;
+
+pub use eval::{Engine, Interpreter, Options, RuntimeError, Verbosity};
+pub use session::{Session, Status};
+pub use debug::Debugger;
 
 #[wasm_bindgen(module = "/site/rust_call.js")]
 extern "C" {
     pub fn logOutput(s: &str);
+    pub fn logDiagnostic(s: &str);
 }
 
 
@@ -15,24 +40,124 @@ pub fn eval_unwrapped(code: String) -> String {
     eval_all(code).unwrap_or_else(|err| format!("Error: {}", err))
 }
 
+#[wasm_bindgen]
+pub fn eval_with_options(code: String, options: &Options) -> String {
+    let mut interp = Interpreter::new(*options);
+    run_program(&code, &mut interp)
+        .map(|val| val.to_string())
+        .unwrap_or_else(|err| format!("Error: {}", err))
+}
+
 pub fn eval_all(code: String) -> Result<String, String> {
-    let mut output = String::new();
-    let tokens = parse::tokenize(&code)?;
-    // println!("{}", tokens.iter().map(|t| t.to_string()).collect::<Vec::<String>>().join(""));
-    
-    let mut funcs = Box::new(eval::FunctionTable{
-        functions: HashMap::new(),
-    });
-    let mut vars = Box::new(eval::VarTable {
-        variables: HashMap::new(),
-    });
-    let mut index = 0;
-    while index < tokens.len() {
-        let (exp, end) = parse::get_next_expression(&tokens, index)?;
-        index = end; 
+    let mut interp = Interpreter::new(Options::default());
+    Ok(format!("{}", run_program(&code, &mut interp)?))
+}
+
+// Why a program did not produce a value: an error while it ran, or anything
+// else that stopped it (a parse error, strict mode or simplify refusing it, a
+// timeout).
+#[derive(Clone, Debug)]
+pub enum ProgramError {
+    Runtime(RuntimeError),
+    Other(String),
+}
+
+impl std::fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProgramError::Runtime(err) => write!(f, "{}", err),
+            ProgramError::Other(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<RuntimeError> for ProgramError {
+    fn from(err: RuntimeError) -> ProgramError {
+        ProgramError::Runtime(err)
+    }
+}
+
+impl From<String> for ProgramError {
+    fn from(err: String) -> ProgramError {
+        ProgramError::Other(err)
+    }
+}
+
+impl From<ProgramError> for String {
+    fn from(err: ProgramError) -> String {
+        err.to_string()
+    }
+}
+
+pub fn run_program(code: &str, interp: &mut Interpreter) -> Result<u32, ProgramError> {
+    if interp.options.engine == Engine::Bytecode {
+        return vm::run_program(code, interp);
+    }
+    let (statements, parse_error) = prepare(code, &interp.options)?;
+    run_statements(statements, parse_error, interp)
+}
+
+// Runs parsed statements with eval_expression, then fails with `parse_error`
+// if there is one.
+pub fn run_statements(statements: Vec<parse::Expression>, parse_error: Option<String>, interp: &mut Interpreter) -> Result<u32, ProgramError> {
+    let mut output = 0;
+    let mut resolver = resolve::Resolver::new();
+    if interp.options.memoize {
+        resolver.pure = purity::pure_functions(&statements);
+    }
+    let mut vars = eval::VarTable {
+        variables: Vec::new(),
+    };
+    for exp in statements {
         // println!("{}", exp); 
-        let x = eval::eval_expression(&exp, funcs.as_mut(), vars.as_mut())?; 
-        output = format!("{}", x); 
+        let node = resolver.resolve_statement(&exp);
+        vars.variables.resize(resolver.globals.len(), None);
+        output = eval::eval_expression(&node, interp, &mut vars)?;
+    }
+    match parse_error {
+        Some(err) => Err(ProgramError::Other(err)),
+        None => Ok(output),
+    }
+}
+
+// Runs a program for the instruments set up in `interp` (trace, profile and
+// coverage, whose counters are made from the parsed program here), any
+// number of them at once. Like the debugger, it runs on the tree evaluator as
+// written: parsed with spans and without simplify, optimize and memoize, which
+// would hide calls and branches.
+pub fn run_instrumented(code: &str, interp: &mut Interpreter) -> Result<u32, ProgramError> {
+    interp.options.engine = Engine::Tree;
+    interp.options.simplify = false;
+    interp.options.optimize = false;
+    interp.options.memoize = false;
+    let (statements, parse_error) = parse::parse_spanned(code, interp.options.compact_numbers)?;
+    if interp.options.strict {
+        lint::check_statements(&statements, code)?;
+    }
+    if interp.coverage.is_some() {
+        interp.coverage = Some(coverage::Coverage::new(&statements));
+    }
+    let result = run_statements(statements, parse_error, interp);
+    let steps = interp.steps;
+    if let Some(profile) = &mut interp.profile {
+        profile.finish(steps);
+    }
+    result
+}
+
+// Tokenizes and parses a program and applies the passes enabled in `options`.
+// A parse error is returned next to the statements before it, which still
+// run first, as if each statement was parsed right before running it.
+pub fn prepare(code: &str, options: &Options) -> Result<(Vec<parse::Expression>, Option<String>), String> {
+    if options.strict {
+        lint::check_code(code, options.compact_numbers)?;
+    }
+    let (mut statements, parse_error) = parse::parse_program(code, options.compact_numbers)?;
+    if options.simplify {
+        simplify::simplify(&mut statements)?;
+    }
+    if options.optimize {
+        optimize::optimize(&mut statements);
     }
-    Ok(output)
+    Ok((statements, parse_error))
 }
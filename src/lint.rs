@@ -0,0 +1,298 @@
+use crate::eval::{Options, RESERVED_CODES};
+use crate::parse::{self, Expression, Span, Var};
+use crate::trace::{parse_json, string};
+use std::collections::{HashMap, HashSet};
+use wasm_bindgen::prelude::*;
+
+// Finds mistakes that eval_expression would only report once the faulty code
+// runs, by looking at the whole parsed program:
+// - calls of functions that are defined nowhere, or with a number of arguments
+//   no definition takes
+// - reads of names that are not a parameter or variable of the function they
+//   are in (functions cannot see top level variables), or at top level of names
+//   never defined there
+// - throws of a constant code reserved for built-in errors
+// - branches that can never run because their condition is a constant
+// - parameter lists naming the same parameter twice
+// - functions defined again outside any conditional, replacing the first
+//   definition for the rest of the run
+//
+// Definitions count wherever they are, even if they run later or in a branch
+// that may not run. Calls and reads that would fail inside a try block are
+// warnings, the error is caught there. Nothing here is a proof that the
+// program works.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub span: Span,
+    pub message: String,
+}
+
+// Lints a program, a parse error is reported from where parsing stopped.
+pub fn lint_code(code: &str, compact: bool) -> Result<Vec<Diagnostic>, String> {
+    let (statements, parse_error) = parse::parse_spanned(code, compact)?;
+    let mut diagnostics = lint(&statements);
+    if let Some(message) = parse_error {
+        let end = statements.iter().filter_map(span).map(|span| span.end).max().unwrap_or(0);
+        let (_, offsets) = parse::tokenize_with_offsets(code)?;
+        let start = offsets.into_iter().find(|offset| *offset >= end).unwrap_or(end);
+        diagnostics.push(Diagnostic { severity: Severity::Error, span: Span { start, end: code.len() }, message });
+    }
+    Ok(diagnostics)
+}
+
+// The strict mode (Options::strict): fails with one `line:col: message` line
+// per error, warnings do not stop a program.
+pub fn check_code(code: &str, compact: bool) -> Result<(), String> {
+    let (statements, _) = parse::parse_spanned(code, compact)?;
+    check_statements(&statements, code)
+}
+
+// Statements as returned by parse::parse_spanned.
+pub fn check_statements(statements: &[Expression], code: &str) -> Result<(), String> {
+    let errors: Vec<String> = lint(statements)
+        .into_iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .map(|diagnostic| {
+            let (line, col) = parse::line_col(code, diagnostic.span.start);
+            format!("{}:{}: {}", line, col, diagnostic.message)
+        })
+        .collect();
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors.join("\n")),
+    }
+}
+
+// Statements as returned by parse::parse_spanned, diagnostics in source order.
+pub fn lint(statements: &[Expression]) -> Vec<Diagnostic> {
+    let mut linter = Linter::default();
+    for exp in statements {
+        let vars = linter.collect(exp, Span::default(), true);
+        linter.top_level.extend(vars);
+    }
+    linter.frames.push(linter.top_level.clone());
+    for exp in statements {
+        linter.check(exp, Span::default());
+    }
+    linter.diagnostics.sort_by_key(|diagnostic| (diagnostic.span.start, diagnostic.severity));
+    linter.diagnostics
+}
+
+// Returns diagnostics as {severity, message, start, end, line, column,
+// endLine, endColumn} objects, lines and columns counted from 1.
+#[wasm_bindgen]
+pub fn lint_program(code: String, options: &Options) -> JsValue {
+    let diagnostics = match lint_code(&code, options.compact_numbers) {
+        Ok(diagnostics) => diagnostics,
+        Err(message) => vec![Diagnostic { severity: Severity::Error, span: Span { start: 0, end: code.len() }, message }],
+    };
+    let objects: Vec<String> = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let (line, col) = parse::line_col(&code, diagnostic.span.start);
+            let (end_line, end_col) = parse::line_col(&code, diagnostic.span.end);
+            let severity = if diagnostic.severity == Severity::Error { "error" } else { "warning" };
+            format!(
+                "{{\"severity\":\"{}\",\"message\":{},\"start\":{},\"end\":{},\"line\":{},\"column\":{},\"endLine\":{},\"endColumn\":{}}}",
+                severity, string(&diagnostic.message), diagnostic.span.start, diagnostic.span.end, line, col, end_line, end_col
+            )
+        })
+        .collect();
+    parse_json(&format!("[{}]", objects.join(",")))
+}
+
+#[derive(Default)]
+struct Linter {
+    arities: HashMap<Var, Vec<usize>>,     // of every definition of a function
+    unconditional: HashSet<Var>,           // functions defined outside conditionals, try-catch and functions
+    top_level: HashSet<Var>,               // variables defined at top level
+    locals: HashMap<Span, HashSet<Var>>,   // variables of the function defined at a span
+    frames: Vec<HashSet<Var>>,             // names readable where check is
+    functions: Vec<Var>,                   // functions check is inside of
+    tries: usize,                          // try blocks check is inside of, in the current function
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Linter {
+    fn report(&mut self, severity: Severity, span: Span, message: String) {
+        self.diagnostics.push(Diagnostic { severity, span, message });
+    }
+
+    // Errors a try block catches are only warnings.
+    fn fault(&mut self, span: Span, message: String) {
+        let severity = if self.tries > 0 { Severity::Warning } else { Severity::Error };
+        self.report(severity, span, message);
+    }
+
+    // First pass: definitions and the variables of every function. Returns the
+    // variables `exp` defines in its own frame. `at` is the span of the
+    // innermost expression with one, `plain` is false inside conditionals,
+    // try-catch and function bodies.
+    fn collect(&mut self, exp: &Expression, at: Span, plain: bool) -> HashSet<Var> {
+        let mut vars = HashSet::new();
+        match exp {
+            Expression::At(span, exp) => return self.collect(exp, *span, plain),
+            Expression::FunctionDef(var, args, body) if args.is_empty() => {
+                vars.insert(var.clone());
+                vars.extend(self.collect(body, at, plain));
+            }
+            Expression::FunctionDef(var, args, body) => {
+                self.arities.entry(var.clone()).or_default().push(args.len());
+                if plain && !self.unconditional.insert(var.clone()) {
+                    self.report(Severity::Warning, at, format!("Function {} is already defined above, this definition replaces it", var));
+                }
+                let mut locals: HashSet<Var> = args.iter().cloned().collect();
+                locals.extend(self.collect(body, at, false));
+                self.locals.insert(at, locals);
+            }
+            Expression::If(cond, then_exp, else_exp) => {
+                vars.extend(self.collect(cond, at, plain));
+                vars.extend(self.collect(then_exp, at, false));
+                vars.extend(self.collect(else_exp, at, false));
+            }
+            Expression::TryCatch(try_block, binding, catch_block) => {
+                vars.extend(binding.clone());
+                vars.extend(self.collect(try_block, at, false));
+                vars.extend(self.collect(catch_block, at, false));
+            }
+            Expression::FunctionCall(_, args) => vars = self.collect(args, at, plain),
+            Expression::ListExp(list) => {
+                for item in list {
+                    vars.extend(self.collect(item, at, plain));
+                }
+            }
+            Expression::Throw(exp) | Expression::Assert(exp, _) | Expression::PrintNum(exp) | Expression::PrintChar(exp) => vars = self.collect(exp, at, plain),
+            Expression::Arith(_, x, y) => {
+                vars.extend(self.collect(x, at, plain));
+                vars.extend(self.collect(y, at, plain));
+            }
+            Expression::Val(_) => {}
+        }
+        vars
+    }
+
+    // Second pass: calls, reads, conditions and parameter lists.
+    fn check(&mut self, exp: &Expression, at: Span) {
+        match exp {
+            Expression::At(span, exp) => self.check(exp, *span),
+            Expression::Val(_) => {}
+            Expression::FunctionDef(var, args, body) => {
+                if args.is_empty() {
+                    return self.check(body, at);
+                }
+                let mut seen = HashSet::new();
+                for arg in args {
+                    if !seen.insert(arg) {
+                        self.report(Severity::Warning, at, format!("Parameter {} of function {} is listed more than once, the last argument wins", arg, var));
+                    }
+                }
+                self.frames.push(self.locals.get(&at).cloned().unwrap_or_default());
+                self.functions.push(var.clone());
+                let tries = std::mem::take(&mut self.tries);
+                self.check(body, at);
+                self.tries = tries;
+                self.functions.pop();
+                self.frames.pop();
+            }
+            Expression::FunctionCall(var, args) => {
+                if let Expression::ListExp(params) = args.as_ref() {
+                    self.check_call(var, params.len(), at);
+                }
+                self.check(args, at);
+            }
+            Expression::If(cond, then_exp, else_exp) => {
+                if let Some(val) = constant(cond) {
+                    let (dead, branch) = if val != 0 { (else_exp, "else") } else { (then_exp, "then") };
+                    if let Some(span) = span(dead) {
+                        self.report(Severity::Warning, span, format!("The {} branch can never run, the condition is always {}", branch, val));
+                    }
+                }
+                self.check(cond, at);
+                self.check(then_exp, at);
+                self.check(else_exp, at);
+            }
+            Expression::ListExp(list) => list.iter().for_each(|item| self.check(item, at)),
+            Expression::TryCatch(try_block, _, catch_block) => {
+                self.tries += 1;
+                self.check(try_block, at);
+                self.tries -= 1;
+                self.check(catch_block, at);
+            }
+            Expression::Throw(exp) => {
+                if let Some(code @ 1..=RESERVED_CODES) = constant(exp) {
+                    self.fault(at, format!("Cannot throw {}, codes 1 to {} are reserved for built-in errors", code, RESERVED_CODES));
+                }
+                self.check(exp, at);
+            }
+            Expression::Assert(exp, _) | Expression::PrintNum(exp) | Expression::PrintChar(exp) => self.check(exp, at),
+            Expression::Arith(_, x, y) => {
+                self.check(x, at);
+                self.check(y, at);
+            }
+        }
+    }
+
+    fn check_call(&mut self, var: &Var, argc: usize, at: Span) {
+        if var.0 == "&" || var.0 == "*" {
+            return;
+        }
+        if argc == 0 {
+            if !self.frames.last().unwrap().contains(var) {
+                let message = match self.functions.last() {
+                    Some(function) => format!("Variable {} is not a parameter or variable of function {}", var, function),
+                    None => format!("Variable {} is never defined at top level", var),
+                };
+                self.fault(at, message);
+            }
+            return;
+        }
+        match self.arities.get(var) {
+            None => self.fault(at, format!("Function {} is not defined anywhere in the program", var)),
+            Some(arities) if !arities.contains(&argc) => {
+                let mut arities = arities.clone();
+                arities.sort();
+                arities.dedup();
+                let expected: Vec<String> = arities.iter().map(|arity| arity.to_string()).collect();
+                let message = format!("Function {} expects {} arguments, got {}", var, expected.join(" or "), argc);
+                self.fault(at, message);
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+// Value of an expression that is always the same and has no effects that
+// matter for it, like the `[()]` of `[[()]] | [x] | []`.
+fn constant(exp: &Expression) -> Option<u32> {
+    match exp {
+        Expression::Val(num) => Some(*num),
+        Expression::At(_, exp) => constant(exp),
+        Expression::ListExp(list) => match list.as_slice() {
+            [] => Some(0),
+            items => items.iter().all(|item| constant(item).is_some()).then(|| constant(items.last().unwrap())).flatten(),
+        },
+        Expression::FunctionCall(var, args) if var.0 == "&" => constant(args).map(|num| num.saturating_add(1)),
+        Expression::FunctionCall(var, args) if var.0 == "*" => constant(args).and_then(|num| num.checked_sub(1)),
+        _ => None,
+    }
+}
+
+// From the first to the last expression with a span, None if there is none.
+fn span(exp: &Expression) -> Option<Span> {
+    match exp {
+        Expression::At(span, _) => Some(*span),
+        Expression::ListExp(list) => {
+            let spans: Vec<Span> = list.iter().filter_map(span).collect();
+            Some(Span { start: spans.first()?.start, end: spans.last()?.end })
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,556 @@
+use crate::highlight::{self, Class};
+use crate::lint::{self, Severity};
+use crate::parse::{Span, Var};
+use crate::symbols::{self, Kind, Symbols, Target};
+use crate::trace::string;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+// Language server for editors speaking the Language Server Protocol over
+// stdin and stdout (the bracket-lsp binary). Documents are synced whole and
+// checked by lint.rs on every change; hover, go to definition, references,
+// rename and document symbols use the names found by symbols.rs, semantic
+// tokens come from highlight.rs.
+//
+// Messages are JSON-RPC with a Content-Length header. The JSON is read and
+// written here rather than with a library, like the JSON of trace.rs.
+
+// JSON values, objects keep the order of their keys.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::Object(members) => members.iter().find(|(name, _)| name == key).map_or(&Json::Null, |(_, value)| value),
+            _ => &Json::Null,
+        }
+    }
+
+    pub fn str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn number(&self) -> Option<f64> {
+        match self {
+            Json::Number(num) => Some(*num),
+            _ => None,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let chars: Vec<char> = text.chars().collect();
+        let (value, end) = parse_value(&chars, skip_space(&chars, 0))?;
+        match skip_space(&chars, end) == chars.len() {
+            true => Ok(value),
+            false => Err(format!("Unexpected character in JSON at {}", end)),
+        }
+    }
+}
+
+impl std::fmt::Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(num) => write!(f, "{}", num),
+            Json::String(s) => write!(f, "{}", string(s)),
+            Json::Array(items) => {
+                let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+                write!(f, "[{}]", items.join(","))
+            }
+            Json::Object(members) => {
+                let members: Vec<String> = members.iter().map(|(name, value)| format!("{}:{}", string(name), value)).collect();
+                write!(f, "{{{}}}", members.join(","))
+            }
+        }
+    }
+}
+
+fn skip_space(chars: &[char], mut index: usize) -> usize {
+    while index < chars.len() && chars[index].is_whitespace() {
+        index += 1;
+    }
+    index
+}
+
+fn parse_value(chars: &[char], start: usize) -> Result<(Json, usize), String> {
+    let literal = |word: &str, value: Json| {
+        let end = start + word.len();
+        match end <= chars.len() && chars[start..end].iter().copied().eq(word.chars()) {
+            true => Ok((value, end)),
+            false => Err(format!("Unexpected character in JSON at {}", start)),
+        }
+    };
+    match chars.get(start) {
+        None => Err("Unexpected end of JSON".to_string()),
+        Some('n') => literal("null", Json::Null),
+        Some('t') => literal("true", Json::Bool(true)),
+        Some('f') => literal("false", Json::Bool(false)),
+        Some('"') => parse_string(chars, start).map(|(s, end)| (Json::String(s), end)),
+        Some('[') => {
+            let mut items = Vec::new();
+            let mut index = skip_space(chars, start + 1);
+            if chars.get(index) == Some(&']') {
+                return Ok((Json::Array(items), index + 1));
+            }
+            loop {
+                let (item, end) = parse_value(chars, index)?;
+                items.push(item);
+                index = skip_space(chars, end);
+                match chars.get(index) {
+                    Some(',') => index = skip_space(chars, index + 1),
+                    Some(']') => return Ok((Json::Array(items), index + 1)),
+                    _ => return Err(format!("Expected , or ] in JSON at {}", index)),
+                }
+            }
+        }
+        Some('{') => {
+            let mut members = Vec::new();
+            let mut index = skip_space(chars, start + 1);
+            if chars.get(index) == Some(&'}') {
+                return Ok((Json::Object(members), index + 1));
+            }
+            loop {
+                if chars.get(index) != Some(&'"') {
+                    return Err(format!("Expected a key in JSON at {}", index));
+                }
+                let (name, end) = parse_string(chars, index)?;
+                index = skip_space(chars, end);
+                if chars.get(index) != Some(&':') {
+                    return Err(format!("Expected : in JSON at {}", index));
+                }
+                let (value, end) = parse_value(chars, skip_space(chars, index + 1))?;
+                members.push((name, value));
+                index = skip_space(chars, end);
+                match chars.get(index) {
+                    Some(',') => index = skip_space(chars, index + 1),
+                    Some('}') => return Ok((Json::Object(members), index + 1)),
+                    _ => return Err(format!("Expected , or }} in JSON at {}", index)),
+                }
+            }
+        }
+        Some(_) => {
+            let mut end = start;
+            while end < chars.len() && (chars[end].is_ascii_digit() || "+-.eE".contains(chars[end])) {
+                end += 1;
+            }
+            let text: String = chars[start..end].iter().collect();
+            text.parse().map(|num| (Json::Number(num), end)).map_err(|_| format!("Unexpected character in JSON at {}", start))
+        }
+    }
+}
+
+fn parse_string(chars: &[char], start: usize) -> Result<(String, usize), String> {
+    let mut s = String::new();
+    let mut index = start + 1;
+    let mut surrogate: Option<u32> = None;
+    loop {
+        let c = *chars.get(index).ok_or("Unexpected end of JSON in a string")?;
+        index += 1;
+        // a high surrogate not followed by a low one
+        if surrogate.is_some() && !(c == '\\' && chars.get(index) == Some(&'u')) {
+            surrogate = None;
+            s.push('\u{FFFD}');
+        }
+        match c {
+            '"' => return Ok((s, index)),
+            '\\' => {
+                let escape = *chars.get(index).ok_or("Unexpected end of JSON in a string")?;
+                index += 1;
+                match escape {
+                    'n' => s.push('\n'),
+                    't' => s.push('\t'),
+                    'r' => s.push('\r'),
+                    'b' => s.push('\u{8}'),
+                    'f' => s.push('\u{c}'),
+                    'u' => {
+                        let hex: String = chars.get(index..index + 4).ok_or("Unexpected end of JSON in a string")?.iter().collect();
+                        let unit = u32::from_str_radix(&hex, 16).map_err(|_| format!("Bad escape in JSON at {}", index))?;
+                        index += 4;
+                        match (surrogate.take(), unit) {
+                            (Some(high), 0xDC00..=0xDFFF) => s.extend(char::from_u32(0x10000 + ((high - 0xD800) << 10) + (unit - 0xDC00))),
+                            (high, unit) => {
+                                if high.is_some() {
+                                    s.push('\u{FFFD}');
+                                }
+                                match unit {
+                                    0xD800..=0xDBFF => surrogate = Some(unit),
+                                    unit => s.push(char::from_u32(unit).unwrap_or('\u{FFFD}')),
+                                }
+                            }
+                        }
+                    }
+                    c => s.push(c),
+                }
+            }
+            c => s.push(c),
+        }
+    }
+}
+
+// LSP positions are 0-based lines and UTF-16 units into the line.
+pub fn position(code: &str, offset: usize) -> (usize, usize) {
+    let before = &code[..offset.min(code.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (before.matches('\n').count(), before[line_start..].encode_utf16().count())
+}
+
+pub fn offset(code: &str, line: usize, character: usize) -> usize {
+    let line_start = match line {
+        0 => 0,
+        line => match code.match_indices('\n').nth(line - 1) {
+            Some((i, _)) => i + 1,
+            None => return code.len(),
+        },
+    };
+    let mut units = 0;
+    for (i, c) in code[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    code.len()
+}
+
+fn range(code: &str, span: Span) -> String {
+    let (line, character) = position(code, span.start);
+    let (end_line, end_character) = position(code, span.end);
+    format!(
+        "{{\"start\":{{\"line\":{},\"character\":{}}},\"end\":{{\"line\":{},\"character\":{}}}}}",
+        line, character, end_line, end_character
+    )
+}
+
+fn location(uri: &str, code: &str, span: Span) -> String {
+    format!("{{\"uri\":{},\"range\":{}}}", string(uri), range(code, span))
+}
+
+pub struct Server {
+    documents: HashMap<String, String>,
+    compact: bool,
+    shut_down: bool,
+}
+
+// Serves until the client sends `exit`, returns whether it asked to shut
+// down first.
+pub fn serve(input: impl BufRead, mut output: impl Write, compact: bool) -> Result<bool, String> {
+    let mut server = Server { documents: HashMap::new(), compact, shut_down: false };
+    let mut input = input;
+    while let Some(message) = read_message(&mut input)? {
+        let message = match Json::parse(&message) {
+            Ok(message) => message,
+            Err(err) => {
+                write_message(&mut output, &format!("{{\"jsonrpc\":\"2.0\",\"id\":null,\"error\":{{\"code\":-32700,\"message\":{}}}}}", string(&err)))?;
+                continue;
+            }
+        };
+        let method = message.get("method").str().unwrap_or("");
+        if method == "exit" {
+            return Ok(server.shut_down);
+        }
+        let (response, notifications) = server.handle(method, message.get("params"));
+        for notification in notifications {
+            write_message(&mut output, &notification)?;
+        }
+        let id = message.get("id");
+        if *id == Json::Null {
+            continue;
+        }
+        let response = match response {
+            Ok(result) => format!("{{\"jsonrpc\":\"2.0\",\"id\":{},\"result\":{}}}", id, result),
+            Err((code, err)) => format!("{{\"jsonrpc\":\"2.0\",\"id\":{},\"error\":{{\"code\":{},\"message\":{}}}}}", id, code, string(&err)),
+        };
+        write_message(&mut output, &response)?;
+    }
+    Ok(server.shut_down)
+}
+
+fn read_message(input: &mut impl BufRead) -> Result<Option<String>, String> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line).map_err(|err| err.to_string())? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') && name.eq_ignore_ascii_case("Content-Length") {
+            length = Some(value.trim().parse::<usize>().map_err(|_| format!("Bad Content-Length: {}", value.trim()))?);
+        }
+    }
+    let mut body = vec![0; length.ok_or("Message without Content-Length")?];
+    input.read_exact(&mut body).map_err(|err| err.to_string())?;
+    String::from_utf8(body).map(Some).map_err(|err| err.to_string())
+}
+
+fn write_message(output: &mut impl Write, message: &str) -> Result<(), String> {
+    write!(output, "Content-Length: {}\r\n\r\n{}", message.len(), message).map_err(|err| err.to_string())?;
+    output.flush().map_err(|err| err.to_string())
+}
+
+type Response = Result<String, (i32, String)>;
+
+impl Server {
+    // The result of a request (ignored for notifications) and notifications to
+    // send before it.
+    fn handle(&mut self, method: &str, params: &Json) -> (Response, Vec<String>) {
+        let uri = params.get("textDocument").get("uri").str().unwrap_or("").to_string();
+        match method {
+            "initialize" => (Ok(capabilities()), Vec::new()),
+            "shutdown" => {
+                self.shut_down = true;
+                (Ok("null".to_string()), Vec::new())
+            }
+            "textDocument/didOpen" => {
+                let text = params.get("textDocument").get("text").str().unwrap_or("").to_string();
+                self.documents.insert(uri.clone(), text);
+                (Ok("null".to_string()), vec![self.diagnostics(&uri)])
+            }
+            "textDocument/didChange" => {
+                // synced whole, the last change is the new text
+                if let Json::Array(changes) = params.get("contentChanges") && let Some(text) = changes.last().and_then(|change| change.get("text").str()) {
+                    self.documents.insert(uri.clone(), text.to_string());
+                }
+                (Ok("null".to_string()), vec![self.diagnostics(&uri)])
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                let clear = format!("{{\"jsonrpc\":\"2.0\",\"method\":\"textDocument/publishDiagnostics\",\"params\":{{\"uri\":{},\"diagnostics\":[]}}}}", string(&uri));
+                (Ok("null".to_string()), vec![clear])
+            }
+            "textDocument/semanticTokens/full" => match self.documents.get(&uri) {
+                Some(code) => (Ok(semantic_tokens(code, self.compact)), Vec::new()),
+                None => (Err((-32602, format!("Unknown document {}", uri))), Vec::new()),
+            },
+            "textDocument/hover" | "textDocument/definition" | "textDocument/references" | "textDocument/rename" | "textDocument/documentSymbol" => {
+                let Some(code) = self.documents.get(&uri) else {
+                    return (Err((-32602, format!("Unknown document {}", uri))), Vec::new());
+                };
+                let symbols = symbols::symbols(code, self.compact);
+                let position = params.get("position");
+                let line = position.get("line").number().unwrap_or(0.0) as usize;
+                let character = position.get("character").number().unwrap_or(0.0) as usize;
+                let document = Document { uri: &uri, code, symbols: &symbols };
+                let target = symbols.at(offset(code, line, character));
+                let response = match method {
+                    "textDocument/hover" => Ok(target.map_or("null".to_string(), |target| document.hover(target))),
+                    "textDocument/definition" => Ok(target.map_or("null".to_string(), |target| document.definition(target))),
+                    "textDocument/references" => {
+                        let declarations = *params.get("context").get("includeDeclaration") != Json::Bool(false);
+                        Ok(target.map_or("[]".to_string(), |target| document.references(target, declarations)))
+                    }
+                    "textDocument/rename" => match target {
+                        Some(target) => document.rename(target, params.get("newName").str().unwrap_or("")),
+                        None => Err((-32602, "There is no name to rename here".to_string())),
+                    },
+                    _ => Ok(document.symbols()),
+                };
+                (response, Vec::new())
+            }
+            method if method.starts_with("$/") => (Ok("null".to_string()), Vec::new()),
+            _ => (Err((-32601, format!("Method {} is not supported", method))), Vec::new()),
+        }
+    }
+
+    fn diagnostics(&self, uri: &str) -> String {
+        let code = self.documents.get(uri).map_or("", |code| code.as_str());
+        let diagnostics = match lint::lint_code(code, self.compact) {
+            Ok(diagnostics) => diagnostics,
+            Err(message) => vec![lint::Diagnostic { severity: Severity::Error, span: Span { start: 0, end: code.len() }, message }],
+        };
+        let diagnostics: Vec<String> = diagnostics
+            .iter()
+            .map(|diagnostic| {
+                let severity = if diagnostic.severity == Severity::Error { 1 } else { 2 };
+                format!(
+                    "{{\"range\":{},\"severity\":{},\"source\":\"bracket\",\"message\":{}}}",
+                    range(code, diagnostic.span), severity, string(&diagnostic.message)
+                )
+            })
+            .collect();
+        format!(
+            "{{\"jsonrpc\":\"2.0\",\"method\":\"textDocument/publishDiagnostics\",\"params\":{{\"uri\":{},\"diagnostics\":[{}]}}}}",
+            string(uri), diagnostics.join(",")
+        )
+    }
+}
+
+// Standard semantic token types for highlight::Class::ALL, in that order, so
+// editor themes colour them without extra configuration.
+const TOKEN_TYPES: [&str; 9] = ["number", "variable", "function", "parameter", "operator", "keyword", "macro", "event", "comment"];
+
+fn capabilities() -> String {
+    let types: Vec<String> = TOKEN_TYPES.iter().map(|name| string(name)).collect();
+    format!(
+        "{{\"capabilities\":{{\"textDocumentSync\":1,\"hoverProvider\":true,\"definitionProvider\":true,\"referencesProvider\":true,\
+\"renameProvider\":true,\"documentSymbolProvider\":true,\"semanticTokensProvider\":{{\"legend\":{{\"tokenTypes\":[{}],\"tokenModifiers\":[]}},\
+\"full\":true}}}},\"serverInfo\":{{\"name\":\"bracket-lsp\"}}}}",
+        types.join(",")
+    )
+}
+
+// Five numbers per token: line and start relative to the previous token,
+// length, type and modifiers. Tokens spanning lines (comments) are split, and
+// sorted so no delta is negative.
+fn semantic_tokens(code: &str, compact: bool) -> String {
+    let mut pieces = Vec::new();
+    for token in highlight::semantic_tokens(code, compact) {
+        let class = Class::ALL.iter().position(|class| *class == token.class).unwrap();
+        let mut start = token.span.start;
+        for piece in code[token.span.start..token.span.end].split_inclusive('\n') {
+            let text = piece.trim_end_matches(['\n', '\r']);
+            let (line, character) = position(code, start);
+            start += piece.len();
+            if !text.is_empty() {
+                pieces.push((line, character, text.encode_utf16().count(), class));
+            }
+        }
+    }
+    pieces.sort();
+    let mut data = Vec::new();
+    let (mut last_line, mut last_start) = (0, 0);
+    for (line, character, length, class) in pieces {
+        let delta = if line == last_line { character - last_start } else { character };
+        data.extend([line - last_line, delta, length, class, 0].map(|num| num.to_string()));
+        (last_line, last_start) = (line, character);
+    }
+    format!("{{\"data\":[{}]}}", data.join(","))
+}
+
+struct Document<'a> {
+    uri: &'a str,
+    code: &'a str,
+    symbols: &'a Symbols,
+}
+
+impl Document<'_> {
+    // Markdown with the definition, like `{(()())}(() (()))` and what it is,
+    // then the comment above it.
+    fn hover(&self, target: Target) -> String {
+        let name = match target {
+            Target::Definition(d) => self.symbols.definitions[d].name,
+            Target::Reference(r) => self.symbols.references[r].name,
+        };
+        let text = match self.symbols.definition(target) {
+            Some(d) => {
+                let definition = &self.symbols.definitions[d];
+                let (line, col) = crate::parse::line_col(self.code, definition.span.start);
+                let mut text = match definition.kind {
+                    Kind::Function => {
+                        let params: Vec<&str> = definition.params.iter().map(|param| param.0.as_str()).collect();
+                        format!("```\n{{{}}}({})\n```\nfunction with {} parameters, defined at {}:{}", definition.var, params.join(" "), params.len(), line, col)
+                    }
+                    Kind::Variable => format!("```\n{{{}}}\n```\nvariable of {}, defined at {}:{}", definition.var, self.frame(definition.frame), line, col),
+                    Kind::Parameter => {
+                        let function = &self.symbols.definitions[definition.frame.unwrap()];
+                        let place = function.params.iter().position(|param| *param == definition.var).unwrap_or(0) + 1;
+                        format!("```\n{{{}}}\n```\nparameter {} of function {}", definition.var, place, function.var)
+                    }
+                    Kind::Binding => format!("```\n{{{}}}\n```\nerror code caught at {}:{}, in {}", definition.var, line, col, self.frame(definition.frame)),
+                };
+                if let Some(comment) = self.symbols.comment(self.code, d) && !comment.is_empty() {
+                    text.push_str("\n\n");
+                    text.push_str(comment);
+                }
+                text
+            }
+            None => match target {
+                Target::Reference(r) if self.symbols.references[r].argc > 0 => format!("function {} is not defined", self.symbols.references[r].var),
+                Target::Reference(r) => format!("variable {} is not defined in {}", self.symbols.references[r].var, self.frame(self.symbols.references[r].frame)),
+                Target::Definition(_) => unreachable!(),
+            },
+        };
+        format!("{{\"contents\":{{\"kind\":\"markdown\",\"value\":{}}},\"range\":{}}}", string(&text), range(self.code, name))
+    }
+
+    fn frame(&self, frame: Option<usize>) -> String {
+        match frame {
+            Some(function) => format!("function {}", self.symbols.definitions[function].var),
+            None => "the top level".to_string(),
+        }
+    }
+
+    fn definition(&self, target: Target) -> String {
+        match self.symbols.definition(target) {
+            Some(d) => location(self.uri, self.code, self.symbols.definitions[d].name),
+            None => "null".to_string(),
+        }
+    }
+
+    fn references(&self, target: Target, declarations: bool) -> String {
+        let (definitions, references) = self.symbols.occurrences(target);
+        let mut spans = references;
+        if declarations {
+            spans.extend(definitions);
+        }
+        spans.sort_by_key(|span| span.start);
+        let locations: Vec<String> = spans.iter().map(|span| location(self.uri, self.code, *span)).collect();
+        format!("[{}]", locations.join(","))
+    }
+
+    fn rename(&self, target: Target, name: &str) -> Response {
+        let name = name.trim();
+        if !symbols::valid_name(name) {
+            return Err((-32602, format!("{} is not a name, names are brackets like (()())", name)));
+        }
+        let var = Var(name.to_string());
+        let current = match target {
+            Target::Definition(d) => &self.symbols.definitions[d].var,
+            Target::Reference(r) => &self.symbols.references[r].var,
+        };
+        if var != *current && self.symbols.taken(target, &var) {
+            return Err((-32602, format!("{} is already used here", name)));
+        }
+        let (definitions, references) = self.symbols.occurrences(target);
+        let edits: Vec<String> = definitions
+            .iter()
+            .chain(&references)
+            .map(|span| format!("{{\"range\":{},\"newText\":{}}}", range(self.code, *span), string(name)))
+            .collect();
+        Ok(format!("{{\"changes\":{{{}:[{}]}}}}", string(self.uri), edits.join(",")))
+    }
+
+    // Functions and variables, nested in the function they are defined in.
+    fn symbols(&self) -> String {
+        format!("[{}]", self.children(None).join(","))
+    }
+
+    fn children(&self, frame: Option<usize>) -> Vec<String> {
+        let definitions = self.symbols.definitions.iter().enumerate();
+        let children = definitions.filter(|(_, definition)| definition.frame == frame && matches!(definition.kind, Kind::Function | Kind::Variable));
+        children
+            .map(|(d, definition)| {
+                let (kind, detail) = match definition.kind {
+                    Kind::Function => {
+                        let params: Vec<&str> = definition.params.iter().map(|param| param.0.as_str()).collect();
+                        (12, format!("({})", params.join(" ")))
+                    }
+                    _ => (13, String::new()),
+                };
+                let detail = match self.symbols.comment(self.code, d) {
+                    Some(comment) if !comment.is_empty() => format!("{} {}", detail, comment).trim().to_string(),
+                    _ => detail,
+                };
+                let children = if definition.kind == Kind::Function { self.children(Some(d)) } else { Vec::new() };
+                format!(
+                    "{{\"name\":{},\"detail\":{},\"kind\":{},\"range\":{},\"selectionRange\":{},\"children\":[{}]}}",
+                    string(&definition.var.0), string(&detail), kind, range(self.code, definition.span), range(self.code, definition.name), children.join(",")
+                )
+            })
+            .collect()
+    }
+}
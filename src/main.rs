@@ -0,0 +1,510 @@
+use eso_lang::{assembler, backend, debug::Breakpoint, coverage, lint, parse, profile, testing, trace, Debugger, Engine, Interpreter, Options, ProgramError, Session, Status, Verbosity};
+use std::{cell::RefCell, env, fs, io::{self, Read, Write}, path::Path, process, rc::Rc, time::{Duration, Instant}};
+
+const USAGE: &str = "Usage: bracket [run] <file|-> [options]
+       bracket compile --target <c|js|wasm|rust> <file|-> [-o <file>] [options]
+       bracket assemble <file|-> [-o <file>] [--shortest]
+       bracket debug <file> [options]
+       bracket lint <file|-> [--compact-numbers]
+       bracket test [file|directory] [options]
+
+Options:
+    --engine <tree|vm>                 evaluate the syntax tree or run compiled bytecode (default: tree)
+    --verbosity <silent|warn|trace>    diagnostics written to stderr (default: warn)
+    -q, --quiet                        same as --verbosity silent
+    --max-steps <n>                    stop after evaluating n expressions
+    --max-depth <n>                    stop when calls nest deeper than n
+    --no-simplify                      keep constant expressions and dead branches as written
+    --no-optimize                      keep recursive arithmetic functions as written
+    --memoize                          remember results of functions that print nothing (tree engine)
+    --memo-size <n>                    remembered results before the cache is emptied (default: 100000)
+    --timeout <ms>                     stop after running for ms milliseconds (tree engine)
+    --trace <file>                     write calls, returns, branches and caught errors to file as JSON lines
+    --lcov <file>                      write which lines, branches and functions ran to file in the LCOV format
+    --annotate <file>                  write the program with run counts per line and per branch to file
+    --profile                          report calls, steps and time per function on stderr
+    --folded <file>                    write the profiled call stacks to file in the folded format of flamegraph.pl
+                                       (these five run on the tree engine, not with --engine vm or --timeout)
+    --compact-numbers                  accept (and assemble to) numbers like [((((((())))))) ()] for 65
+    --strict                           refuse to run programs with errors `bracket lint` reports
+    --target <c|js|wasm|rust>          language to compile to
+    --int-type <u32|u64|u128>          number type of compiled Rust (default: u32)
+    -o, --output <file>                where to write the compiled program (default: stdout)
+    --shortest                         write assembled numbers as & chains when that is shorter";
+
+// Steps run between two checks of the --timeout clock.
+const SLICE: u64 = 100_000;
+
+struct Args {
+    options: Options,
+    path: String,
+    timeout: Option<Duration>,
+    target: Option<String>,
+    output: Option<String>,
+    int_type: String,
+    shortest: bool,
+    trace: Option<String>,
+    profile: bool,
+    folded: Option<String>,
+    lcov: Option<String>,
+    annotate: Option<String>,
+}
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let command = match args.first().map(|a| a.as_str()) {
+        Some("run") | Some("compile") | Some("assemble") | Some("debug") | Some("lint") | Some("test") => args.remove(0),
+        _ => "run".to_string(),
+    };
+    let default_path = if command == "test" { Some("tests") } else { None };
+    let result = parse_args(&args, default_path).and_then(|args| match (command.as_str(), args) {
+        (_, None) => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        ("compile", Some(args)) => compile(args),
+        ("assemble", Some(args)) => assemble(args),
+        ("debug", Some(args)) => debug(args),
+        ("lint", Some(args)) => lint(args),
+        ("test", Some(args)) => test(args),
+        (_, Some(args)) => run(args),
+    });
+    if let Err(err) = result {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+// None when only the help was asked for. `default_path` is used when no
+// program is given.
+fn parse_args(args: &[String], default_path: Option<&str>) -> Result<Option<Args>, String> {
+    let mut options = Options::new();
+    let mut path = None;
+    let mut timeout = None;
+    let mut target = None;
+    let mut output = None;
+    let mut int_type = "u32".to_string();
+    let mut shortest = false;
+    let mut trace = None;
+    let mut profile = false;
+    let mut folded = None;
+    let mut lcov = None;
+    let mut annotate = None;
+    let mut index = 0;
+    while index < args.len() {
+        match args[index].as_str() {
+            "--verbosity" => {
+                index += 1;
+                options.verbosity = parse_verbosity(args.get(index).map(|a| a.as_str()))?;
+            }
+            "-q" | "--quiet" => options.verbosity = Verbosity::Silent,
+            "--engine" => {
+                index += 1;
+                options.engine = match args.get(index).map(|a| a.as_str()) {
+                    Some("tree") => Engine::Tree,
+                    Some("vm") => Engine::Bytecode,
+                    Some(other) => return Err(format!("Unknown engine {}, expected tree or vm", other)),
+                    None => return Err("Expected an engine after --engine".to_string()),
+                };
+            }
+            "--max-steps" => {
+                index += 1;
+                options.max_steps = Some(parse_number("--max-steps", args.get(index))?);
+            }
+            "--max-depth" => {
+                index += 1;
+                options.max_depth = Some(parse_number("--max-depth", args.get(index))?);
+            }
+            "--no-simplify" => options.simplify = false,
+            "--no-optimize" => options.optimize = false,
+            "--memoize" => options.memoize = true,
+            "--compact-numbers" => options.compact_numbers = true,
+            "--strict" => options.strict = true,
+            "--memo-size" => {
+                index += 1;
+                options.memo_capacity = parse_number("--memo-size", args.get(index))?;
+            }
+            "--timeout" => {
+                index += 1;
+                timeout = Some(Duration::from_millis(parse_number("--timeout", args.get(index))? as u64));
+            }
+            "--target" => {
+                index += 1;
+                target = Some(args.get(index).ok_or("Expected a language after --target")?.clone());
+            }
+            "--int-type" => {
+                index += 1;
+                int_type = args.get(index).ok_or("Expected a type after --int-type")?.clone();
+            }
+            "--shortest" => shortest = true,
+            "--trace" => {
+                index += 1;
+                trace = Some(args.get(index).ok_or("Expected a file after --trace")?.clone());
+            }
+            "--profile" => profile = true,
+            "--lcov" => {
+                index += 1;
+                lcov = Some(args.get(index).ok_or("Expected a file after --lcov")?.clone());
+            }
+            "--annotate" => {
+                index += 1;
+                annotate = Some(args.get(index).ok_or("Expected a file after --annotate")?.clone());
+            }
+            "--folded" => {
+                index += 1;
+                folded = Some(args.get(index).ok_or("Expected a file after --folded")?.clone());
+            }
+            "-o" | "--output" => {
+                index += 1;
+                output = Some(args.get(index).ok_or("Expected a file after --output")?.clone());
+            }
+            "-h" | "--help" => return Ok(None),
+            arg if path.is_none() && (arg == "-" || !arg.starts_with('-')) => path = Some(arg.to_string()),
+            arg => return Err(format!("Unexpected argument {}\n{}", arg, USAGE)),
+        }
+        index += 1;
+    }
+
+    // a run with a timeout is paused between slices, which only session.rs can do
+    if timeout.is_some() && options.engine == Engine::Bytecode {
+        return Err("--timeout runs on the tree engine, it cannot be combined with --engine vm".to_string());
+    }
+    let path = path.or(default_path.map(|path| path.to_string())).ok_or_else(|| USAGE.to_string())?;
+    Ok(Some(Args { options, path, timeout, target, output, int_type, shortest, trace, profile, folded, lcov, annotate }))
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let options = args.options;
+    let code = read_source(&args.path)?;
+    let instrumented = args.trace.is_some() || args.lcov.is_some() || args.annotate.is_some() || args.profile || args.folded.is_some();
+    if instrumented {
+        if options.engine == Engine::Bytecode || args.timeout.is_some() {
+            return Err("--trace, --lcov, --annotate, --profile and --folded run on the tree engine without a timeout, they cannot be combined with --engine vm or --timeout".to_string());
+        }
+        let mut interp = Interpreter::new(options);
+        // events are written as they happen, a failed write is reported at the end
+        let trace_error = Rc::new(RefCell::new(None));
+        if let Some(path) = &args.trace {
+            let file = fs::File::create(path).map_err(|err| format!("Cannot write {}: {}", path, err))?;
+            let (mut file, code, path, trace_error) = (io::LineWriter::new(file), code.clone(), path.clone(), trace_error.clone());
+            interp.trace = Some(Box::new(move |event| {
+                if let Err(err) = writeln!(file, "{}", trace::json(event, &code)) {
+                    trace_error.borrow_mut().get_or_insert(format!("Cannot write {}: {}", path, err));
+                }
+            }));
+        }
+        if args.lcov.is_some() || args.annotate.is_some() {
+            interp.coverage = Some(coverage::Coverage::default());
+        }
+        if args.profile || args.folded.is_some() {
+            interp.profile = Some(profile::Profile::default());
+        }
+        let result = eso_lang::run_instrumented(&code, &mut interp);
+        interp.trace = None;
+        if let Some(err) = trace_error.take() {
+            return Err(err);
+        }
+        let coverage = interp.coverage.take().unwrap_or_default();
+        if let Some(path) = args.lcov {
+            let source = if args.path == "-" { "stdin" } else { args.path.as_str() };
+            write_output(Some(path), coverage.lcov(&code, source).as_bytes())?;
+        }
+        if let Some(path) = args.annotate {
+            write_output(Some(path), coverage.annotate(&code).as_bytes())?;
+        }
+        let profile = interp.profile.take().unwrap_or_default();
+        if args.profile {
+            eprint!("{}", profile.report());
+        }
+        if let Some(path) = args.folded {
+            write_output(Some(path), profile.folded().as_bytes())?;
+        }
+        println!("{}", result?);
+        return Ok(());
+    }
+    let value = match args.timeout {
+        Some(timeout) => run_with_timeout(&code, Interpreter::new(options), timeout)?,
+        None => {
+            let mut interp = Interpreter::new(options);
+            let result = eso_lang::run_program(&code, &mut interp);
+            if options.memoize {
+                let memo = &interp.memo;
+                let stats = format!("Memo: {} hits, {} misses, {} evicted, {} cached\n", memo.hits, memo.misses, memo.evictions, memo.len());
+                interp.diagnose(Verbosity::Warn, stats.as_str());
+            }
+            result?
+        }
+    };
+    println!("{}", value);
+    Ok(())
+}
+
+fn compile(args: Args) -> Result<(), String> {
+    let code = read_source(&args.path)?;
+    let compiled = match args.target.as_deref() {
+        Some("c") => backend::c::compile(&code, &args.options)?.into_bytes(),
+        Some("js") => backend::js::compile(&code, &args.options)?.into_bytes(),
+        Some("wasm") => backend::wasm::compile(&code, &args.options)?,
+        Some("rust") => backend::rust::compile(&code, &args.options, &args.int_type)?.into_bytes(),
+        Some(other) => return Err(format!("Unknown target {}, expected c, js, wasm or rust", other)),
+        None => return Err("Expected --target for compile".to_string()),
+    };
+    write_output(args.output, &compiled)
+}
+
+fn assemble(args: Args) -> Result<(), String> {
+    let source = read_source(&args.path)?;
+    let bracket = assembler::to_bracket(&source, args.shortest, args.options.compact_numbers)?;
+    write_output(args.output, bracket.as_bytes())
+}
+
+// Prints `file:line:col: severity: message` per diagnostic, fails if any is an error.
+fn lint(args: Args) -> Result<(), String> {
+    let code = read_source(&args.path)?;
+    let diagnostics = lint::lint_code(&code, args.options.compact_numbers)?;
+    for diagnostic in &diagnostics {
+        let (line, col) = parse::line_col(&code, diagnostic.span.start);
+        let severity = if diagnostic.severity == lint::Severity::Error { "error" } else { "warning" };
+        println!("{}:{}:{}: {}: {}", args.path, line, col, severity, diagnostic.message);
+    }
+    let errors = diagnostics.iter().filter(|diagnostic| diagnostic.severity == lint::Severity::Error).count();
+    match errors {
+        0 => Ok(()),
+        _ => Err(format!("{} errors, {} warnings", errors, diagnostics.len() - errors)),
+    }
+}
+
+// Runs every .bkt file in a directory (and the ones below it) or a single
+// file, checking it against its expectations (see testing.rs).
+fn test(args: Args) -> Result<(), String> {
+    let mut paths = Vec::new();
+    find_programs(Path::new(&args.path), &mut paths)?;
+    let mut failed = 0;
+    for path in &paths {
+        let code = fs::read_to_string(path).map_err(|err| format!("Cannot read {}: {}", path.display(), err))?;
+        let mut expectations: Vec<_> = match testing::expectations(&code) {
+            Ok(expectations) => expectations.into_iter().map(|(span, expectation)| (Some(span), expectation)).collect(),
+            Err(err) => {
+                failed += 1;
+                println!("FAILED  {}", path.display());
+                println!("    {}", err);
+                continue;
+            }
+        };
+        let sidecar = path.with_extension("out");
+        if sidecar.is_file() {
+            let stdout = fs::read_to_string(&sidecar).map_err(|err| format!("Cannot read {}: {}", sidecar.display(), err))?;
+            expectations.push((None, testing::Expectation::Stdout(stdout)));
+        }
+        let outcome = match args.timeout {
+            Some(timeout) => {
+                let (interp, output) = testing::capture(args.options);
+                let result = run_with_timeout(&code, interp, timeout);
+                let output = output.borrow().clone();
+                testing::Outcome { output, result }
+            }
+            None => testing::run(&code, args.options),
+        };
+        let failures = testing::failures(&code, &expectations, &outcome);
+        if failures.is_empty() {
+            println!("ok      {}", path.display());
+            continue;
+        }
+        failed += 1;
+        println!("FAILED  {}", path.display());
+        for failure in failures {
+            println!("    {}", failure.trim_end().replace('\n', "\n    "));
+        }
+    }
+    println!("{} passed, {} failed", paths.len() - failed, failed);
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!("{} of {} tests failed", failed, paths.len())),
+    }
+}
+
+// .bkt files in name order, hidden directories and target are skipped.
+fn find_programs(path: &Path, programs: &mut Vec<std::path::PathBuf>) -> Result<(), String> {
+    if !path.is_dir() {
+        if !path.exists() {
+            return Err(format!("Cannot read {}: no such file or directory", path.display()));
+        }
+        programs.push(path.to_path_buf());
+        return Ok(());
+    }
+    let entries = fs::read_dir(path).map_err(|err| format!("Cannot read {}: {}", path.display(), err))?;
+    let mut entries: Vec<_> = entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect();
+    entries.sort();
+    for entry in entries {
+        let name = entry.file_name().map_or(String::new(), |name| name.to_string_lossy().to_string());
+        if entry.is_dir() && !name.starts_with('.') && name != "target" && name != "node_modules" {
+            find_programs(&entry, programs)?;
+        } else if entry.is_file() && name.ends_with(".bkt") {
+            programs.push(entry);
+        }
+    }
+    Ok(())
+}
+
+const DEBUG_HELP: &str = "Commands:
+    s, step                  run to the next expression, entering calls
+    n, next                  run to the next expression after this one
+    f, finish                run until the current function returns
+    c, continue              run to the next breakpoint
+    b, break <name|line[:col]>  stop when a function is entered or at a place in the program
+    d, delete                remove all breakpoints
+    v, vars                  variables of the current frame
+    bt, stack                running functions, innermost first
+    l, where                 show where the program is paused
+    q, quit                  stop debugging";
+
+fn debug(args: Args) -> Result<(), String> {
+    if args.path == "-" {
+        return Err("The debugger reads its commands from stdin, the program has to be a file".to_string());
+    }
+    let code = read_source(&args.path)?;
+    let mut debugger = Debugger::with_interpreter(code, Interpreter::new(args.options));
+    let mut lines = io::stdin().lines();
+    loop {
+        while !debugger.run(u32::MAX) {}
+        match debugger.status() {
+            Status::Finished(val) => {
+                println!("{}", val);
+                return Ok(());
+            }
+            Status::Failed(err) => return Err(err.clone()),
+            Status::Running => show_location(&debugger),
+        }
+        loop {
+            print!("(bracket) ");
+            io::stdout().flush().map_err(|err| format!("Cannot write the output: {}", err))?;
+            let Some(line) = lines.next() else { return Ok(()) };
+            let line = line.map_err(|err| format!("Cannot read stdin: {}", err))?;
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("s" | "step"), _) => debugger.step_into(),
+                (Some("n" | "next"), _) => debugger.step_over(),
+                (Some("f" | "finish"), _) => debugger.step_out(),
+                (Some("c" | "continue"), _) => debugger.resume(),
+                (Some("b" | "break"), Some(place)) => {
+                    match parse_breakpoint(&debugger, place) {
+                        Ok(breakpoint) => debugger.add_breakpoint(breakpoint),
+                        Err(err) => println!("{}", err),
+                    }
+                    continue;
+                }
+                (Some("d" | "delete"), _) => {
+                    debugger.clear_breakpoints();
+                    continue;
+                }
+                (Some("v" | "vars"), _) => {
+                    for (var, val) in debugger.frame() {
+                        println!("{} = {}", var, val);
+                    }
+                    continue;
+                }
+                (Some("bt" | "stack"), _) => {
+                    for name in debugger.call_stack().iter().rev() {
+                        println!("{}", name);
+                    }
+                    println!("<top level>");
+                    continue;
+                }
+                (Some("l" | "where"), _) => {
+                    show_location(&debugger);
+                    continue;
+                }
+                (Some("q" | "quit"), _) => return Ok(()),
+                (None, _) => continue,
+                _ => {
+                    println!("{}", DEBUG_HELP);
+                    continue;
+                }
+            }
+            break;
+        }
+    }
+}
+
+// `(()())` for a function, `12` for a line or `12:5` for the expression
+// starting at that column.
+fn parse_breakpoint(debugger: &Debugger, place: &str) -> Result<Breakpoint, String> {
+    if place.starts_with('(') {
+        return Ok(Breakpoint::Function(parse::Var(place.to_string())));
+    }
+    let invalid = || format!("Expected a function name, a line or line:column, got {}", place);
+    let (line, col) = match place.split_once(':') {
+        Some((line, col)) => (line, Some(col.parse::<usize>().map_err(|_| invalid())?)),
+        None => (place, None),
+    };
+    let line: usize = line.parse().map_err(|_| invalid())?;
+    let span = debugger.line_span(line);
+    match col {
+        Some(col) => {
+            let start = debugger.code()[span.start..span.end].char_indices().nth(col.saturating_sub(1)).map(|(i, _)| span.start + i);
+            let start = start.ok_or_else(|| format!("Line {} has no column {}", line, col))?;
+            Ok(Breakpoint::Span(parse::Span { start, end: start + 1 }))
+        }
+        None => Ok(Breakpoint::Span(span)),
+    }
+}
+
+// The line of the expression the debugger is paused at, with the expression
+// underlined as far as it goes on that line.
+fn show_location(debugger: &Debugger) {
+    let Some(span) = debugger.span() else { return };
+    let code = debugger.code();
+    let (line, col) = parse::line_col(code, span.start);
+    let text = code.split('\n').nth(line - 1).unwrap_or("");
+    let width = code[span.start..span.end].split('\n').next().unwrap_or("").chars().count().max(1);
+    let function = debugger.call_stack().last().cloned().unwrap_or_else(|| "<top level>".to_string());
+    println!("Paused at {}:{} in {}", line, col, function);
+    println!("{:4} | {}", line, text);
+    println!("     | {}{}", " ".repeat(col - 1), "^".repeat(width));
+}
+
+fn write_output(path: Option<String>, content: &[u8]) -> Result<(), String> {
+    match path {
+        Some(path) => fs::write(&path, content).map_err(|err| format!("Cannot write {}: {}", path, err)),
+        None => io::stdout().write_all(content).map_err(|err| format!("Cannot write the output: {}", err)),
+    }
+}
+
+fn run_with_timeout(code: &str, interp: Interpreter, timeout: Duration) -> Result<u32, ProgramError> {
+    let start = Instant::now();
+    let mut session = Session::with_interpreter(code, interp);
+    loop {
+        match session.run(SLICE).clone() {
+            Status::Running if start.elapsed() >= timeout => {
+                return Err(ProgramError::Other(format!("Timed out after {} ms", timeout.as_millis())));
+            }
+            Status::Running => {}
+            Status::Finished(val) => return Ok(val),
+            Status::Failed(err) => return Err(session.error().cloned().map_or(ProgramError::Other(err), ProgramError::Runtime)),
+        }
+    }
+}
+
+fn parse_verbosity(level: Option<&str>) -> Result<Verbosity, String> {
+    match level {
+        Some("silent") => Ok(Verbosity::Silent),
+        Some("warn") => Ok(Verbosity::Warn),
+        Some("trace") => Ok(Verbosity::Trace),
+        Some(other) => Err(format!("Unknown verbosity {}, expected silent, warn or trace", other)),
+        None => Err("Expected a verbosity after --verbosity".to_string()),
+    }
+}
+
+fn parse_number(flag: &str, value: Option<&String>) -> Result<u32, String> {
+    let value = value.ok_or_else(|| format!("Expected a number after {}", flag))?;
+    value.parse().map_err(|_| format!("Expected a number after {}, got {}", flag, value))
+}
+
+fn read_source(path: &str) -> Result<String, String> {
+    if path == "-" {
+        let mut code = String::new();
+        std::io::stdin().read_to_string(&mut code).map_err(|err| format!("Cannot read stdin: {}", err))?;
+        return Ok(code);
+    }
+    fs::read_to_string(path).map_err(|err| format!("Cannot read {}: {}", path, err))
+}
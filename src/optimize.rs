@@ -0,0 +1,194 @@
+use crate::parse::{Arith, Expression, Var};
+use std::collections::HashMap;
+
+// Recognises the usual unary-recursion definitions of arithmetic and replaces
+// their bodies with native Expression::Arith nodes:
+//
+//   add(x, y)      = y ? add(&x, *y) : x           or  y ? &add(x, *y) : x
+//   sub(x, y)      = y ? sub(*x, *y) : x           or  y ? *sub(x, *y) : x
+//   mul(x, y, acc) = y ? mul(x, *y, add(acc, x)) : acc
+//
+// Only the bodies change, calls are left alone, so arguments (and any prints
+// in them) are still evaluated exactly as before. A function is only rewritten
+// when its name is defined once in the whole program, so the recursive call
+// cannot reach a different definition. The rewritten functions take fewer
+// steps and calls, which is visible through --max-steps, --max-depth and
+// trace diagnostics but nothing else.
+
+pub fn optimize(statements: &mut [Expression]) {
+    let mut definitions = HashMap::new();
+    for exp in statements.iter() {
+        count_definitions(exp, &mut definitions);
+    }
+    let mut adders = HashMap::new();
+    for (index, exp) in statements.iter().enumerate() {
+        if let Expression::FunctionDef(var, args, body) = exp
+            && definitions.get(var) == Some(&1)
+            && let Some(Expression::Arith(Arith::Add, ..)) = recognize(var, args, body, &HashMap::new())
+        {
+            adders.insert(var.clone(), index);
+        }
+    }
+    for (index, exp) in statements.iter_mut().enumerate() {
+        // an adder helps a multiplication only if it is surely defined before it runs
+        let defined_before: HashMap<Var, usize> = adders.iter().filter(|(_, i)| **i < index).map(|(var, i)| (var.clone(), *i)).collect();
+        rewrite(exp, &definitions, &defined_before);
+    }
+}
+
+fn count_definitions(exp: &Expression, definitions: &mut HashMap<Var, usize>) {
+    match exp {
+        Expression::Val(_) => {}
+        Expression::If(cond, then_exp, else_exp) => {
+            count_definitions(cond, definitions);
+            count_definitions(then_exp, definitions);
+            count_definitions(else_exp, definitions);
+        }
+        Expression::FunctionDef(var, args, body) => {
+            if !args.is_empty() {
+                *definitions.entry(var.clone()).or_insert(0) += 1;
+            }
+            count_definitions(body, definitions);
+        }
+        Expression::FunctionCall(_, args) => count_definitions(args, definitions),
+        Expression::ListExp(list) => list.iter().for_each(|item| count_definitions(item, definitions)),
+        Expression::TryCatch(try_block, _, catch_block) => {
+            count_definitions(try_block, definitions);
+            count_definitions(catch_block, definitions);
+        }
+        Expression::Throw(exp) | Expression::Assert(exp, _) | Expression::PrintNum(exp) | Expression::PrintChar(exp) => count_definitions(exp, definitions),
+        Expression::Arith(_, x, y) => {
+            count_definitions(x, definitions);
+            count_definitions(y, definitions);
+        }
+        Expression::At(_, exp) => count_definitions(exp, definitions),
+    }
+}
+
+fn rewrite(exp: &mut Expression, definitions: &HashMap<Var, usize>, adders: &HashMap<Var, usize>) {
+    match exp {
+        Expression::Val(_) => {}
+        Expression::If(cond, then_exp, else_exp) => {
+            rewrite(cond, definitions, adders);
+            rewrite(then_exp, definitions, adders);
+            rewrite(else_exp, definitions, adders);
+        }
+        Expression::FunctionDef(var, args, body) => {
+            if definitions.get(var) == Some(&1) && let Some(native) = recognize(var, args, body, adders) {
+                **body = Expression::ListExp(vec![native]);
+            } else {
+                rewrite(body, definitions, adders);
+            }
+        }
+        Expression::FunctionCall(_, args) => rewrite(args, definitions, adders),
+        Expression::ListExp(list) => list.iter_mut().for_each(|item| rewrite(item, definitions, adders)),
+        Expression::TryCatch(try_block, _, catch_block) => {
+            rewrite(try_block, definitions, adders);
+            rewrite(catch_block, definitions, adders);
+        }
+        Expression::Throw(exp) | Expression::Assert(exp, _) | Expression::PrintNum(exp) | Expression::PrintChar(exp) => rewrite(exp, definitions, adders),
+        Expression::Arith(_, x, y) => {
+            rewrite(x, definitions, adders);
+            rewrite(y, definitions, adders);
+        }
+        Expression::At(_, exp) => rewrite(exp, definitions, adders),
+    }
+}
+
+// Native body equivalent to the definition of `f`, if it has one of the shapes above.
+fn recognize(f: &Var, args: &[Var], body: &Expression, adders: &HashMap<Var, usize>) -> Option<Expression> {
+    let Expression::If(cond, then_exp, else_exp) = strip(body) else {
+        return None;
+    };
+    match args {
+        [x, y] if x != y && read(cond)? == y && read(else_exp)? == x => {
+            if let Some([a, b]) = call(then_exp, f) && op(b, "*").and_then(read) == Some(y) {
+                if op(a, "&").and_then(read) == Some(x) {
+                    return Some(native(Arith::Add, x, y));
+                }
+                if op(a, "*").and_then(read) == Some(x) {
+                    return Some(native(Arith::Sub, x, y));
+                }
+            }
+            for (outer, arith) in [("&", Arith::Add), ("*", Arith::Sub)] {
+                if let Some([a, b]) = op(then_exp, outer).and_then(|inner| call(inner, f))
+                    && read(a) == Some(x)
+                    && op(b, "*").and_then(read) == Some(y)
+                {
+                    return Some(native(arith, x, y));
+                }
+            }
+            None
+        }
+
+        [x, y, acc] if x != y && x != acc && y != acc && read(cond)? == y && read(else_exp)? == acc => {
+            let [a, b, c] = call(then_exp, f)? else {
+                return None;
+            };
+            if read(a)? != x || op(b, "*").and_then(read)? != y {
+                return None;
+            }
+            let Expression::FunctionCall(g, params) = strip(c) else {
+                return None;
+            };
+            let Expression::ListExp(params) = params.as_ref() else {
+                return None;
+            };
+            let summands = [read(params.first()?)?, read(params.get(1)?)?];
+            if !adders.contains_key(g) || params.len() != 2 || (summands != [acc, x] && summands != [x, acc]) {
+                return None;
+            }
+            Some(Expression::Arith(Arith::Add, Box::new(get(acc)), Box::new(native(Arith::Mul, x, y))))
+        }
+
+        _ => None,
+    }
+}
+
+fn native(arith: Arith, x: &Var, y: &Var) -> Expression {
+    Expression::Arith(arith, Box::new(get(x)), Box::new(get(y)))
+}
+
+fn get(var: &Var) -> Expression {
+    Expression::FunctionCall(var.clone(), Box::new(Expression::ListExp(vec![])))
+}
+
+// Looks through groups of a single expression, `[[x]]` behaves exactly like `x`,
+// and through positions.
+fn strip(exp: &Expression) -> &Expression {
+    match exp {
+        Expression::ListExp(list) if list.len() == 1 => strip(&list[0]),
+        Expression::At(_, exp) => strip(exp),
+        _ => exp,
+    }
+}
+
+// `{x}[]`
+fn read(exp: &Expression) -> Option<&Var> {
+    match strip(exp) {
+        Expression::FunctionCall(var, args) if var.0 != "*" && var.0 != "&" && **args == Expression::ListExp(vec![]) => Some(var),
+        _ => None,
+    }
+}
+
+// `&x` or `*x`, returns x
+fn op<'a>(exp: &'a Expression, operator: &str) -> Option<&'a Expression> {
+    match strip(exp) {
+        Expression::FunctionCall(var, args) if var.0 == operator => match args.as_ref() {
+            Expression::ListExp(list) if list.len() == 1 => Some(&list[0]),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// `{f}[...]`, returns the arguments
+fn call<'a>(exp: &'a Expression, f: &Var) -> Option<&'a [Expression]> {
+    match strip(exp) {
+        Expression::FunctionCall(var, args) if var == f => match args.as_ref() {
+            Expression::ListExp(list) if !list.is_empty() => Some(list),
+            _ => None,
+        },
+        _ => None,
+    }
+}
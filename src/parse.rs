@@ -35,7 +35,7 @@ impl fmt::Display for Token {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
 pub struct Var(pub String);
 impl fmt::Display for Var {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -50,9 +50,60 @@ pub enum Expression {
     FunctionDef(Var, Vec<Var>, Box<Expression>), // define function x with y as argument and z as body
     FunctionCall(Var, Box<Expression>), // call function x with y as argument
     ListExp(Vec<Expression>), // List of expressions
-    TryCatch(Box<Expression>, Box<Expression>), // try block and catch block
+    TryCatch(Box<Expression>, Option<Var>, Box<Expression>), // try block, optional error code binding and catch block
+    Throw(Box<Expression>), // raise a user error with the given code
+    Assert(Box<Expression>, Option<(Span, String)>), // raise an assertion error if the value is 0, with where and what (`line:col: source`) was asserted when known
     PrintNum(Box<Expression>), // Print val 
     PrintChar(Box<Expression>), // Print char
+    Arith(Arith, Box<Expression>, Box<Expression>), // native arithmetic, only produced by optimize.rs
+    At(Span, Box<Expression>), // where the expression is in the source, only produced by parse_spanned
+}
+
+// Byte offsets into the source, `end` is exclusive.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn contains(&self, offset: usize) -> bool {
+        self.start <= offset && offset < self.end
+    }
+}
+
+// 1-based line and column of a byte offset.
+pub fn line_col(code: &str, offset: usize) -> (usize, usize) {
+    let before = &code[..offset.min(code.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arith {
+    Add, // saturating like repeated &
+    Sub, // fails like repeated * when the result would be negative
+    Mul, // saturating like repeated additions
+}
+
+impl fmt::Display for Arith {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arith::Add => write!(f, "add"),
+            Arith::Sub => write!(f, "sub"),
+            Arith::Mul => write!(f, "mul"),
+        }
+    }
+}
+
+impl Arith {
+    pub fn apply(&self, x: u32, y: u32) -> Option<u32> {
+        match self {
+            Arith::Add => Some(x.saturating_add(y)),
+            Arith::Sub => x.checked_sub(y),
+            Arith::Mul => Some(x.saturating_mul(y)),
+        }
+    }
 }
 
 impl fmt::Display for Expression {
@@ -78,12 +129,20 @@ impl fmt::Display for Expression {
             }
             Expression::ListExp(exprs) => write!(f, "{}", exprs.iter().map(|e| e.to_string()).collect::<Vec<String>>().join(";\n")),
             
-            Expression::TryCatch(try_block, catch_block) => {
-                write!(f, "try {{\n{}\n}} catch {{\n{}\n}}", try_block, catch_block)
+            Expression::TryCatch(try_block, binding, catch_block) => {
+                match binding {
+                    Some(var) => write!(f, "try {{\n{}\n}} catch ({}) {{\n{}\n}}", try_block, var.0, catch_block),
+                    None => write!(f, "try {{\n{}\n}} catch {{\n{}\n}}", try_block, catch_block),
+                }
             }
 
+            Expression::Throw(expr) => write!(f, "throw({})", expr),
+            Expression::Assert(expr, _) => write!(f, "assert({})", expr),
+
             Expression::PrintNum(expr) => write!(f, "print_num({})", expr),
             Expression::PrintChar(expr) => write!(f, "print_char({})", expr),
+            Expression::Arith(op, x, y) => write!(f, "{}({}, {})", op, x, y),
+            Expression::At(_, exp) => write!(f, "{}", exp),
 
 
         }
@@ -91,40 +150,110 @@ impl fmt::Display for Expression {
 }
 
 pub fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    Ok(tokenize_with_offsets(input)?.0)
+}
+
+// Also returns the byte offset of every token in `input`.
+pub fn tokenize_with_offsets(input: &str) -> Result<(Vec<Token>, Vec<usize>), String> {
     let mut tokens = Vec::new();
-    let mut index = 0;
+    let mut offsets = Vec::new();
     let mut in_comment = false;
-    for c in input.chars() {
-        match c {
-            '/' => in_comment = !in_comment,
+    for (index, (offset, c)) in input.char_indices().enumerate() {
+        let token = match c {
+            '/' => {
+                in_comment = !in_comment;
+                continue;
+            }
             _ if in_comment => continue, // Skip characters inside comments
-            '(' => tokens.push(Token::OpenParen),
-            ')' => tokens.push(Token::CloseParen),
-            '[' => tokens.push(Token::OpenSquare),
-            ']' => tokens.push(Token::CloseSquare),
-            '{' => tokens.push(Token::OpenCurly),
-            '}' => tokens.push(Token::CloseCurly),
-            '|' => tokens.push(Token::Pipe),
-            '*' => tokens.push(Token::Dec),
-            '&' => tokens.push(Token::Inc),
-            '<' => tokens.push(Token::OpenAngle),
-            '>' => tokens.push(Token::CloseAngle),
+            '(' => Token::OpenParen,
+            ')' => Token::CloseParen,
+            '[' => Token::OpenSquare,
+            ']' => Token::CloseSquare,
+            '{' => Token::OpenCurly,
+            '}' => Token::CloseCurly,
+            '|' => Token::Pipe,
+            '*' => Token::Dec,
+            '&' => Token::Inc,
+            '<' => Token::OpenAngle,
+            '>' => Token::CloseAngle,
             _ => {
                 if !c.is_whitespace() {
                     return Err(format!("Unexpected character: {} at {}", c, index));
                 }
+                continue;
             }
-        }
-        index += 1;
+        };
+        tokens.push(token);
+        offsets.push(offset);
     }
-    Ok(tokens)
+    Ok((tokens, offsets))
 }
 
 // pub fn parse(tokens: Vec<Token>) -> Result<Expression, String> {
     
 // }
 
-pub fn get_next_expression(tokens: &Vec<Token>, start: usize) -> Result<(Expression, usize), String> {
+// Parses every top level statement up to the first parse error. The error is
+// returned next to the statements so callers can still run the statements
+// before it, like the one-statement-at-a-time loop in run_program does.
+pub fn parse_statements(tokens: &[Token], compact: bool) -> (Vec<Expression>, Option<String>) {
+    parse_with(tokens, Syntax { compact, source: None, spans: false })
+}
+
+// Like tokenize and parse_statements, but every expression is wrapped in an
+// Expression::At with its place in `code`, for tools that point at the source.
+pub fn parse_spanned(code: &str, compact: bool) -> Result<(Vec<Expression>, Option<String>), String> {
+    let (tokens, offsets) = tokenize_with_offsets(code)?;
+    Ok(parse_with(&tokens, Syntax { compact, source: Some((code, &offsets)), spans: true }))
+}
+
+// Like tokenize and parse_statements, but assertions know where they are.
+pub fn parse_program(code: &str, compact: bool) -> Result<(Vec<Expression>, Option<String>), String> {
+    let (tokens, offsets) = tokenize_with_offsets(code)?;
+    Ok(parse_with(&tokens, Syntax { compact, source: Some((code, &offsets)), spans: false }))
+}
+
+fn parse_with(tokens: &[Token], syntax: Syntax) -> (Vec<Expression>, Option<String>) {
+    let mut statements = Vec::new();
+    let mut index = 0;
+    while index < tokens.len() {
+        match get_next_expression(tokens, index, syntax) {
+            Ok((exp, end)) => {
+                statements.push(exp);
+                index = end;
+            }
+            Err(err) => return (statements, Some(err)),
+        }
+    }
+    (statements, None)
+}
+
+// How tokens are read: with `compact`, number literals may use the compact
+// notation (see get_num). `source` is the code with the offset of every token
+// in it, with `spans` expressions are wrapped in Expression::At.
+#[derive(Clone, Copy)]
+pub struct Syntax<'a> {
+    pub compact: bool,
+    pub source: Option<(&'a str, &'a [usize])>,
+    pub spans: bool,
+}
+
+impl Syntax<'_> {
+    // tokens are single characters
+    fn span(&self, start: usize, end: usize) -> Option<Span> {
+        self.source.map(|(_, offsets)| Span { start: offsets[start], end: offsets[end - 1] + 1 })
+    }
+}
+
+pub fn get_next_expression(tokens: &[Token], start: usize, syntax: Syntax) -> Result<(Expression, usize), String> {
+    let (exp, end) = get_expression(tokens, start, syntax)?;
+    match syntax.span(start, end) {
+        Some(span) if syntax.spans => Ok((Expression::At(span, Box::new(exp)), end)),
+        _ => Ok((exp, end)),
+    }
+}
+
+fn get_expression(tokens: &[Token], start: usize, syntax: Syntax) -> Result<(Expression, usize), String> {
     if start >= tokens.len() {
         return Err("No more tokens to parse".to_string());
     }
@@ -150,12 +279,12 @@ pub fn get_next_expression(tokens: &Vec<Token>, start: usize) -> Result<(Express
                     return Err(format!("Expected [ after arg list at {}", index));
                 }
                 
-                let (body, end) = get_expression_list(tokens, index)?;
+                let (body, end) = get_expression_list(tokens, index, syntax)?;
                 index = end;
                 
                 return Ok((Expression::FunctionDef(Var(var), args, Box::new(Expression::ListExp(body))), index));
             } else if tokens[index] == Token::OpenSquare {
-                let (body, end) = get_expression_list(tokens, index)?;
+                let (body, end) = get_expression_list(tokens, index, syntax)?;
                 index = end;
                 
                 return Ok((Expression::FunctionCall(Var(var), Box::new(Expression::ListExp(body))), index));
@@ -169,7 +298,7 @@ pub fn get_next_expression(tokens: &Vec<Token>, start: usize) -> Result<(Express
                 return Err("Unexpected end of tokens after [".to_string());
             }
             if tokens[index+1] == Token::OpenParen {
-                let (num, end) = get_num(tokens, index)?;
+                let (num, end) = get_num(tokens, index, syntax)?;
                 index = end;
                 return Ok((Expression::Val(num), index));
             } else if tokens[index+1] == Token::CloseSquare {
@@ -177,7 +306,7 @@ pub fn get_next_expression(tokens: &Vec<Token>, start: usize) -> Result<(Express
                 return Ok((Expression::Val(0), index));
             }
             
-            let (condition, end) = get_expression_list(tokens, index)?;
+            let (condition, end) = get_expression_list(tokens, index, syntax)?;
             index = end;
 
             if index == tokens.len() || tokens[index] != Token::Pipe {
@@ -193,7 +322,7 @@ pub fn get_next_expression(tokens: &Vec<Token>, start: usize) -> Result<(Express
             if index >= tokens.len() {
                 return Err("Unexpected end of tokens while trying to find then branch of conditional".to_string());
             } 
-            let (then_branch, end) = get_expression_list(tokens, index)?;
+            let (then_branch, end) = get_expression_list(tokens, index, syntax)?;
             index = end;
 
             if index >= tokens.len() || tokens[index] != Token::Pipe {
@@ -207,7 +336,7 @@ pub fn get_next_expression(tokens: &Vec<Token>, start: usize) -> Result<(Express
                 return Err("Unexpected end of tokens while trying to find else branch of conditional".to_string());
             }
 
-            let (else_branch, end) = get_expression_list(tokens, index)?;
+            let (else_branch, end) = get_expression_list(tokens, index, syntax)?;
             index = end;
 
             return Ok((Expression::If(Box::new(Expression::ListExp(condition)), 
@@ -220,7 +349,7 @@ pub fn get_next_expression(tokens: &Vec<Token>, start: usize) -> Result<(Express
             if index >= tokens.len() {
                 return Err("Unexpected end of tokens after decrement operator".to_string());
             }
-            let (body, end) = get_next_expression(tokens, index)?;
+            let (body, end) = get_next_expression(tokens, index, syntax)?;
             index = end;
             let body = Expression::ListExp(vec![body]);
             return Ok((Expression::FunctionCall(Var('*'.to_string()), Box::new(body)), index));
@@ -231,7 +360,7 @@ pub fn get_next_expression(tokens: &Vec<Token>, start: usize) -> Result<(Express
             if index >= tokens.len() {
                 return Err("Unexpected end of tokens after increment operator".to_string());
             }
-            let (body, end) = get_next_expression(tokens, index)?;
+            let (body, end) = get_next_expression(tokens, index, syntax)?;
             index = end;
             let body = Expression::ListExp(vec![body]);
             return Ok((Expression::FunctionCall(Var('&'.to_string()), Box::new(body)), index));
@@ -242,7 +371,34 @@ pub fn get_next_expression(tokens: &Vec<Token>, start: usize) -> Result<(Express
             if index >= tokens.len() {
                 return Err("Unexpected end of tokens after < ".to_string());
             }
-            let (try_block, end) = get_next_expression(tokens, index)?;
+            if tokens[index] == Token::Pipe {
+                index += 1;
+                if index >= tokens.len() {
+                    return Err("Unexpected end of tokens after <|".to_string());
+                }
+                if tokens[index] == Token::Pipe {
+                    index += 1;
+                    if index >= tokens.len() {
+                        return Err("Unexpected end of tokens after <||".to_string());
+                    }
+                    let (exp, end) = get_next_expression(tokens, index, syntax)?;
+                    if end >= tokens.len() || tokens[end] != Token::CloseAngle {
+                        return Err(format!("Expected > at end of assertion at {}", end));
+                    }
+                    let asserted = syntax.span(index, end).zip(syntax.source).map(|(span, (code, _))| {
+                        let (line, col) = line_col(code, span.start);
+                        (span, format!("{}:{}: {}", line, col, code[span.start..span.end].split_whitespace().collect::<Vec<&str>>().join(" ")))
+                    });
+                    return Ok((Expression::Assert(Box::new(exp), asserted), end + 1));
+                }
+                let (code, end) = get_next_expression(tokens, index, syntax)?;
+                index = end;
+                if index >= tokens.len() || tokens[index] != Token::CloseAngle {
+                    return Err(format!("Expected > at end of throw at {}", index));
+                }
+                return Ok((Expression::Throw(Box::new(code)), index + 1));
+            }
+            let (try_block, end) = get_next_expression(tokens, index, syntax)?;
             index = end;
             if index >= tokens.len() {
                 return Err("Unxpected end of tokens".to_string());
@@ -256,12 +412,25 @@ pub fn get_next_expression(tokens: &Vec<Token>, start: usize) -> Result<(Express
                     index += 1;
                     return Ok((Expression::PrintChar(Box::new(try_block)), index));
                 }
-                let (catch_block, end) = get_next_expression(tokens, index)?;
+                let mut binding = None;
+                if tokens[index] == Token::OpenParen {
+                    let (var, end) = get_var_name(tokens, index)?;
+                    index = end;
+                    if index >= tokens.len() || tokens[index] != Token::Pipe {
+                        return Err(format!("Expected | after error binding at {}", index));
+                    }
+                    index += 1;
+                    if index >= tokens.len() {
+                        return Err("Unexpected end of tokens after error binding".to_string());
+                    }
+                    binding = Some(Var(var));
+                }
+                let (catch_block, end) = get_next_expression(tokens, index, syntax)?;
                 index = end;
                 if index >= tokens.len() || tokens[index] != Token::CloseAngle {
                     return Err(format!("Expected > at end of try-catch block at {}", index));
                 }
-                return Ok((Expression::TryCatch(Box::new(try_block), Box::new(catch_block)), index + 1));
+                return Ok((Expression::TryCatch(Box::new(try_block), binding, Box::new(catch_block)), index + 1));
             } else if tokens[index] == Token::CloseAngle {
                 index += 1;
                 return Ok((Expression::PrintNum(Box::new(try_block)), index));
@@ -275,7 +444,7 @@ pub fn get_next_expression(tokens: &Vec<Token>, start: usize) -> Result<(Express
     }
 }
 
-fn get_expression_list(tokens: &Vec<Token>, start: usize) -> Result<(Vec<Expression>, usize), String> {
+fn get_expression_list(tokens: &[Token], start: usize, syntax: Syntax) -> Result<(Vec<Expression>, usize), String> {
     let mut index = start;
     if index >= tokens.len() {
         return Err("Unexpected end of tokens while trying to find expression list".to_string());
@@ -296,7 +465,7 @@ fn get_expression_list(tokens: &Vec<Token>, start: usize) -> Result<(Vec<Express
                 break;
             }
             _ => {
-                let (expr, end) = get_next_expression(tokens, index)?;
+                let (expr, end) = get_next_expression(tokens, index, syntax)?;
                 expressions.push(expr);
                 index = end;
                 if index >= tokens.len() {
@@ -309,7 +478,7 @@ fn get_expression_list(tokens: &Vec<Token>, start: usize) -> Result<(Vec<Express
     Ok((expressions, index))
 }
 
-fn get_var_name(tokens: &Vec<Token>, start: usize) -> Result<(String, usize), String> {
+fn get_var_name(tokens: &[Token], start: usize) -> Result<(String, usize), String> {
     if start >= tokens.len() {
         return Err("Unexpected end of token while trying to find variable name".to_string());
     }
@@ -344,7 +513,7 @@ fn get_var_name(tokens: &Vec<Token>, start: usize) -> Result<(String, usize), St
     Ok((var_name, index))
 }
 
-fn get_var_list(tokens: &Vec<Token>, start: usize) -> Result<(Vec<Var>, usize), String> {
+fn get_var_list(tokens: &[Token], start: usize) -> Result<(Vec<Var>, usize), String> {
     if start >= tokens.len() {
         return Err("Unexpected end of token while trying to find variable list".to_string());
     }
@@ -372,7 +541,10 @@ fn get_var_list(tokens: &Vec<Token>, start: usize) -> Result<(Vec<Var>, usize),
     Ok((vars, index))
 }
 
-fn get_num(tokens: &Vec<Token>, start: usize) -> Result<(u32, usize), String> {
+// A number is a list of groups: `()` counts 1, so `[()()()]` is 3. In the
+// compact notation a group may contain groups and counts twice their sum:
+// `(())` is 2, `((()))` is 4 and `[((((((())))))) ()]` is 65.
+fn get_num(tokens: &[Token], start: usize, syntax: Syntax) -> Result<(u32, usize), String> {
     if start >= tokens.len()-1 {
         return Err("Unexpected end of token while trying to find number".to_string());
     }
@@ -380,7 +552,7 @@ fn get_num(tokens: &Vec<Token>, start: usize) -> Result<(u32, usize), String> {
         return Err(format!("Expected [( at start of number at {}", start));
     }
     let mut index = start + 1;
-    let mut num = 0;
+    let mut num: u32 = 0;
 
     loop {
         if index >= tokens.len() {
@@ -388,11 +560,9 @@ fn get_num(tokens: &Vec<Token>, start: usize) -> Result<(u32, usize), String> {
         }
         match &tokens[index] {
             Token::OpenParen => {
-                index += 1;
-                if tokens[index] != Token::CloseParen {
-                    return Err(format!("Expected ) after ( in number at {}", index));
-                }
-                num += 1;
+                let (group, end) = get_num_group(tokens, index, syntax)?;
+                num = num.checked_add(group).ok_or_else(|| format!("Number too large at {}", start))?;
+                index = end;
             }
             Token::CloseSquare => {
                 index+=1;
@@ -400,8 +570,34 @@ fn get_num(tokens: &Vec<Token>, start: usize) -> Result<(u32, usize), String> {
             }
             _ => return Err(format!("Unexpected token {} in number at {}", tokens[index], index)),
         }
-        index += 1;
     }
 
     Ok((num, index))
-}
\ No newline at end of file
+}
+
+fn get_num_group(tokens: &[Token], start: usize, syntax: Syntax) -> Result<(u32, usize), String> {
+    let mut index = start + 1;
+    if index < tokens.len() && tokens[index] == Token::CloseParen {
+        return Ok((1, index + 1));
+    }
+    if !syntax.compact {
+        return Err(format!("Expected ) after ( in number at {}", index));
+    }
+    let mut sum: u32 = 0;
+    loop {
+        if index >= tokens.len() {
+            return Err(format!("Unexpected end of tokens while parsing number at {}", index));
+        }
+        match &tokens[index] {
+            Token::OpenParen => {
+                let (group, end) = get_num_group(tokens, index, syntax)?;
+                sum = sum.checked_add(group).ok_or_else(|| format!("Number too large at {}", start))?;
+                index = end;
+            }
+            Token::CloseParen => break,
+            _ => return Err(format!("Unexpected token {} in number at {}", tokens[index], index)),
+        }
+    }
+    let num = sum.checked_mul(2).ok_or_else(|| format!("Number too large at {}", start))?;
+    Ok((num, index + 1))
+}
@@ -0,0 +1,173 @@
+use crate::parse::Expression;
+
+// Prints expressions as Bracket source. Parsing the output gives back the same
+// expressions for anything parse.rs produced; other trees (like an empty
+// condition) are printed as an equivalent program. Numbers are printed in the
+// classic notation unless `compact` is set (see parse::get_num).
+//
+// The grammar has one trap: a group followed by `|` is read as a conditional,
+// so a try block or a `<x|>` argument ending in a group is wrapped in
+// `[[()]] | [x] | []`, which has the same value.
+
+pub fn program(statements: &[Expression], compact: bool) -> Result<String, String> {
+    let mut out = String::new();
+    for exp in statements {
+        write(exp, compact, &mut out)?;
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+pub fn expression(exp: &Expression, compact: bool) -> Result<String, String> {
+    let mut out = String::new();
+    write(exp, compact, &mut out)?;
+    Ok(out)
+}
+
+pub fn number(num: u32, compact: bool) -> String {
+    let mut out = "[".to_string();
+    if compact {
+        out.push_str(&groups(num));
+    } else {
+        out.push_str(&"()".repeat(num as usize));
+    }
+    out.push(']');
+    out
+}
+
+// Compact groups summing to `num`: a doubled group for num / 2 and `()` if odd.
+fn groups(num: u32) -> String {
+    match num {
+        0 => String::new(),
+        1 => "()".to_string(),
+        _ => format!("({}){}", groups(num / 2), if num % 2 == 1 { "()" } else { "" }),
+    }
+}
+
+fn write(exp: &Expression, compact: bool, out: &mut String) -> Result<(), String> {
+    match exp {
+        Expression::Val(num) => out.push_str(&number(*num, compact)),
+
+        Expression::If(cond, then_exp, else_exp) => {
+            if **cond == Expression::ListExp(vec![]) {
+                out.push_str("[[]]");
+            } else {
+                write_list(cond, compact, out)?;
+            }
+            out.push_str(" | ");
+            write_list(then_exp, compact, out)?;
+            out.push_str(" | ");
+            write_list(else_exp, compact, out)?;
+        }
+
+        Expression::FunctionDef(var, args, body) => {
+            out.push_str(&format!("{{{}}}(", var));
+            out.push_str(&args.iter().map(|arg| arg.0.as_str()).collect::<Vec<_>>().join(" "));
+            out.push_str(") ");
+            write_list(body, compact, out)?;
+        }
+
+        Expression::FunctionCall(var, args) => {
+            if var.0 == "&" || var.0 == "*" {
+                let Expression::ListExp(list) = args.as_ref() else {
+                    return Err(format!("Cannot print {} applied to {}", var.0, args));
+                };
+                let [arg] = list.as_slice() else {
+                    return Err(format!("Cannot print {} applied to {} arguments", var.0, list.len()));
+                };
+                out.push_str(&var.0);
+                return write(arg, compact, out);
+            }
+            if !matches!(args.as_ref(), Expression::ListExp(_)) {
+                return Err(format!("Cannot print a call of {} without an argument list", var));
+            }
+            out.push_str(&format!("{{{}}}", var));
+            write_list(args, compact, out)?;
+        }
+
+        Expression::ListExp(list) => write_items(list, compact, out)?,
+
+        Expression::TryCatch(try_block, binding, catch_block) => {
+            out.push('<');
+            write_guarded(try_block, compact, out)?;
+            out.push_str(" | ");
+            if let Some(var) = binding {
+                out.push_str(&format!("{} | ", var));
+            }
+            write(catch_block, compact, out)?;
+            out.push('>');
+        }
+
+        Expression::Throw(exp) => {
+            out.push_str("<|");
+            write(exp, compact, out)?;
+            out.push('>');
+        }
+
+        Expression::Assert(exp, _) => {
+            out.push_str("<||");
+            write(exp, compact, out)?;
+            out.push('>');
+        }
+
+        Expression::PrintNum(exp) => {
+            out.push('<');
+            write(exp, compact, out)?;
+            out.push('>');
+        }
+
+        Expression::PrintChar(exp) => {
+            out.push('<');
+            write_guarded(exp, compact, out)?;
+            out.push_str("|>");
+        }
+
+        Expression::Arith(op, ..) => return Err(format!("Cannot print native {}, it has no Bracket syntax", op)),
+
+        Expression::At(_, exp) => write(exp, compact, out)?,
+    }
+    Ok(())
+}
+
+// `[x y z]`, the brackets of a conditional, a body or an argument list.
+fn write_list(exp: &Expression, compact: bool, out: &mut String) -> Result<(), String> {
+    match exp {
+        Expression::ListExp(list) => write_items(list, compact, out),
+        exp => write_items(std::slice::from_ref(exp), compact, out),
+    }
+}
+
+fn write_items(list: &[Expression], compact: bool, out: &mut String) -> Result<(), String> {
+    out.push('[');
+    for (i, item) in list.iter().enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        write(item, compact, out)?;
+    }
+    out.push(']');
+    Ok(())
+}
+
+// For the places followed by `|`.
+fn write_guarded(exp: &Expression, compact: bool, out: &mut String) -> Result<(), String> {
+    if !ends_with_group(exp) {
+        return write(exp, compact, out);
+    }
+    out.push_str("[[()]] | ");
+    write_list(exp, compact, out)?;
+    out.push_str(" | []");
+    Ok(())
+}
+
+fn ends_with_group(exp: &Expression) -> bool {
+    match exp {
+        Expression::ListExp(list) => !list.is_empty(),
+        Expression::At(_, exp) => ends_with_group(exp),
+        Expression::FunctionCall(var, args) if var.0 == "&" || var.0 == "*" => match args.as_ref() {
+            Expression::ListExp(list) => list.last().is_some_and(ends_with_group),
+            _ => false,
+        },
+        _ => false,
+    }
+}
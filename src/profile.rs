@@ -0,0 +1,210 @@
+use crate::eval::{Interpreter, Options};
+use crate::parse::Var;
+use crate::resolve::FunctionIndex;
+use crate::trace::{parse_json, string};
+use std::{collections::HashMap, fmt::Write};
+use wasm_bindgen::prelude::*;
+
+// Counts calls, evaluation steps and time per function while eval_expression
+// runs. Self steps are the steps of a function's own body, total steps also
+// include the functions it calls; for recursive functions only the outermost
+// call adds to the total, so nothing is counted twice. Calls are also added to
+// a call tree, which is written as folded stacks (`a;b;c steps`, the input of
+// flamegraph.pl and similar tools).
+//
+// Like the tracer, the program runs without simplify, optimize and memoize, so
+// every function runs and is counted as written instead of as the native
+// arithmetic or cached result it would become.
+
+#[derive(Clone, Default)]
+pub struct FunctionStats {
+    pub name: Var,
+    pub calls: u64,
+    pub self_steps: u64,
+    pub total_steps: u64,
+    pub self_time: f64,  // milliseconds
+    pub total_time: f64, // milliseconds
+    pub max_depth: usize, // most calls of this function running at once
+    active: usize,
+}
+
+struct Frame {
+    function: FunctionIndex,
+    node: usize, // in the call tree
+    steps: u64,  // when the call started
+    time: f64,
+    child_steps: u64,
+    child_time: f64,
+}
+
+// Call tree nodes, node 0 is the top level.
+struct CallNode {
+    name: Var,
+    parent: usize,
+    self_steps: u64,
+}
+
+pub struct Profile {
+    pub functions: Vec<FunctionStats>, // by function index, unused indices have no calls
+    pub max_depth: usize,
+    pub steps: u64,
+    pub time: f64,
+    stack: Vec<Frame>,
+    nodes: Vec<CallNode>,
+    children: HashMap<(usize, FunctionIndex), usize>,
+    start: f64,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            functions: Vec::new(),
+            max_depth: 0,
+            steps: 0,
+            time: 0.0,
+            stack: Vec::new(),
+            nodes: vec![CallNode { name: Var("<top level>".to_string()), parent: 0, self_steps: 0 }],
+            children: HashMap::new(),
+            start: now(),
+        }
+    }
+}
+
+impl Profile {
+    pub fn enter(&mut self, function: FunctionIndex, name: &Var, steps: u64) {
+        if self.functions.len() <= function {
+            self.functions.resize(function + 1, FunctionStats::default());
+        }
+        let stats = &mut self.functions[function];
+        if stats.calls == 0 {
+            stats.name = name.clone();
+        }
+        stats.calls += 1;
+        stats.active += 1;
+        stats.max_depth = stats.max_depth.max(stats.active);
+
+        let parent = self.stack.last().map_or(0, |frame| frame.node);
+        let next = self.nodes.len();
+        let node = *self.children.entry((parent, function)).or_insert(next);
+        if node == next {
+            self.nodes.push(CallNode { name: name.clone(), parent, self_steps: 0 });
+        }
+        self.stack.push(Frame { function, node, steps, time: now(), child_steps: 0, child_time: 0.0 });
+        self.max_depth = self.max_depth.max(self.stack.len());
+    }
+
+    pub fn leave(&mut self, steps: u64) {
+        let frame = self.stack.pop().unwrap();
+        let (total_steps, total_time) = (steps - frame.steps, now() - frame.time);
+        let stats = &mut self.functions[frame.function];
+        stats.active -= 1;
+        stats.self_steps += total_steps - frame.child_steps;
+        stats.self_time += total_time - frame.child_time;
+        if stats.active == 0 {
+            stats.total_steps += total_steps;
+            stats.total_time += total_time;
+        }
+        self.nodes[frame.node].self_steps += total_steps - frame.child_steps;
+        if let Some(caller) = self.stack.last_mut() {
+            caller.child_steps += total_steps;
+            caller.child_time += total_time;
+        }
+    }
+
+    // Called once the run is over, gives the top level the steps no function took.
+    pub fn finish(&mut self, steps: u64) {
+        self.steps = steps;
+        self.time = now() - self.start;
+        let in_functions: u64 = self.nodes.iter().skip(1).map(|node| node.self_steps).sum();
+        self.nodes[0].self_steps = steps - in_functions;
+    }
+
+    // Functions that were called, most self steps first.
+    pub fn ranked(&self) -> Vec<&FunctionStats> {
+        let mut ranked: Vec<&FunctionStats> = self.functions.iter().filter(|stats| stats.calls > 0).collect();
+        ranked.sort_by(|a, b| b.self_steps.cmp(&a.self_steps).then(b.calls.cmp(&a.calls)));
+        ranked
+    }
+
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "Profile: {} steps in {:.3} ms, maximum call depth {}", self.steps, self.time, self.max_depth).unwrap();
+        let width = self.ranked().iter().map(|stats| stats.name.0.len()).max().unwrap_or(0).max("function".len());
+        writeln!(out, "{:width$}  {:>10}  {:>12}  {:>12}  {:>10}  {:>10}  {:>9}", "function", "calls", "self steps", "total steps", "self ms", "total ms", "max depth").unwrap();
+        for stats in self.ranked() {
+            writeln!(
+                out,
+                "{:width$}  {:>10}  {:>12}  {:>12}  {:>10.3}  {:>10.3}  {:>9}",
+                stats.name.0, stats.calls, stats.self_steps, stats.total_steps, stats.self_time, stats.total_time, stats.max_depth
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    // One `caller;callee steps` line per path in the call tree with steps of its own.
+    pub fn folded(&self) -> String {
+        let mut out = String::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.self_steps == 0 {
+                continue;
+            }
+            let mut path = vec![node.name.0.as_str()];
+            let mut at = i;
+            while at != 0 {
+                at = self.nodes[at].parent;
+                path.push(self.nodes[at].name.0.as_str());
+            }
+            path.reverse();
+            writeln!(out, "{} {}", path.join(";"), node.self_steps).unwrap();
+        }
+        out
+    }
+}
+
+// Runs a program on the tree evaluator, profiling into interp.profile.
+pub fn profile_program(code: &str, interp: &mut Interpreter) -> Result<u32, String> {
+    interp.profile.get_or_insert_with(Profile::default);
+    Ok(crate::run_instrumented(code, interp)?)
+}
+
+// Runs a program and returns {result, steps, time, maxDepth, functions, folded}
+// with a {name, calls, selfSteps, totalSteps, selfMs, totalMs, maxDepth} object
+// per function, as ranked by the command line report.
+#[wasm_bindgen]
+pub fn profile_functions(code: String, options: &Options) -> JsValue {
+    let mut interp = Interpreter::new(*options);
+    let result = profile_program(&code, &mut interp)
+        .map(|val| val.to_string())
+        .unwrap_or_else(|err| format!("Error: {}", err));
+    let profile = interp.profile.unwrap_or_default();
+    let functions: Vec<String> = profile
+        .ranked()
+        .iter()
+        .map(|stats| {
+            format!(
+                "{{\"name\":{},\"calls\":{},\"selfSteps\":{},\"totalSteps\":{},\"selfMs\":{},\"totalMs\":{},\"maxDepth\":{}}}",
+                string(&stats.name.0), stats.calls, stats.self_steps, stats.total_steps, stats.self_time, stats.total_time, stats.max_depth
+            )
+        })
+        .collect();
+    parse_json(&format!(
+        "{{\"result\":{},\"steps\":{},\"time\":{},\"maxDepth\":{},\"functions\":[{}],\"folded\":{}}}",
+        string(&result), profile.steps, profile.time, profile.max_depth, functions.join(","), string(&profile.folded())
+    ))
+}
+
+// Milliseconds since some fixed point.
+#[cfg(not(target_arch = "wasm32"))]
+fn now() -> f64 {
+    use std::{sync::OnceLock, time::Instant};
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_secs_f64() * 1000.0
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = performance, js_name = now)]
+    fn now() -> f64;
+}
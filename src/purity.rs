@@ -0,0 +1,96 @@
+use crate::parse::{Expression, Var};
+use std::collections::{HashMap, HashSet};
+
+// Finds the functions whose result only depends on their arguments, so
+// eval.rs may remember results instead of evaluating the body again.
+//
+// A function is pure when its body prints nothing, defines no functions (the
+// function table is global state) and only calls pure functions. Every
+// function involved must be defined exactly once in the program, otherwise a
+// later redefinition could change what a call returns.
+
+pub fn pure_functions(statements: &[Expression]) -> HashSet<Var> {
+    let mut definitions: HashMap<Var, Vec<&Expression>> = HashMap::new();
+    for exp in statements {
+        collect_definitions(exp, &mut definitions);
+    }
+
+    let mut pure: HashMap<&Var, Vec<&Var>> = HashMap::new();
+    for (var, bodies) in &definitions {
+        if let [body] = bodies.as_slice() {
+            let mut calls = Vec::new();
+            if body_is_local(body, &mut calls) {
+                pure.insert(var, calls);
+            }
+        }
+    }
+
+    // drop functions calling something impure until nothing changes
+    loop {
+        let impure: Vec<&Var> = pure
+            .iter()
+            .filter(|(_, calls)| calls.iter().any(|callee| !pure.contains_key(callee)))
+            .map(|(var, _)| *var)
+            .collect();
+        if impure.is_empty() {
+            break;
+        }
+        for var in impure {
+            pure.remove(var);
+        }
+    }
+    pure.into_keys().cloned().collect()
+}
+
+fn collect_definitions<'a>(exp: &'a Expression, definitions: &mut HashMap<Var, Vec<&'a Expression>>) {
+    match exp {
+        Expression::Val(_) => {}
+        Expression::If(cond, then_exp, else_exp) => {
+            collect_definitions(cond, definitions);
+            collect_definitions(then_exp, definitions);
+            collect_definitions(else_exp, definitions);
+        }
+        Expression::FunctionDef(var, args, body) => {
+            if !args.is_empty() {
+                definitions.entry(var.clone()).or_default().push(body);
+            }
+            collect_definitions(body, definitions);
+        }
+        Expression::FunctionCall(_, args) => collect_definitions(args, definitions),
+        Expression::ListExp(list) => list.iter().for_each(|item| collect_definitions(item, definitions)),
+        Expression::TryCatch(try_block, _, catch_block) => {
+            collect_definitions(try_block, definitions);
+            collect_definitions(catch_block, definitions);
+        }
+        Expression::Throw(exp) | Expression::Assert(exp, _) | Expression::PrintNum(exp) | Expression::PrintChar(exp) => collect_definitions(exp, definitions),
+        Expression::Arith(_, x, y) => {
+            collect_definitions(x, definitions);
+            collect_definitions(y, definitions);
+        }
+        Expression::At(_, exp) => collect_definitions(exp, definitions),
+    }
+}
+
+// True if evaluating `exp` has no effect outside its own frame, collecting the
+// functions it calls along the way.
+fn body_is_local<'a>(exp: &'a Expression, calls: &mut Vec<&'a Var>) -> bool {
+    match exp {
+        Expression::Val(_) => true,
+        Expression::If(cond, then_exp, else_exp) => {
+            body_is_local(cond, calls) && body_is_local(then_exp, calls) && body_is_local(else_exp, calls)
+        }
+        Expression::FunctionDef(_, args, body) => args.is_empty() && body_is_local(body, calls),
+        Expression::FunctionCall(var, args) => {
+            if var.0 != "*" && var.0 != "&" && **args != Expression::ListExp(vec![]) {
+                calls.push(var);
+            }
+            body_is_local(args, calls)
+        }
+        Expression::ListExp(list) => list.iter().all(|item| body_is_local(item, calls)),
+        Expression::TryCatch(try_block, _, catch_block) => body_is_local(try_block, calls) && body_is_local(catch_block, calls),
+        Expression::Throw(exp) | Expression::Assert(exp, _) => body_is_local(exp, calls),
+        Expression::PrintNum(_) | Expression::PrintChar(_) => false,
+        Expression::Arith(_, x, y) => body_is_local(x, calls) && body_is_local(y, calls),
+        Expression::At(_, exp) => body_is_local(exp, calls),
+    }
+}
@@ -0,0 +1,172 @@
+use crate::parse::{Arith, Expression, Span, Var};
+use crate::eval::RuntimeError;
+use std::{collections::{HashMap, HashSet}, rc::Rc};
+
+// Resolves the names of a parsed program before it is evaluated. Variables
+// become slots in the flat frame of the function (or top level) they belong
+// to, functions become indices into the global function table, so
+// eval::eval_expression never has to hash a name. Children are shared, so
+// session.rs can keep the nodes it still has to evaluate on its task stack.
+
+pub type Slot = usize;
+pub type FunctionIndex = usize;
+
+pub struct Function {
+    pub name: Var,
+    pub args: Vec<Slot>,
+    pub frame_size: usize,
+    pub names: Vec<Var>, // of the slots, for the debugger
+    pub body: Rc<Node>,
+    pub pure: bool, // see purity.rs, results may be remembered
+}
+
+pub enum Node {
+    Val(u32),
+    If(Rc<Node>, Rc<Node>, Rc<Node>),
+    DefineVar(Slot, Rc<Node>),
+    DefineFunction(FunctionIndex, Rc<Function>),
+    Read(Slot, Var),
+    Inc(Rc<Node>),
+    Dec(Rc<Node>),
+    Call(FunctionIndex, Var, Vec<Rc<Node>>),
+    List(Vec<Rc<Node>>),
+    TryCatch(Rc<Node>, Option<Slot>, Rc<Node>),
+    Throw(Rc<Node>),
+    Assert(Rc<Node>, RuntimeError), // fails with this error if the value is 0
+    PrintNum(Rc<Node>),
+    PrintChar(Rc<Node>),
+    Arith(Arith, Rc<Node>, Rc<Node>),
+    Invalid(String), // a malformed call, fails with this message when evaluated
+    At(Span, Option<Span>, Rc<Node>), // see parse::parse_spanned, with the span of the expression around it
+}
+
+// Variable names of one frame.
+#[derive(Default)]
+pub struct Scope {
+    slots: HashMap<Var, Slot>,
+}
+
+impl Scope {
+    pub fn slot(&mut self, var: &Var) -> Slot {
+        let next = self.slots.len();
+        *self.slots.entry(var.clone()).or_insert(next)
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    // Variable names by slot.
+    pub fn names(&self) -> Vec<Var> {
+        let mut names: Vec<(Slot, Var)> = self.slots.iter().map(|(var, slot)| (*slot, var.clone())).collect();
+        names.sort_by_key(|(slot, _)| *slot);
+        names.into_iter().map(|(_, var)| var).collect()
+    }
+}
+
+// Kept for a whole run: top level statements are resolved one at a time and
+// share the function indices and the top level scope.
+#[derive(Default)]
+pub struct Resolver {
+    functions: HashMap<Var, FunctionIndex>,
+    pub globals: Scope,
+    pub pure: HashSet<Var>,
+    enclosing: Option<Span>, // span of the expression being resolved
+}
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        Resolver::default()
+    }
+
+    // Number of function indices handed out so far.
+    pub fn function_count(&self) -> usize {
+        self.functions.len()
+    }
+
+    pub fn resolve_statement(&mut self, exp: &Expression) -> Node {
+        let mut scope = std::mem::take(&mut self.globals);
+        let node = self.resolve(exp, &mut scope);
+        self.globals = scope;
+        node
+    }
+
+    fn function(&mut self, var: &Var) -> FunctionIndex {
+        let next = self.functions.len();
+        *self.functions.entry(var.clone()).or_insert(next)
+    }
+
+    fn resolve(&mut self, exp: &Expression, scope: &mut Scope) -> Node {
+        match exp {
+            Expression::Val(num) => Node::Val(*num),
+
+            Expression::If(cond, then_exp, else_exp) => Node::If(
+                Rc::new(self.resolve(cond, scope)),
+                Rc::new(self.resolve(then_exp, scope)),
+                Rc::new(self.resolve(else_exp, scope)),
+            ),
+
+            Expression::FunctionDef(var, args, body) => {
+                if args.is_empty() {
+                    let body = self.resolve(body, scope);
+                    return Node::DefineVar(scope.slot(var), Rc::new(body));
+                }
+                let index = self.function(var);
+                let mut locals = Scope::default();
+                let args = args.iter().map(|arg| locals.slot(arg)).collect();
+                let body = self.resolve(body, &mut locals);
+                Node::DefineFunction(index, Rc::new(Function {
+                    name: var.clone(),
+                    args,
+                    frame_size: locals.len(),
+                    names: locals.names(),
+                    body: Rc::new(body),
+                    pure: self.pure.contains(var),
+                }))
+            }
+
+            Expression::FunctionCall(var, args) => {
+                let Expression::ListExp(params) = args.as_ref() else {
+                    return Node::Invalid(format!("Expected a list of arguments for function call, got {}", args));
+                };
+                if var.0 == "*" || var.0 == "&" {
+                    if params.len() != 1 {
+                        return Node::Invalid(format!("{} operator expects exactly one argument, got {}", var.0, params.len()));
+                    }
+                    let arg = Rc::new(self.resolve(&params[0], scope));
+                    return if var.0 == "*" { Node::Dec(arg) } else { Node::Inc(arg) };
+                }
+                if params.is_empty() {
+                    return Node::Read(scope.slot(var), var.clone());
+                }
+                let index = self.function(var);
+                let params = params.iter().map(|param| Rc::new(self.resolve(param, scope))).collect();
+                Node::Call(index, var.clone(), params)
+            }
+
+            Expression::ListExp(list) => Node::List(list.iter().map(|item| Rc::new(self.resolve(item, scope))).collect()),
+
+            Expression::TryCatch(try_block, binding, catch_block) => {
+                let try_block = Rc::new(self.resolve(try_block, scope));
+                let binding = binding.as_ref().map(|var| scope.slot(var));
+                Node::TryCatch(try_block, binding, Rc::new(self.resolve(catch_block, scope)))
+            }
+
+            Expression::Throw(exp) => Node::Throw(Rc::new(self.resolve(exp, scope))),
+            Expression::Assert(exp, asserted) => Node::Assert(Rc::new(self.resolve(exp, scope)), crate::eval::assertion(asserted)),
+            Expression::PrintNum(exp) => Node::PrintNum(Rc::new(self.resolve(exp, scope))),
+            Expression::PrintChar(exp) => Node::PrintChar(Rc::new(self.resolve(exp, scope))),
+            Expression::Arith(op, x, y) => Node::Arith(*op, Rc::new(self.resolve(x, scope)), Rc::new(self.resolve(y, scope))),
+            Expression::At(span, exp) => {
+                let outer = self.enclosing.replace(*span);
+                let node = self.resolve(exp, scope);
+                self.enclosing = outer;
+                Node::At(*span, outer, Rc::new(node))
+            }
+        }
+    }
+}
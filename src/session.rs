@@ -0,0 +1,431 @@
+use crate::eval::{self, thrown, ErrorKind, Interpreter, Options, RuntimeError, VarTable, Verbosity};
+use crate::parse::{Arith, Expression, Span, Var};
+use crate::resolve::{Function, FunctionIndex, Node, Resolver, Slot};
+use std::{collections::VecDeque, rc::Rc};
+use wasm_bindgen::prelude::*;
+
+// Resumable evaluation of a program. Instead of recursing like
+// eval::eval_expression it keeps its own stack of pending tasks, so a run can
+// be stopped after any number of steps and picked up again later. It runs the
+// same resolved nodes (resolve.rs) with the same helpers of eval.rs, so
+// errors, limits, memoization, diagnostics and output behave exactly like
+// eval_expression. It always runs on the tree engine (options.engine is not
+// looked at) and does not record traces, coverage or profiles.
+
+enum Task {
+    Eval(Rc<Node>),
+    Branch(Rc<Node>, Rc<Node>), // pick a branch on the condition value
+    Bind(Slot),                 // store the value as a variable of the frame
+    Next(Rc<Node>, usize),      // evaluate the next item of a list
+    Arg(Rc<Node>, Rc<Function>, usize), // evaluate the next argument of a call
+    Return(Option<(FunctionIndex, Vec<u32>)>), // leave a call, remembering the result under this key
+    Catch(Option<Slot>, Rc<Node>, usize, usize, usize), // binding, catch block, value/frame/call depth to unwind to
+    Inc,
+    Dec,
+    Throw,
+    Assert(Rc<Node>), // fail if the value is 0, with the error of this Node::Assert
+    PrintNum,
+    PrintChar,
+    Arith(Arith),
+}
+
+#[derive(Clone, PartialEq)]
+pub enum Status {
+    Running,
+    Finished(u32),
+    Failed(String),
+}
+
+struct Frame {
+    function: Option<Rc<Function>>, // None at top level
+    vars: VarTable,
+}
+
+#[wasm_bindgen]
+pub struct Session {
+    interp: Interpreter,
+    statements: VecDeque<Expression>,
+    parse_error: Option<String>,
+    resolver: Resolver,
+    tasks: Vec<Task>,
+    values: Vec<u32>,
+    frames: Vec<Frame>,
+    last: u32,
+    status: Status,
+    error: Option<RuntimeError>,        // the one the run failed with
+    reached: Option<(Span, Option<Span>)>, // see run_to_span
+}
+
+#[wasm_bindgen]
+impl Session {
+    #[wasm_bindgen(constructor)]
+    pub fn new(code: String, options: &Options) -> Session {
+        Session::with_interpreter(&code, Interpreter::new(*options))
+    }
+
+    // Runs at most `steps` evaluation steps, returns true once the run is over.
+    pub fn run_slice(&mut self, steps: u32) -> bool {
+        self.run(steps as u64) != &Status::Running
+    }
+
+    pub fn cancel(&mut self) {
+        if self.status == Status::Running {
+            self.status = Status::Failed("Cancelled".to_string());
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.status != Status::Running
+    }
+
+    // Value of the program, or the error it stopped with, once it is finished.
+    pub fn result(&self) -> String {
+        match &self.status {
+            Status::Running => String::new(),
+            Status::Finished(val) => val.to_string(),
+            Status::Failed(err) => format!("Error: {}", err),
+        }
+    }
+
+    pub fn steps(&self) -> f64 {
+        self.interp.steps as f64
+    }
+}
+
+impl Session {
+    pub fn with_interpreter(code: &str, interp: Interpreter) -> Session {
+        let prepared = crate::prepare(code, &interp.options);
+        Session::with_statements(prepared, interp)
+    }
+
+    // Runs statements parsed elsewhere, like the ones of parse::parse_spanned.
+    pub fn with_statements(prepared: Result<(Vec<Expression>, Option<String>), String>, interp: Interpreter) -> Session {
+        let mut resolver = Resolver::new();
+        let (statements, parse_error, status) = match prepared {
+            Ok((statements, parse_error)) => {
+                if interp.options.memoize {
+                    resolver.pure = crate::purity::pure_functions(&statements);
+                }
+                (statements.into(), parse_error, Status::Running)
+            }
+            Err(err) => (VecDeque::new(), None, Status::Failed(err)),
+        };
+        Session {
+            interp,
+            statements,
+            parse_error,
+            resolver,
+            tasks: Vec::new(),
+            values: Vec::new(),
+            frames: vec![Frame { function: None, vars: VarTable { variables: Vec::new() } }],
+            last: 0,
+            status,
+            error: None,
+            reached: None,
+        }
+    }
+
+    pub fn status(&self) -> &Status {
+        &self.status
+    }
+
+    // The runtime error of a failed run, None if it failed for another reason.
+    pub fn error(&self) -> Option<&RuntimeError> {
+        self.error.as_ref()
+    }
+
+    pub fn run(&mut self, steps: u64) -> &Status {
+        let limit = self.interp.steps.saturating_add(steps);
+        while self.status == Status::Running && self.interp.steps < limit {
+            if let Err(err) = self.advance() {
+                self.unwind(err);
+            }
+        }
+        &self.status
+    }
+
+    // Runs until an expression with a span (see parse::parse_spanned) is about
+    // to be evaluated and returns its span and the span of the expression
+    // around it. None when the run is over or `steps` steps have been run.
+    pub fn run_to_span(&mut self, steps: u64) -> Option<(Span, Option<Span>)> {
+        let limit = self.interp.steps.saturating_add(steps);
+        while self.status == Status::Running && self.interp.steps < limit {
+            if let Err(err) = self.advance() {
+                self.unwind(err);
+            }
+            if let Some(reached) = self.reached.take() {
+                return Some(reached);
+            }
+        }
+        None
+    }
+
+    pub fn interpreter(&self) -> &Interpreter {
+        &self.interp
+    }
+
+    // Variables of the innermost frame, ordered by name.
+    pub fn variables(&self) -> Vec<(Var, u32)> {
+        let frame = self.frames.last().unwrap();
+        let names = match &frame.function {
+            Some(function) => function.names.clone(),
+            None => self.resolver.globals.names(),
+        };
+        let mut vars: Vec<(Var, u32)> = names.into_iter().zip(&frame.vars.variables).filter_map(|(var, val)| Some((var, (*val)?))).collect();
+        vars.sort_by(|a, b| (a.0.0.len(), &a.0.0).cmp(&(b.0.0.len(), &b.0.0)));
+        vars
+    }
+
+    // Number of pending tasks. Everything evaluated for an expression happens
+    // above the height the stack had when the expression was reached.
+    pub fn pending(&self) -> usize {
+        self.tasks.len()
+    }
+
+    fn advance(&mut self) -> Result<(), RuntimeError> {
+        let task = match self.tasks.pop() {
+            Some(task) => task,
+            None => {
+                if let Some(val) = self.values.pop() {
+                    self.last = val;
+                }
+                match self.statements.pop_front() {
+                    Some(exp) => {
+                        let node = self.resolver.resolve_statement(&exp);
+                        self.frames[0].vars.variables.resize(self.resolver.globals.len(), None);
+                        self.tasks.push(Task::Eval(Rc::new(node)));
+                    }
+                    None => match self.parse_error.take() {
+                        Some(err) => self.status = Status::Failed(err),
+                        None => self.status = Status::Finished(self.last),
+                    },
+                }
+                return Ok(());
+            }
+        };
+
+        match task {
+            Task::Eval(node) => self.eval(node)?,
+
+            Task::Branch(then_node, else_node) => {
+                let cond = self.pop();
+                self.tasks.push(Task::Eval(if cond != 0 { then_node } else { else_node }));
+            }
+
+            Task::Bind(slot) => {
+                let val = *self.values.last().unwrap();
+                self.vars().variables[slot].get_or_insert(val);
+            }
+
+            Task::Next(node, next) => {
+                let Node::List(items) = node.as_ref() else { unreachable!() };
+                if next < items.len() {
+                    let item = items[next].clone();
+                    self.pop();
+                    self.tasks.push(Task::Next(node, next + 1));
+                    self.tasks.push(Task::Eval(item));
+                }
+            }
+
+            Task::Arg(node, func, next) => {
+                let Node::Call(index, var, params) = node.as_ref() else { unreachable!() };
+                if next < params.len() {
+                    let param = params[next].clone();
+                    self.tasks.push(Task::Arg(node.clone(), func, next + 1));
+                    self.tasks.push(Task::Eval(param));
+                    return Ok(());
+                }
+                let vals = self.values.split_off(self.values.len() - func.args.len());
+                if self.interp.options.verbosity >= Verbosity::Trace {
+                    eval::trace_call(&mut self.interp, var, &vals);
+                }
+                let memoize = self.interp.options.memoize && func.pure;
+                let key = (*index, vals);
+                if memoize && let Some(val) = self.interp.memo.lookup(&key) {
+                    self.values.push(val);
+                    return Ok(());
+                }
+                self.interp.enter(var)?;
+                let mut vars = VarTable { variables: vec![None; func.frame_size] };
+                for (slot, val) in func.args.iter().zip(&key.1) {
+                    vars.variables[*slot] = Some(*val);
+                }
+                let body = func.body.clone();
+                self.frames.push(Frame { function: Some(func), vars });
+                self.tasks.push(Task::Return(memoize.then_some(key)));
+                self.tasks.push(Task::Eval(body));
+            }
+
+            Task::Return(key) => {
+                self.frames.pop();
+                self.interp.leave();
+                if let Some(key) = key {
+                    let (val, capacity) = (*self.values.last().unwrap(), self.interp.options.memo_capacity);
+                    self.interp.memo.insert(key, val, capacity);
+                }
+            }
+
+            // Reached without an error: the try block's value is the result.
+            Task::Catch(..) => {}
+
+            Task::Inc => {
+                let val = self.pop();
+                self.values.push(val.saturating_add(1));
+            }
+
+            Task::Dec => {
+                let val = self.pop();
+                self.values.push(eval::decrement(val)?);
+            }
+
+            Task::Throw => {
+                let code = self.pop();
+                return Err(thrown(code));
+            }
+
+            Task::Assert(node) => {
+                if *self.values.last().unwrap() == 0 && let Node::Assert(_, error) = node.as_ref() {
+                    return Err(error.clone());
+                }
+            }
+
+            Task::PrintNum => {
+                let val = *self.values.last().unwrap();
+                eval::print_num(&mut self.interp, val);
+            }
+
+            Task::PrintChar => {
+                let val = *self.values.last().unwrap();
+                eval::print_char(&mut self.interp, val);
+            }
+
+            Task::Arith(op) => {
+                let y = self.pop();
+                let x = self.pop();
+                self.values.push(eval::arith(op, x, y)?);
+            }
+        }
+        Ok(())
+    }
+
+    fn eval(&mut self, node: Rc<Node>) -> Result<(), RuntimeError> {
+        if let Node::At(span, outer, inner) = node.as_ref() {
+            self.tasks.push(Task::Eval(inner.clone()));
+            self.reached = Some((*span, *outer));
+            return Ok(());
+        }
+        self.interp.step()?;
+        match node.as_ref() {
+            Node::Val(num) => self.values.push(*num),
+
+            Node::If(cond, then_node, else_node) => {
+                self.tasks.push(Task::Branch(then_node.clone(), else_node.clone()));
+                self.tasks.push(Task::Eval(cond.clone()));
+            }
+
+            Node::DefineVar(slot, body) => {
+                self.tasks.push(Task::Bind(*slot));
+                self.tasks.push(Task::Eval(body.clone()));
+            }
+
+            Node::DefineFunction(index, func) => {
+                eval::define_function(&mut self.interp, *index, func.clone());
+                self.values.push(0);
+            }
+
+            Node::Read(slot, var) => {
+                let val = eval::read(&self.frames.last().unwrap().vars, *slot, var)?;
+                self.values.push(val);
+            }
+
+            Node::Inc(arg) => {
+                self.tasks.push(Task::Inc);
+                self.tasks.push(Task::Eval(arg.clone()));
+            }
+
+            Node::Dec(arg) => {
+                self.tasks.push(Task::Dec);
+                self.tasks.push(Task::Eval(arg.clone()));
+            }
+
+            Node::Call(index, var, params) => {
+                let func = eval::callee(&self.interp, *index, var, params.len())?;
+                self.tasks.push(Task::Arg(node.clone(), func, 0));
+            }
+
+            Node::List(items) => match items.first() {
+                Some(first) => {
+                    let first = first.clone();
+                    self.tasks.push(Task::Next(node.clone(), 1));
+                    self.tasks.push(Task::Eval(first));
+                }
+                None => self.values.push(0),
+            },
+
+            Node::TryCatch(try_node, binding, catch_node) => {
+                self.tasks.push(Task::Catch(*binding, catch_node.clone(), self.values.len(), self.frames.len(), self.interp.call_stack.len()));
+                self.tasks.push(Task::Eval(try_node.clone()));
+            }
+
+            Node::Throw(code) => {
+                self.tasks.push(Task::Throw);
+                self.tasks.push(Task::Eval(code.clone()));
+            }
+
+            Node::Assert(val, _) => {
+                self.tasks.push(Task::Assert(node.clone()));
+                self.tasks.push(Task::Eval(val.clone()));
+            }
+
+            Node::PrintNum(val) => {
+                self.tasks.push(Task::PrintNum);
+                self.tasks.push(Task::Eval(val.clone()));
+            }
+
+            Node::PrintChar(val) => {
+                self.tasks.push(Task::PrintChar);
+                self.tasks.push(Task::Eval(val.clone()));
+            }
+
+            Node::Arith(op, x, y) => {
+                self.tasks.push(Task::Arith(*op));
+                self.tasks.push(Task::Eval(y.clone()));
+                self.tasks.push(Task::Eval(x.clone()));
+            }
+
+            Node::Invalid(message) => return Err(RuntimeError::new(ErrorKind::InvalidCall, message.clone())),
+            Node::At(..) => unreachable!(),
+        }
+        Ok(())
+    }
+
+    // Drops pending tasks up to the innermost try-catch and continues with its
+    // catch block, or ends the run when nothing catches the error.
+    fn unwind(&mut self, err: RuntimeError) {
+        if !matches!(err.kind, ErrorKind::LimitExceeded { .. }) {
+            while let Some(task) = self.tasks.pop() {
+                if let Task::Catch(binding, catch_node, values, frames, depth) = task {
+                    self.values.truncate(values);
+                    self.frames.truncate(frames);
+                    self.interp.call_stack.truncate(depth);
+                    eval::caught(&mut self.interp, &err, None);
+                    if let Some(slot) = binding {
+                        self.vars().variables[slot] = Some(err.kind.code());
+                    }
+                    self.tasks.push(Task::Eval(catch_node));
+                    return;
+                }
+            }
+        }
+        self.status = Status::Failed(err.to_string());
+        self.error = Some(err);
+    }
+
+    fn vars(&mut self) -> &mut VarTable {
+        &mut self.frames.last_mut().unwrap().vars
+    }
+
+    fn pop(&mut self) -> u32 {
+        self.values.pop().unwrap()
+    }
+}
@@ -0,0 +1,131 @@
+use crate::parse::Expression;
+
+// Simplifies a parsed program before it runs:
+// - `&` and `*` applied to constants become a single Expression::Val,
+// - conditionals with a constant condition become the branch that is taken,
+// - assertions of a constant other than 0 become the constant,
+// - groups of a single expression are replaced by that expression.
+//
+// `*` applied to a constant zero can only ever fail, so it is reported as an
+// error before the program starts when it is certain to be the first error:
+// it runs whenever the program gets that far (at top level, outside any try
+// block, function body, catch block or conditional branch) and nothing that
+// runs before it can fail. In `{f}[*[]]` the lookup of f comes first, so an
+// undefined f is still the error the program stops with. Everywhere else it
+// is left alone to fail when it runs, inside a try block that is even a
+// common way of testing for zero.
+
+pub fn simplify(statements: &mut [Expression]) -> Result<(), String> {
+    let mut certain = true;
+    for exp in statements.iter_mut() {
+        let folded = fold(std::mem::replace(exp, Expression::Val(0)), &mut certain)?;
+        *exp = folded;
+    }
+    Ok(())
+}
+
+// `certain`: the expression runs whenever the program gets this far and
+// nothing before it can fail. Cleared once the expression itself may fail,
+// for whatever runs after it.
+fn fold(exp: Expression, certain: &mut bool) -> Result<Expression, String> {
+    match exp {
+        Expression::Val(_) => Ok(exp),
+
+        Expression::If(cond, then_exp, else_exp) => {
+            let cond = fold(*cond, certain)?;
+            match cond {
+                Expression::Val(0) => fold(*else_exp, certain),
+                Expression::Val(_) => fold(*then_exp, certain),
+                cond => {
+                    *certain = false;
+                    Ok(Expression::If(Box::new(cond), Box::new(fold(*then_exp, &mut false)?), Box::new(fold(*else_exp, &mut false)?)))
+                }
+            }
+        }
+
+        // a function body runs whenever the function is called, if ever
+        Expression::FunctionDef(var, args, body) if args.is_empty() => Ok(Expression::FunctionDef(var, args, Box::new(fold(*body, certain)?))),
+        Expression::FunctionDef(var, args, body) => Ok(Expression::FunctionDef(var, args, Box::new(fold(*body, &mut false)?))),
+
+        Expression::FunctionCall(var, args) => {
+            let Expression::ListExp(params) = *args else {
+                *certain = false;
+                return Ok(Expression::FunctionCall(var, args));
+            };
+            // the callee is looked up before its arguments run
+            if var.0 != "&" && var.0 != "*" {
+                *certain = false;
+            }
+            let params = params.into_iter().map(|param| fold(param, certain)).collect::<Result<Vec<_>, _>>()?;
+            match (var.0.as_str(), params.as_slice()) {
+                ("&", [Expression::Val(num)]) => Ok(Expression::Val(num.saturating_add(1))),
+                ("*", [Expression::Val(0)]) if *certain => Err("Cannot decrement zero: * is applied to the constant 0".to_string()),
+                ("*", [Expression::Val(num)]) if *num > 0 => Ok(Expression::Val(num - 1)),
+                _ => {
+                    *certain = false;
+                    Ok(Expression::FunctionCall(var, Box::new(Expression::ListExp(params))))
+                }
+            }
+        }
+
+        Expression::ListExp(list) => {
+            let mut items = Vec::new();
+            for item in list {
+                match fold(item, certain)? {
+                    Expression::ListExp(inner) => items.extend(inner),
+                    item => items.push(item),
+                }
+            }
+            match items.len() {
+                0 => Ok(Expression::Val(0)),
+                1 => Ok(items.pop().unwrap()),
+                _ => Ok(Expression::ListExp(items)),
+            }
+        }
+
+        Expression::TryCatch(try_block, binding, catch_block) => {
+            let try_block = fold(*try_block, &mut false)?;
+            if let Expression::Val(_) = try_block {
+                return Ok(try_block);
+            }
+            *certain = false;
+            Ok(Expression::TryCatch(Box::new(try_block), binding, Box::new(fold(*catch_block, &mut false)?)))
+        }
+
+        Expression::Throw(exp) => {
+            let exp = fold(*exp, certain)?;
+            *certain = false;
+            Ok(Expression::Throw(Box::new(exp)))
+        }
+        // an assertion of a constant other than 0 always holds
+        Expression::Assert(exp, asserted) => match fold(*exp, certain)? {
+            Expression::Val(num) if num != 0 => Ok(Expression::Val(num)),
+            exp => {
+                *certain = false;
+                Ok(Expression::Assert(Box::new(exp), asserted))
+            }
+        },
+        Expression::PrintNum(exp) => Ok(Expression::PrintNum(Box::new(fold(*exp, certain)?))),
+        Expression::PrintChar(exp) => Ok(Expression::PrintChar(Box::new(fold(*exp, certain)?))),
+
+        Expression::Arith(op, x, y) => {
+            let (x, y) = (fold(*x, certain)?, fold(*y, certain)?);
+            match (&x, &y) {
+                (Expression::Val(a), Expression::Val(b)) => match op.apply(*a, *b) {
+                    Some(val) => Ok(Expression::Val(val)),
+                    None if *certain => Err(format!("Cannot decrement zero: {} is applied to the constants {} and {}", op, a, b)),
+                    None => {
+                        *certain = false;
+                        Ok(Expression::Arith(op, Box::new(x), Box::new(y)))
+                    }
+                },
+                _ => {
+                    *certain = false;
+                    Ok(Expression::Arith(op, Box::new(x), Box::new(y)))
+                }
+            }
+        }
+
+        Expression::At(span, exp) => Ok(Expression::At(span, Box::new(fold(*exp, certain)?))),
+    }
+}
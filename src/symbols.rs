@@ -0,0 +1,292 @@
+use crate::parse::{self, Expression, Span, Token, Var};
+
+// Where every name of a program is defined and used, for editor features like
+// go to definition, find references and rename (see lsp.rs). Expressions from
+// parse::parse_spanned know where they are but not where their names are, so
+// names are found again in the tokens an expression starts with: a definition
+// or call starts with `{name}`, parameters follow in `(...)` and an error
+// binding is the `(name)` right after the `|` that ends the try block.
+//
+// Functions share one table for the whole program, so a call refers to the
+// definitions with its name, preferring those taking as many arguments. A
+// variable, parameter or error binding only exists in the frame (top level or
+// function body) it is defined in, a read refers to the last one of its frame
+// written before it.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Function,
+    Variable,
+    Parameter,
+    Binding, // error code of a try-catch
+}
+
+#[derive(Clone, Debug)]
+pub struct Definition {
+    pub var: Var,
+    pub kind: Kind,
+    pub name: Span,
+    pub span: Span,           // the whole definition, the name for parameters and bindings
+    pub params: Vec<Var>,     // of functions
+    pub frame: Option<usize>, // function whose body it is in, or whose parameter it is
+}
+
+#[derive(Clone, Debug)]
+pub struct Reference {
+    pub var: Var,
+    pub name: Span,
+    pub argc: usize, // 0 for reads
+    pub frame: Option<usize>,
+    pub definition: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    Definition(usize),
+    Reference(usize),
+}
+
+#[derive(Default)]
+pub struct Symbols {
+    pub definitions: Vec<Definition>,
+    pub references: Vec<Reference>,
+    pub comments: Vec<Span>, // including the slashes
+}
+
+// Statements up to a parse error still count, a program that does not
+// tokenize has no symbols.
+pub fn symbols(code: &str, compact: bool) -> Symbols {
+    let (Ok((tokens, offsets)), Ok((statements, _))) = (parse::tokenize_with_offsets(code), parse::parse_spanned(code, compact)) else {
+        return Symbols::default();
+    };
+    let mut collector = Collector { tokens: &tokens, offsets: &offsets, symbols: Symbols::default(), frame: None };
+    for exp in &statements {
+        collector.walk(exp, None);
+    }
+    let mut symbols = collector.symbols;
+    symbols.comments = comments(code);
+    symbols.resolve();
+    symbols
+}
+
+impl Symbols {
+    fn resolve(&mut self) {
+        for i in 0..self.references.len() {
+            let reference = &self.references[i];
+            let candidates: Vec<usize> = match reference.argc {
+                0 => self.variables(&reference.var, reference.frame).collect(),
+                argc => {
+                    let functions: Vec<usize> = self.functions(&reference.var).collect();
+                    let matching: Vec<usize> = functions.iter().copied().filter(|&d| self.definitions[d].params.len() == argc).collect();
+                    if matching.is_empty() { functions } else { matching }
+                }
+            };
+            let before = candidates.iter().copied().rfind(|&d| self.definitions[d].span.start < reference.name.start);
+            self.references[i].definition = before.or(candidates.first().copied());
+        }
+    }
+
+    fn functions<'a>(&'a self, var: &'a Var) -> impl Iterator<Item = usize> + 'a {
+        (0..self.definitions.len()).filter(move |&d| self.definitions[d].kind == Kind::Function && self.definitions[d].var == *var)
+    }
+
+    fn variables<'a>(&'a self, var: &'a Var, frame: Option<usize>) -> impl Iterator<Item = usize> + 'a {
+        (0..self.definitions.len()).filter(move |&d| {
+            let definition = &self.definitions[d];
+            definition.kind != Kind::Function && definition.var == *var && definition.frame == frame
+        })
+    }
+
+    // The name at a byte offset, a cursor right after a name counts too.
+    pub fn at(&self, offset: usize) -> Option<Target> {
+        let names = self
+            .definitions
+            .iter()
+            .enumerate()
+            .map(|(i, definition)| (Target::Definition(i), definition.name))
+            .chain(self.references.iter().enumerate().map(|(i, reference)| (Target::Reference(i), reference.name)));
+        let names: Vec<(Target, Span)> = names.collect();
+        names
+            .iter()
+            .find(|(_, span)| span.contains(offset))
+            .or_else(|| names.iter().find(|(_, span)| span.end == offset))
+            .map(|(target, _)| *target)
+    }
+
+    // The definition a name is or refers to.
+    pub fn definition(&self, target: Target) -> Option<usize> {
+        match target {
+            Target::Definition(d) => Some(d),
+            Target::Reference(r) => self.references[r].definition,
+        }
+    }
+
+    // Every definition and use of the same function or variable as `target`,
+    // definitions first, each in source order.
+    pub fn occurrences(&self, target: Target) -> (Vec<Span>, Vec<Span>) {
+        let (var, function, frame) = match target {
+            Target::Definition(d) => {
+                let definition = &self.definitions[d];
+                (&definition.var, definition.kind == Kind::Function, definition.frame)
+            }
+            Target::Reference(r) => {
+                let reference = &self.references[r];
+                (&reference.var, reference.argc > 0, reference.frame)
+            }
+        };
+        let definitions: Vec<usize> = match function {
+            true => self.functions(var).collect(),
+            false => self.variables(var, frame).collect(),
+        };
+        let references = self.references.iter().filter(|reference| {
+            reference.var == *var && (reference.argc > 0) == function && (function || reference.frame == frame)
+        });
+        (definitions.iter().map(|&d| self.definitions[d].name).collect(), references.map(|reference| reference.name).collect())
+    }
+
+    // Whether renaming `target` to `var` would make it the same as another
+    // function or variable.
+    pub fn taken(&self, target: Target, var: &Var) -> bool {
+        let (function, frame) = match target {
+            Target::Definition(d) => (self.definitions[d].kind == Kind::Function, self.definitions[d].frame),
+            Target::Reference(r) => (self.references[r].argc > 0, self.references[r].frame),
+        };
+        match function {
+            true => self.functions(var).next().is_some(),
+            false => self.variables(var, frame).next().is_some(),
+        }
+    }
+
+    // Text of the comment right above a definition (only blank space between),
+    // without the slashes.
+    pub fn comment<'a>(&self, code: &'a str, definition: usize) -> Option<&'a str> {
+        let start = self.definitions[definition].span.start;
+        let comment = self.comments.iter().rev().find(|comment| comment.end <= start)?;
+        code[comment.end..start].trim().is_empty().then(|| code[comment.start + 1..comment.end].trim_end_matches('/').trim())
+    }
+}
+
+struct Collector<'a> {
+    tokens: &'a [Token],
+    offsets: &'a [usize],
+    symbols: Symbols,
+    frame: Option<usize>,
+}
+
+impl Collector<'_> {
+    fn walk(&mut self, exp: &Expression, at: Option<Span>) {
+        match exp {
+            Expression::At(span, exp) => self.walk(exp, Some(*span)),
+            Expression::FunctionDef(var, args, body) => {
+                let Some(span) = at else { return self.walk(body, None) };
+                let (name, end) = self.name(self.token(span.start) + 1);
+                let kind = if args.is_empty() { Kind::Variable } else { Kind::Function };
+                let definition = Definition { var: var.clone(), kind, name, span, params: args.clone(), frame: self.frame };
+                self.symbols.definitions.push(definition);
+                if args.is_empty() {
+                    return self.walk(body, None);
+                }
+                let function = self.symbols.definitions.len() - 1;
+                let mut index = end + 2; // past `}(`
+                for arg in args {
+                    let (name, end) = self.name(index);
+                    let definition = Definition { var: arg.clone(), kind: Kind::Parameter, name, span: name, params: Vec::new(), frame: Some(function) };
+                    self.symbols.definitions.push(definition);
+                    index = end;
+                }
+                let outer = self.frame.replace(function);
+                self.walk(body, None);
+                self.frame = outer;
+            }
+            Expression::FunctionCall(var, args) => {
+                if let Some(span) = at && var.0 != "&" && var.0 != "*" {
+                    let (name, _) = self.name(self.token(span.start) + 1);
+                    let argc = match args.as_ref() {
+                        Expression::ListExp(list) => list.len(),
+                        _ => 1,
+                    };
+                    self.symbols.references.push(Reference { var: var.clone(), name, argc, frame: self.frame, definition: None });
+                }
+                self.walk(args, None);
+            }
+            Expression::TryCatch(try_block, binding, catch_block) => {
+                self.walk(try_block, None);
+                if let (Some(var), Expression::At(try_span, _)) = (binding, try_block.as_ref()) {
+                    let index = self.offsets.partition_point(|offset| *offset < try_span.end) + 1; // past `|`
+                    let (name, _) = self.name(index);
+                    let definition = Definition { var: var.clone(), kind: Kind::Binding, name, span: name, params: Vec::new(), frame: self.frame };
+                    self.symbols.definitions.push(definition);
+                }
+                self.walk(catch_block, None);
+            }
+            Expression::If(cond, then_exp, else_exp) => {
+                self.walk(cond, None);
+                self.walk(then_exp, None);
+                self.walk(else_exp, None);
+            }
+            Expression::ListExp(list) => list.iter().for_each(|item| self.walk(item, None)),
+            Expression::Throw(exp) | Expression::Assert(exp, _) | Expression::PrintNum(exp) | Expression::PrintChar(exp) => self.walk(exp, None),
+            Expression::Arith(_, x, y) => {
+                self.walk(x, None);
+                self.walk(y, None);
+            }
+            Expression::Val(_) => {}
+        }
+    }
+
+    fn token(&self, offset: usize) -> usize {
+        self.offsets.partition_point(|start| *start < offset)
+    }
+
+    // Span of the name starting at token `start` (its first `(`), and the
+    // token after it. The parser has already checked the brackets match.
+    fn name(&self, start: usize) -> (Span, usize) {
+        let mut depth = 0;
+        let mut index = start;
+        while index < self.tokens.len() {
+            depth += match self.tokens[index] {
+                Token::OpenParen => 1,
+                _ => -1,
+            };
+            index += 1;
+            if depth == 0 {
+                break;
+            }
+        }
+        (Span { start: self.offsets[start], end: self.offsets[index - 1] + 1 }, index)
+    }
+}
+
+// Spans of the `/.../` comments, an unclosed one runs to the end.
+pub fn comments(code: &str) -> Vec<Span> {
+    let mut comments = Vec::new();
+    let mut open = None;
+    for (offset, c) in code.char_indices() {
+        if c == '/' {
+            match open.take() {
+                Some(start) => comments.push(Span { start, end: offset + 1 }),
+                None => open = Some(offset),
+            }
+        }
+    }
+    if let Some(start) = open {
+        comments.push(Span { start, end: code.len() });
+    }
+    comments
+}
+
+// Whether `name` is a single bracket name like `(()())`.
+pub fn valid_name(name: &str) -> bool {
+    let mut depth = 0;
+    for (i, c) in name.char_indices() {
+        depth += match c {
+            '(' => 1,
+            ')' => -1,
+            _ => return false,
+        };
+        if depth == 0 {
+            return i == name.len() - 1;
+        }
+    }
+    false
+}
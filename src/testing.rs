@@ -0,0 +1,178 @@
+use crate::eval::{ErrorKind, Interpreter, Options, RuntimeError};
+use crate::parse::{self, Span};
+use crate::symbols;
+use crate::ProgramError;
+use std::{cell::RefCell, fmt::Write, rc::Rc};
+
+// Checks what a program does against what it is expected to do, for
+// `bracket test`. Expectations are comments in the program:
+// - `/expect: 48/` the value of the last statement
+// - `/expect output: 1\n0\n/` everything the program prints, with \n, \t and \\
+//   escapes (a comment cannot contain a slash)
+// - `/expect error: Cannot decrement zero/` the program stops with an error
+//   whose message contains the text
+// or a sidecar file next to it (`mul.bkt` and `mul.out`) holding what
+// `bracket run` would write to stdout: the printed output, then the value.
+// A program stopped by a failed assertion (`<||x>`) is reported with the line
+// of the assertion.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expectation {
+    Value(u32),
+    Output(String),
+    Error(String),
+    Stdout(String), // of a sidecar file
+}
+
+// What a run printed and how it ended.
+pub struct Outcome {
+    pub output: String,
+    pub result: Result<u32, ProgramError>,
+}
+
+impl Outcome {
+    // What `bracket run` writes to stdout, errors go to stderr.
+    pub fn stdout(&self) -> String {
+        match &self.result {
+            Ok(val) => format!("{}{}\n", self.output, val),
+            Err(_) => self.output.clone(),
+        }
+    }
+}
+
+// The expectations in the comments of a program, with where they are.
+pub fn expectations(code: &str) -> Result<Vec<(Span, Expectation)>, String> {
+    let mut expectations = Vec::new();
+    for span in symbols::comments(code) {
+        let text = code[span.start + 1..span.end].trim_end_matches('/').trim();
+        let expectation = if let Some(value) = text.strip_prefix("expect:") {
+            let value = value.trim();
+            let value = value.parse().map_err(|_| {
+                let (line, col) = parse::line_col(code, span.start);
+                format!("{}:{}: expected a number after expect:, got {}", line, col, value)
+            })?;
+            Expectation::Value(value)
+        } else if let Some(output) = text.strip_prefix("expect output:") {
+            Expectation::Output(unescape(output.trim()))
+        } else if let Some(error) = text.strip_prefix("expect error:") {
+            Expectation::Error(error.trim().to_string())
+        } else {
+            continue;
+        };
+        expectations.push((span, expectation));
+    }
+    Ok(expectations)
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (c, c == '\\') {
+            (_, true) => match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some(c) => out.push(c),
+                None => out.push('\\'),
+            },
+            (c, false) => out.push(c),
+        }
+    }
+    out
+}
+
+// An interpreter that collects what the program prints, and throws away its
+// diagnostics.
+pub fn capture(options: Options) -> (Interpreter, Rc<RefCell<String>>) {
+    let output = Rc::new(RefCell::new(String::new()));
+    let sink = output.clone();
+    let interp = Interpreter::new(options)
+        .with_output(Box::new(move |text| sink.borrow_mut().push_str(text)))
+        .with_diagnostics(Box::new(|_| {}));
+    (interp, output)
+}
+
+pub fn run(code: &str, options: Options) -> Outcome {
+    let (mut interp, output) = capture(options);
+    let result = crate::run_program(code, &mut interp);
+    let output = output.borrow().clone();
+    Outcome { output, result }
+}
+
+// One message per expectation the outcome does not meet, with `line:col` of
+// the comment and a diff for output. Without expectations, the program only
+// has to finish without an error.
+pub fn failures(code: &str, expectations: &[(Option<Span>, Expectation)], outcome: &Outcome) -> Vec<String> {
+    let mut failures = Vec::new();
+    if let Err(err) = &outcome.result && !expectations.iter().any(|(_, expectation)| matches!(expectation, Expectation::Error(_))) {
+        failures.push(assertion(code, err).unwrap_or_else(|| format!("stopped with an error: {}", err)));
+    }
+    for (span, expectation) in expectations {
+        let place = match span {
+            Some(span) => {
+                let (line, col) = parse::line_col(code, span.start);
+                format!("{}:{}: ", line, col)
+            }
+            None => String::new(),
+        };
+        let failure = match (expectation, &outcome.result) {
+            (Expectation::Value(expected), Ok(val)) if val != expected => format!("expected the value {}, got {}", expected, val),
+            (Expectation::Value(_), _) => continue,
+            (Expectation::Output(expected), _) if *expected != outcome.output => format!("expected other output:\n{}", diff(expected, &outcome.output)),
+            (Expectation::Stdout(expected), _) if *expected != outcome.stdout() => format!("expected other stdout:\n{}", diff(expected, &outcome.stdout())),
+            (Expectation::Output(_) | Expectation::Stdout(_), _) => continue,
+            (Expectation::Error(expected), Ok(val)) => format!("expected an error containing \"{}\", finished with the value {}", expected, val),
+            (Expectation::Error(expected), Err(err)) if !err.to_string().contains(expected.as_str()) => {
+                format!("expected an error containing \"{}\", got: {}", expected, err)
+            }
+            (Expectation::Error(_), Err(_)) => continue,
+        };
+        failures.push(format!("{}{}", place, failure));
+    }
+    failures
+}
+
+// A failed assertion that knows where it is as `line:col: assertion failed:
+// ...`, with the line of the assertion and its operand underlined as far as
+// it goes there.
+fn assertion(code: &str, err: &ProgramError) -> Option<String> {
+    let ProgramError::Runtime(RuntimeError { kind: ErrorKind::AssertionFailed(Some(span)), .. }) = err else { return None };
+    let (line, col) = parse::line_col(code, span.start);
+    let asserted: Vec<&str> = code[span.start..span.end].split_whitespace().collect();
+    let text = code.split('\n').nth(line - 1).unwrap_or("");
+    let width = code[span.start..span.end].split('\n').next().unwrap_or("").chars().count().max(1);
+    Some(format!("{}:{}: assertion failed: {} is 0\n{:4} | {}\n     | {}{}", line, col, asserted.join(" "), line, text, " ".repeat(col - 1), "^".repeat(width)))
+}
+
+// Lines only in `expected` start with `-`, lines only in `actual` with `+`,
+// shared lines with a space; from the longest common subsequence of lines.
+pub fn diff(expected: &str, actual: &str) -> String {
+    let a: Vec<&str> = expected.split_inclusive('\n').collect();
+    let b: Vec<&str> = actual.split_inclusive('\n').collect();
+    // common[i][j]: longest common subsequence of a[i..] and b[j..]
+    let mut common = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            common[i][j] = if a[i] == b[j] { common[i + 1][j + 1] + 1 } else { common[i + 1][j].max(common[i][j + 1]) };
+        }
+    }
+    let mut out = String::new();
+    let mut line = |sign: char, text: &str| {
+        let shown = text.strip_suffix('\n').map_or(format!("{} (no newline at the end)", text), |text| text.to_string());
+        writeln!(out, "{} {}", sign, shown).unwrap();
+    };
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            line(' ', a[i]);
+            (i, j) = (i + 1, j + 1);
+        } else if j == b.len() || (i < a.len() && common[i + 1][j] >= common[i][j + 1]) {
+            line('-', a[i]);
+            i += 1;
+        } else {
+            line('+', b[j]);
+            j += 1;
+        }
+    }
+    out
+}
@@ -0,0 +1,99 @@
+use crate::eval::{EventSink, Interpreter, Options, RuntimeError};
+use crate::parse::{self, Span, Var};
+use std::{cell::RefCell, rc::Rc};
+use wasm_bindgen::prelude::*;
+
+// Structured record of a run: every call with its arguments, every return with
+// its value or error, every branch an If takes and every error a try-catch
+// swallows, passed on as they happen (see Interpreter::record), so a long
+// trace is never held in memory. Written as one JSON object per event,
+// e.g. {"event":"call","function":"(()())","args":[2,3],"depth":1,"line":9,"column":2}.
+//
+// Like the debugger, the program is parsed with spans and runs as written:
+// without simplify, optimize and memoize, which would hide calls.
+
+#[derive(Clone, Debug)]
+pub enum Event {
+    Call { function: Var, args: Vec<u32>, depth: usize, span: Option<Span> },
+    Return { function: Var, result: Result<u32, RuntimeError>, depth: usize },
+    Branch { taken: bool, condition: u32, span: Option<Span> },
+    Caught { error: RuntimeError, span: Option<Span> },
+}
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = JSON, js_name = parse)]
+    pub(crate) fn parse_json(text: &str) -> JsValue;
+}
+
+// Runs a program on the tree evaluator, passing every event to `sink` as it
+// happens.
+pub fn trace_program(code: &str, interp: &mut Interpreter, sink: EventSink) -> Result<u32, String> {
+    interp.trace = Some(sink);
+    let result = crate::run_instrumented(code, interp);
+    interp.trace = None;
+    Ok(result?)
+}
+
+// Runs a program and returns {result, events} with the events as objects
+// like the JSON lines of `bracket run --trace`.
+#[wasm_bindgen]
+pub fn trace_events(code: String, options: &Options) -> JsValue {
+    let mut interp = Interpreter::new(*options);
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let sink = {
+        let (events, code) = (events.clone(), code.clone());
+        Box::new(move |event: &Event| events.borrow_mut().push(json(event, &code)))
+    };
+    let result = trace_program(&code, &mut interp, sink)
+        .map(|val| val.to_string())
+        .unwrap_or_else(|err| format!("Error: {}", err));
+    let events = events.borrow();
+    parse_json(&format!("{{\"result\":{},\"events\":[{}]}}", string(&result), events.join(",")))
+}
+
+// One event as a JSON object, spans as the line and column they start at.
+pub fn json(event: &Event, code: &str) -> String {
+    let (mut out, span) = match event {
+        Event::Call { function, args, depth, span } => {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            (format!("{{\"event\":\"call\",\"function\":{},\"args\":[{}],\"depth\":{}", string(&function.0), args.join(","), depth), *span)
+        }
+        Event::Return { function, result, depth } => {
+            let result = match result {
+                Ok(val) => format!("\"value\":{}", val),
+                Err(err) => format!("\"error\":{},\"code\":{}", string(&err.message), err.kind.code()),
+            };
+            (format!("{{\"event\":\"return\",\"function\":{},{},\"depth\":{}", string(&function.0), result, depth), None)
+        }
+        Event::Branch { taken, condition, span } => {
+            let taken = if *taken { "then" } else { "else" };
+            (format!("{{\"event\":\"branch\",\"taken\":\"{}\",\"condition\":{}", taken, condition), *span)
+        }
+        Event::Caught { error, span } => {
+            (format!("{{\"event\":\"caught\",\"error\":{},\"code\":{}", string(&error.message), error.kind.code()), *span)
+        }
+    };
+    if let Some(span) = span {
+        let (line, col) = parse::line_col(code, span.start);
+        out.push_str(&format!(",\"line\":{},\"column\":{}", line, col));
+    }
+    out.push('}');
+    out
+}
+
+// A JSON string literal.
+pub(crate) fn string(s: &str) -> String {
+    let mut out = "\"".to_string();
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
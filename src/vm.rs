@@ -0,0 +1,232 @@
+use crate::bytecode::{FunctionId, Instruction, NameId, Program};
+use crate::eval::{thrown, ErrorKind, Interpreter, RuntimeError, Verbosity};
+use crate::ProgramError;
+use std::collections::HashMap;
+
+// Stack machine running the instructions produced by bytecode.rs. Output,
+// diagnostics, errors and limits match eval::eval_expression, except that a
+// step is one executed instruction instead of one evaluated expression.
+
+struct Frame {
+    ret: usize,
+    vars: HashMap<NameId, u32>,
+}
+
+struct Handler {
+    catch: usize,
+    binding: Option<NameId>,
+    values: usize,
+    frames: usize,
+    callees: usize,
+    depth: usize,
+}
+
+pub struct Vm {
+    pub program: Program,
+    functions: HashMap<NameId, FunctionId>,
+    values: Vec<u32>,
+    frames: Vec<Frame>,
+    callees: Vec<FunctionId>,
+    handlers: Vec<Handler>,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Vm::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Vm {
+        Vm {
+            program: Program::new(),
+            functions: HashMap::new(),
+            values: Vec::new(),
+            frames: vec![Frame { ret: 0, vars: HashMap::new() }],
+            callees: Vec::new(),
+            handlers: Vec::new(),
+        }
+    }
+
+    // Runs from `pc` until the Halt ending the statement, returns its value.
+    pub fn execute(&mut self, mut pc: usize, interp: &mut Interpreter) -> Result<u32, RuntimeError> {
+        loop {
+            match self.step(pc, interp) {
+                Ok(Some(next)) => pc = next,
+                Ok(None) => return Ok(self.values.pop().unwrap()),
+                Err(err) => pc = self.catch(err, interp)?,
+            }
+        }
+    }
+
+    // Executes the instruction at `pc`, returns the next address or None on Halt.
+    fn step(&mut self, pc: usize, interp: &mut Interpreter) -> Result<Option<usize>, RuntimeError> {
+        interp.step()?;
+        let mut next = pc + 1;
+        match self.program.code[pc] {
+            Instruction::Push(num) => self.values.push(num),
+
+            Instruction::Pop => {
+                self.values.pop();
+            }
+
+            Instruction::Load(name) => {
+                match self.frames.last().unwrap().vars.get(&name) {
+                    Some(val) => self.values.push(*val),
+                    None => return Err(RuntimeError::new(ErrorKind::UndefinedVariable, format!("Variable {} not defined", self.program.name(name).0))),
+                }
+            }
+
+            Instruction::Define(name) => {
+                let val = *self.values.last().unwrap();
+                self.frames.last_mut().unwrap().vars.entry(name).or_insert(val);
+            }
+
+            Instruction::DefineFunction(name, function) => {
+                let args = self.program.functions[function as usize].args.len();
+                interp.diagnose(Verbosity::Trace, format!("Trace: defined function {} with {} arguments\n", self.program.name(name).0, args).as_str());
+                self.functions.insert(name, function);
+                self.values.push(0);
+            }
+
+            Instruction::Inc => {
+                let val = self.values.pop().unwrap();
+                self.values.push(val.saturating_add(1));
+            }
+
+            Instruction::Dec => {
+                let val = self.values.pop().unwrap();
+                if val == 0 {
+                    return Err(RuntimeError::new(ErrorKind::DecrementZero, "Cannot decrement zero".to_string()));
+                }
+                self.values.push(val - 1);
+            }
+
+            Instruction::Arith(op) => {
+                let y = self.values.pop().unwrap();
+                let x = self.values.pop().unwrap();
+                match op.apply(x, y) {
+                    Some(val) => self.values.push(val),
+                    None => return Err(RuntimeError::new(ErrorKind::DecrementZero, "Cannot decrement zero".to_string())),
+                }
+            }
+
+            Instruction::Jump(addr) => next = addr,
+
+            Instruction::JumpIfZero(addr) => {
+                if self.values.pop().unwrap() == 0 {
+                    next = addr;
+                }
+            }
+
+            Instruction::Prepare(name, argc) => {
+                let Some(function) = self.functions.get(&name).copied() else {
+                    return Err(RuntimeError::new(ErrorKind::UndefinedFunction, format!("Function {} not defined", self.program.name(name).0)));
+                };
+                let expected = self.program.functions[function as usize].args.len();
+                if expected != argc as usize {
+                    return Err(RuntimeError::new(ErrorKind::ArityMismatch, format!("Function {} expects {} arguments, got {}", self.program.name(name).0, expected, argc)));
+                }
+                self.callees.push(function);
+            }
+
+            Instruction::Call(name, argc) => {
+                let function = &self.program.functions[self.callees.pop().unwrap() as usize];
+                let vals = self.values.split_off(self.values.len() - argc as usize);
+                let var = self.program.name(name).clone();
+                if interp.options.verbosity >= Verbosity::Trace {
+                    let arg_vals: Vec<String> = vals.iter().map(|val| val.to_string()).collect();
+                    interp.diagnose(Verbosity::Trace, format!("Trace: calling {}({})\n", var.0, arg_vals.join(", ")).as_str());
+                }
+                interp.enter(&var)?;
+                let vars = function.args.iter().copied().zip(vals).collect();
+                self.frames.push(Frame { ret: next, vars });
+                next = function.entry;
+            }
+
+            Instruction::Ret => {
+                next = self.frames.pop().unwrap().ret;
+                interp.leave();
+            }
+
+            Instruction::TryBegin(catch, binding) => {
+                self.handlers.push(Handler {
+                    catch,
+                    binding,
+                    values: self.values.len(),
+                    frames: self.frames.len(),
+                    callees: self.callees.len(),
+                    depth: interp.call_stack.len(),
+                });
+            }
+
+            Instruction::TryEnd(addr) => {
+                self.handlers.pop();
+                next = addr;
+            }
+
+            Instruction::Throw => {
+                let code = self.values.pop().unwrap();
+                return Err(thrown(code));
+            }
+
+            Instruction::Assert(assertion) => {
+                if *self.values.last().unwrap() == 0 {
+                    return Err(self.program.assertions[assertion as usize].clone());
+                }
+            }
+
+            Instruction::PrintNum => {
+                let val = *self.values.last().unwrap();
+                interp.print(format!("{}\n", val).as_str());
+            }
+
+            Instruction::PrintChar => {
+                let val = *self.values.last().unwrap();
+                interp.print(format!("{}", char::from_u32(val).unwrap_or('?')).as_str());
+            }
+
+            Instruction::Fail(message) => {
+                return Err(RuntimeError::new(ErrorKind::InvalidCall, self.program.messages[message as usize].clone()));
+            }
+
+            Instruction::Halt => return Ok(None),
+        }
+        Ok(Some(next))
+    }
+
+    // Resumes at the innermost try-catch handler, or gives the error back.
+    fn catch(&mut self, err: RuntimeError, interp: &mut Interpreter) -> Result<usize, RuntimeError> {
+        if matches!(err.kind, ErrorKind::LimitExceeded { .. }) {
+            return Err(err);
+        }
+        let Some(handler) = self.handlers.pop() else {
+            return Err(err);
+        };
+        self.values.truncate(handler.values);
+        self.frames.truncate(handler.frames);
+        self.callees.truncate(handler.callees);
+        interp.call_stack.truncate(handler.depth);
+        interp.diagnose(Verbosity::Warn, format!("Warning!: Error caught in try block: {}\n", err).as_str());
+        if let Some(name) = handler.binding {
+            self.frames.last_mut().unwrap().vars.insert(name, err.kind.code());
+        }
+        Ok(handler.catch)
+    }
+}
+
+// Counterpart of crate::run_program for the bytecode engine: statements are
+// compiled and executed one at a time, sharing one Vm.
+pub fn run_program(code: &str, interp: &mut Interpreter) -> Result<u32, ProgramError> {
+    let (statements, parse_error) = crate::prepare(code, &interp.options)?;
+    let mut vm = Vm::new();
+    let mut output = 0;
+    for exp in statements {
+        let start = vm.program.compile_statement(&exp);
+        output = vm.execute(start, interp)?;
+    }
+    match parse_error {
+        Some(err) => Err(ProgramError::Other(err)),
+        None => Ok(output),
+    }
+}
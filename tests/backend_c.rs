@@ -0,0 +1,34 @@
+mod common;
+
+use eso_lang::backend::c;
+use eso_lang::testing;
+use std::{env, fs, process::Command};
+
+// Compiles the programs to C with the system compiler and checks that they
+// print what `bracket run` prints and exit with its code. Compiled programs
+// have no --max-depth, so the runaway ones are left out.
+#[test]
+fn compiled_c_matches_interpreter() {
+    if Command::new("cc").arg("--version").output().is_err() {
+        eprintln!("skipped: no cc");
+        return;
+    }
+    let dir = env::temp_dir().join(format!("bracket-c-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for (index, (name, code)) in common::programs().into_iter().enumerate() {
+        if name.ends_with("limit") {
+            continue;
+        }
+        let source = dir.join(format!("{}.c", index));
+        let binary = dir.join(index.to_string());
+        fs::write(&source, c::compile(&code, &common::options()).unwrap()).unwrap();
+        let status = Command::new("cc").arg("-o").arg(&binary).arg(&source).status().unwrap();
+        assert!(status.success(), "{}: cc failed", name);
+        let run = Command::new(&binary).output().unwrap();
+
+        let expected = testing::run(&code, common::options());
+        assert_eq!(String::from_utf8_lossy(&run.stdout), expected.stdout(), "{}", name);
+        assert_eq!(run.status.code(), Some(if expected.result.is_ok() { 0 } else { 1 }), "{}", name);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use eso_lang::backend::js;
+use eso_lang::testing;
+use eso_lang::ProgramError;
+use std::{env, fs, process::Command};
+
+// Runs a compiled program like `bracket run` would: output on stdout followed
+// by the value, or the error and its code on stderr and exit code 1.
+const RUNNER: &str = "
+const run = eval(require('fs').readFileSync(process.argv[1], 'utf8'));
+try {
+    const value = run(text => process.stdout.write(text), text => process.stderr.write(text));
+    process.stdout.write(value + '\\n');
+} catch (e) {
+    process.stderr.write('Error: ' + e.message + '\\nCode: ' + e.code + '\\n');
+    process.exitCode = 1;
+}
+";
+
+// Compiles the programs to JavaScript and checks that they print what the
+// interpreter prints and fail with its message and code. Compiled programs
+// have no --max-depth, so the runaway ones are left out.
+#[test]
+fn js_matches_interpreter() {
+    if Command::new("node").arg("--version").output().is_err() {
+        eprintln!("skipped: no node");
+        return;
+    }
+    let dir = env::temp_dir().join(format!("bracket-js-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for (index, (name, code)) in common::programs().into_iter().enumerate() {
+        if name.ends_with("limit") {
+            continue;
+        }
+        let script = dir.join(format!("{}.js", index));
+        fs::write(&script, js::compile(&code, &common::options()).unwrap()).unwrap();
+        let run = Command::new("node").args(["-e", RUNNER]).arg(&script).output().unwrap();
+
+        let expected = testing::run(&code, common::options());
+        assert_eq!(String::from_utf8_lossy(&run.stdout), expected.stdout(), "{}", name);
+        match &expected.result {
+            Ok(_) => assert!(run.status.success(), "{}: {}", name, String::from_utf8_lossy(&run.stderr)),
+            Err(err) => {
+                assert_eq!(run.status.code(), Some(1), "{}", name);
+                let stderr = String::from_utf8_lossy(&run.stderr);
+                assert!(stderr.contains(&format!("Error: {}\n", err)), "{}: {}", name, stderr);
+                if let ProgramError::Runtime(err) = err {
+                    assert!(stderr.ends_with(&format!("Code: {}\n", err.kind.code())), "{}: {}", name, stderr);
+                }
+            }
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use eso_lang::backend::rust;
+use eso_lang::testing;
+use eso_lang::Options;
+use std::{env, fs, path::Path, process::{Command, Output}};
+
+fn build_and_run(dir: &Path, name: &str, code: &str, options: &Options, int_type: &str) -> Output {
+    let source = dir.join(format!("{}_{}.rs", name, int_type));
+    let binary = dir.join(format!("{}_{}", name, int_type));
+    fs::write(&source, rust::compile(code, options, int_type).unwrap()).unwrap();
+    let status = Command::new("rustc").arg("-o").arg(&binary).arg(&source).status().unwrap();
+    assert!(status.success(), "{}: rustc failed", name);
+    Command::new(&binary).output().unwrap()
+}
+
+// Compiles the programs to Rust with each number type and checks that they
+// print what `bracket run` prints and fail with its message. None of them
+// gets near u32::MAX, so the wider types must not change anything. Compiled
+// programs have no --max-depth, so the runaway ones are left out.
+#[test]
+fn compiled_rust_matches_interpreter() {
+    if Command::new("rustc").arg("--version").output().is_err() {
+        eprintln!("skipped: no rustc");
+        return;
+    }
+    let dir = env::temp_dir().join(format!("bracket-rust-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for (index, (name, code)) in common::programs().into_iter().enumerate() {
+        if name.ends_with("limit") {
+            continue;
+        }
+        let expected = testing::run(&code, common::options());
+        for int_type in ["u32", "u64"] {
+            let run = build_and_run(&dir, &index.to_string(), &code, &common::options(), int_type);
+            assert_eq!(String::from_utf8_lossy(&run.stdout), expected.stdout(), "{} as {}", name, int_type);
+            match &expected.result {
+                Ok(_) => assert!(run.status.success(), "{} as {}", name, int_type),
+                Err(err) => {
+                    assert_eq!(run.status.code(), Some(1), "{} as {}", name, int_type);
+                    let stderr = String::from_utf8_lossy(&run.stderr);
+                    assert!(stderr.contains(&format!("Error: {}\n", err)), "{} as {}: {}", name, int_type, stderr);
+                }
+            }
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// Where the number types differ: u32 saturates like the interpreter, u64
+// does not, and constants must not be folded at u32 for it.
+#[test]
+fn wider_types_do_not_saturate() {
+    if Command::new("rustc").arg("--version").output().is_err() {
+        eprintln!("skipped: no rustc");
+        return;
+    }
+    let dir = env::temp_dir().join(format!("bracket-rust-wide-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    // u32::MAX in the compact notation: 2^0 + 2^1 + ... + 2^31
+    let max: Vec<String> = (1..=32).map(|depth| format!("{}{}", "(".repeat(depth), ")".repeat(depth))).collect();
+    let code = format!("&[{}]", max.join(" "));
+    let options = Options { compact_numbers: true, ..common::options() };
+    assert_eq!(testing::run(&code, options).stdout(), "4294967295\n");
+
+    let narrow = build_and_run(&dir, "max", &code, &options, "u32");
+    assert_eq!(String::from_utf8_lossy(&narrow.stdout), "4294967295\n");
+    let wide = build_and_run(&dir, "max", &code, &options, "u64");
+    assert_eq!(String::from_utf8_lossy(&wide.stdout), "4294967296\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use eso_lang::backend::wasm;
+use eso_lang::testing;
+use std::{env, fs, process::Command};
+
+// Runs a module with site/bracket_wasm.js like `bracket run` would: output on
+// stdout followed by the value, or the error on stderr and exit code 1.
+const RUNNER: &str = "
+const { runWasm } = await import(process.argv[1]);
+const bytes = (await import('node:fs')).readFileSync(process.argv[2]);
+try {
+    const value = await runWasm(bytes, text => process.stdout.write(text), text => process.stderr.write(text));
+    process.stdout.write(value + '\\n');
+} catch (e) {
+    process.stderr.write('Error: ' + e.message + '\\n');
+    process.exitCode = 1;
+}
+";
+
+// Compiles the programs to WebAssembly and checks that they print what the
+// interpreter prints and fail with its message. Compiled programs have no
+// --max-depth, so the runaway ones are left out.
+#[test]
+fn wasm_matches_interpreter() {
+    if Command::new("node").arg("--version").output().is_err() {
+        eprintln!("skipped: no node");
+        return;
+    }
+    let runner = concat!("file://", env!("CARGO_MANIFEST_DIR"), "/site/bracket_wasm.js");
+    let dir = env::temp_dir().join(format!("bracket-wasm-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for (index, (name, code)) in common::programs().into_iter().enumerate() {
+        if name.ends_with("limit") {
+            continue;
+        }
+        let module = dir.join(format!("{}.wasm", index));
+        fs::write(&module, wasm::compile(&code, &common::options()).unwrap()).unwrap();
+        let run = Command::new("node")
+            .args(["--input-type=module", "-e", RUNNER, runner])
+            .arg(&module)
+            .output()
+            .unwrap();
+
+        let expected = testing::run(&code, common::options());
+        assert_eq!(String::from_utf8_lossy(&run.stdout), expected.stdout(), "{}", name);
+        match &expected.result {
+            Ok(_) => assert!(run.status.success(), "{}: {}", name, String::from_utf8_lossy(&run.stderr)),
+            Err(err) => {
+                assert_eq!(run.status.code(), Some(1), "{}", name);
+                let stderr = String::from_utf8_lossy(&run.stderr);
+                assert!(stderr.contains(&format!("Error: {}\n", err)), "{}: {}", name, stderr);
+            }
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,65 @@
+#![allow(dead_code)]
+
+use eso_lang::testing::Outcome;
+use eso_lang::{Options, ProgramError};
+use std::{fs, panic, thread};
+
+// Programs for the tests that run one program several ways and compare: the
+// examples in tests/, then small ones for errors, try-catch and limits.
+pub fn programs() -> Vec<(String, String)> {
+    let mut programs = examples();
+    programs.extend(PROGRAMS.iter().map(|(name, code)| (name.to_string(), code.to_string())));
+    programs
+}
+
+// The .bkt files in tests/, by name.
+pub fn examples() -> Vec<(String, String)> {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests");
+    let mut paths: Vec<_> = fs::read_dir(dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "bkt"))
+        .collect();
+    paths.sort();
+    paths.iter()
+        .map(|path| (path.file_name().unwrap().to_string_lossy().into_owned(), fs::read_to_string(path).unwrap()))
+        .collect()
+}
+
+const PROGRAMS: &[(&str, &str)] = &[
+    ("decrement zero", "{(())}(()) [*{()}[]] <[()]> {(())}[[]]"),
+    ("undefined variable", "<[()()]> {((()))}[]"),
+    ("undefined function", "{((()))}[[()]]"),
+    ("wrong arity", "{()}(() (())) [{()}[]] {()}[[()]]"),
+    ("catch decrement zero", "{()}()[[]] < *{()}[] | (()) | <{(())}[]> >"),
+    ("catch without code", "< {((()))}[] | <[()()()]> >"),
+    ("catch thrown", "< <|[()()()()()()()()()()()()()()()()()()()()]> | (()) | <{(())}[]> >"),
+    ("uncaught throw", "<[()()()]> <|[()()()()()()()()()()()()()()()()()]>"),
+    ("reserved throw", "< <|[()()]> | (()) | <{(())}[]> > <|[()]>"),
+    ("rethrow", "< < <|[()()()()()()()()()()()()()()()()()()()()]> | (()) | <|&{(())}[]> > | ((())) | <{((()))}[]> >"),
+    ("failed assertion", "{()}()[[]] <||&{()}[]> <||{()}[]>"),
+    ("depth limit", "{()}(()) [{()}[&{()}[]]] <[()]> {()}[[]]"),
+    ("uncatchable limit", "{()}(()) [{()}[&{()}[]]] < {()}[[]] | (()) | <{(())}[]> >"),
+];
+
+// Without optimize, factorial.bkt nests a few hundred calls; the runaway
+// programs above stop at this depth instead of overflowing the stack.
+pub fn options() -> Options {
+    Options { max_depth: Some(1000), ..Options::default() }
+}
+
+// Runs a test on a stack deep enough for the tree evaluator of a debug build.
+pub fn on_big_stack(test: impl FnOnce() + Send + 'static) {
+    let thread = thread::Builder::new().stack_size(256 << 20).spawn(test).unwrap();
+    if let Err(err) = thread.join() {
+        panic::resume_unwind(err);
+    }
+}
+
+// What a run printed, its value, and the code and message of its error.
+pub fn summary(outcome: &Outcome) -> (String, Result<u32, (Option<u32>, String)>) {
+    let result = outcome.result.as_ref().copied().map_err(|err| match err {
+        ProgramError::Runtime(err) => (Some(err.kind.code()), err.to_string()),
+        ProgramError::Other(err) => (None, err.clone()),
+    });
+    (outcome.output.clone(), result)
+}
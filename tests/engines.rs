@@ -0,0 +1,43 @@
+mod common;
+
+use eso_lang::testing;
+use eso_lang::{Engine, Options};
+
+// Runs a program on the tree evaluator and on the bytecode vm, which have to
+// print the same, return the same value and fail with the same error.
+fn compare(name: &str, code: &str, options: Options) {
+    let tree = testing::run(code, Options { engine: Engine::Tree, ..options });
+    let vm = testing::run(code, Options { engine: Engine::Bytecode, ..options });
+    assert_eq!(common::summary(&vm), common::summary(&tree), "{}", name);
+}
+
+#[test]
+fn engines_agree() {
+    common::on_big_stack(|| {
+        for (name, code) in common::programs() {
+            compare(&name, &code, common::options());
+        }
+    });
+}
+
+#[test]
+fn engines_agree_without_passes() {
+    common::on_big_stack(|| {
+        let options = Options { simplify: false, optimize: false, ..common::options() };
+        for (name, code) in common::programs() {
+            compare(&name, &code, options);
+        }
+    });
+}
+
+// The vm counts instructions rather than expressions as steps, so only
+// programs that never finish stop the same way under a step limit.
+#[test]
+fn engines_agree_on_step_limit() {
+    common::on_big_stack(|| {
+        let options = Options { max_steps: Some(10_000), max_depth: None, ..common::options() };
+        for (name, code) in common::programs().into_iter().filter(|(name, _)| name.ends_with("limit")) {
+            compare(&name, &code, options);
+        }
+    });
+}
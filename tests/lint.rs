@@ -0,0 +1,78 @@
+use eso_lang::lint::{self, Severity};
+
+fn diagnostics(code: &str) -> Vec<(Severity, String, &str)> {
+    let diagnostics = lint::lint_code(code, true).unwrap();
+    diagnostics.into_iter().map(|diagnostic| (diagnostic.severity, diagnostic.message, &code[diagnostic.span.start..diagnostic.span.end])).collect()
+}
+
+fn error(message: &str, at: &'static str) -> (Severity, String, &'static str) {
+    (Severity::Error, message.to_string(), at)
+}
+
+fn warning(message: &str, at: &'static str) -> (Severity, String, &'static str) {
+    (Severity::Warning, message.to_string(), at)
+}
+
+#[test]
+fn undefined_functions() {
+    assert_eq!(diagnostics("<[()]> {((()))}[[()]]"), [error("Function ((())) is not defined anywhere in the program", "{((()))}[[()]]")]);
+    // a definition anywhere counts, even after the call
+    assert_eq!(diagnostics("{()}[[()]] {()}(()) [{()}[]]"), []);
+}
+
+#[test]
+fn wrong_arity() {
+    let code = "{()}(() (())) [{()}[]] {()}[[()]]";
+    assert_eq!(diagnostics(code), [error("Function () expects 2 arguments, got 1", "{()}[[()]]")]);
+    // every definition's arity is accepted
+    let code = "{(())}()[[()]] [{(())}[]] | [{()}(()) [{()}[]]] | [{()}(() (())) [{()}[]]] {()}[[()]] {()}[[] []] {()}[[] [] []]";
+    assert_eq!(diagnostics(code), [error("Function () expects 1 or 2 arguments, got 3", "{()}[[] [] []]")]);
+}
+
+#[test]
+fn undefined_variables() {
+    assert_eq!(diagnostics("<{()}[]>"), [error("Variable () is never defined at top level", "{()}[]")]);
+    // functions cannot see top level variables
+    let code = "{(())}()[[()]] {()}((())) [{(())}[] {((()))}[]] {()}[[]]";
+    assert_eq!(diagnostics(code), [error("Variable ((())) is not a parameter or variable of function ()", "{((()))}[]")]);
+}
+
+#[test]
+fn reserved_throws() {
+    assert_eq!(diagnostics("<|[()()()]>"), [error("Cannot throw 3, codes 1 to 15 are reserved for built-in errors", "<|[()()()]>")]);
+    assert_eq!(diagnostics("<|[(((((())))))]>"), []);
+}
+
+#[test]
+fn dead_branches() {
+    assert_eq!(diagnostics("[[()]] | [<[()]>] | [<[]>]"), [warning("The else branch can never run, the condition is always 1", "<[]>")]);
+    assert_eq!(diagnostics("[[]] | [<[()]>] | [<[]>]"), [warning("The then branch can never run, the condition is always 0", "<[()]>")]);
+}
+
+#[test]
+fn duplicate_parameters() {
+    let code = "{()}(() ()) [{()}[]] {()}[[] [()]]";
+    assert_eq!(diagnostics(code), [warning("Parameter () of function () is listed more than once, the last argument wins", "{()}(() ()) [{()}[]]")]);
+}
+
+#[test]
+fn redefined_functions() {
+    let code = "{()}(()) [{()}[]] {()}(()) [&{()}[]]";
+    assert_eq!(diagnostics(code), [warning("Function () is already defined above, this definition replaces it", "{()}(()) [&{()}[]]")]);
+    // not inside a conditional
+    assert_eq!(diagnostics("{((()))}()[[]] {()}(()) [{()}[]] [{((()))}[]] | [{()}(()) [&{()}[]]] | [[]]"), []);
+}
+
+#[test]
+fn faults_inside_try_are_warnings() {
+    let code = "< {((()))}[[()]] | (()) | <{(())}[]> > < <|[()]> | (()) | <{(())}[]> >";
+    assert_eq!(
+        diagnostics(code),
+        [
+            warning("Function ((())) is not defined anywhere in the program", "{((()))}[[()]]"),
+            warning("Cannot throw 1, codes 1 to 15 are reserved for built-in errors", "<|[()]>"),
+        ]
+    );
+    // but not in the catch block
+    assert_eq!(diagnostics("< [] | (()) | {((()))}[[()]] >"), [error("Function ((())) is not defined anywhere in the program", "{((()))}[[()]]")]);
+}
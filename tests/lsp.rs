@@ -0,0 +1,102 @@
+use eso_lang::lsp::{serve, Json};
+
+#[test]
+fn json_escapes() {
+    let parsed = Json::parse(r#""a\"b\\c\/d\n\t\r\b\f\u0041""#).unwrap();
+    assert_eq!(parsed, Json::String("a\"b\\c/d\n\t\r\u{8}\u{c}A".to_string()));
+    // printed back with escapes, parsed back to the same string
+    let printed = parsed.to_string();
+    assert_eq!(printed, r#""a\"b\\c/d\n\u0009\u000d\u0008\u000cA""#);
+    assert_eq!(Json::parse(&printed).unwrap(), parsed);
+}
+
+#[test]
+fn json_surrogate_pairs() {
+    assert_eq!(Json::parse(r#""\ud83d\ude00!""#).unwrap(), Json::String("\u{1F600}!".to_string()));
+    assert_eq!(Json::parse(r#""\uD83D\uDE00""#).unwrap(), Json::String("\u{1F600}".to_string()));
+    assert_eq!(Json::parse(r#""\u00e9\u4e2d""#).unwrap(), Json::String("\u{e9}\u{4e2d}".to_string()));
+    // halves of a pair on their own
+    assert_eq!(Json::parse(r#""\ud83dx""#).unwrap(), Json::String("\u{FFFD}x".to_string()));
+    assert_eq!(Json::parse(r#""\ud83d""#).unwrap(), Json::String("\u{FFFD}".to_string()));
+    assert_eq!(Json::parse(r#""\ud83d\u0041""#).unwrap(), Json::String("\u{FFFD}A".to_string()));
+    assert_eq!(Json::parse(r#""\ude00""#).unwrap(), Json::String("\u{FFFD}".to_string()));
+}
+
+#[test]
+fn json_nested_values() {
+    let text = r#"{"a":[1,-2.5,{"b":null}],"c":{"d":[true,false,[]],"e":{}},"f":"g"}"#;
+    let parsed = Json::parse(text).unwrap();
+    assert_eq!(parsed.get("c").get("d"), &Json::Array(vec![Json::Bool(true), Json::Bool(false), Json::Array(Vec::new())]));
+    assert_eq!(parsed.get("f").str(), Some("g"));
+    assert_eq!(parsed.get("missing"), &Json::Null);
+    let Json::Array(items) = parsed.get("a") else { panic!("a is not an array") };
+    assert_eq!(items[1].number(), Some(-2.5));
+    assert_eq!(items[2].get("b"), &Json::Null);
+    assert_eq!(parsed.to_string(), text);
+    assert_eq!(Json::parse(" { \"a\" : [ 1 , 2 ] } ").unwrap().to_string(), r#"{"a":[1,2]}"#);
+}
+
+#[test]
+fn json_malformed() {
+    for text in ["", "{", "[1,", "[1 2]", r#"{"a" 1}"#, r#"{"a":1,}"#, "{a:1}", "tru", "nul", r#""abc"#, r#""\u12""#, "1 2", "-", "[]]"] {
+        assert!(Json::parse(text).is_err(), "{} was parsed", text);
+    }
+}
+
+fn frame(message: &str) -> String {
+    format!("Content-Length: {}\r\n\r\n{}", message.len(), message)
+}
+
+fn messages(mut output: &str) -> Vec<Json> {
+    let mut messages = Vec::new();
+    while let Some((header, rest)) = output.split_once("\r\n\r\n") {
+        let length: usize = header.strip_prefix("Content-Length: ").unwrap().parse().unwrap();
+        messages.push(Json::parse(&rest[..length]).unwrap());
+        output = &rest[length..];
+    }
+    messages
+}
+
+#[test]
+fn initialize_open_and_diagnose() {
+    let code = "/a/ {(())}(()) [{()}[]]\n{(())}[[()] [()]]\n/two\nlines/ {(())}[[()]]";
+    let open = format!(
+        r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"file:///a.bkt","text":{}}}}}}}"#,
+        Json::String(code.to_string())
+    );
+    let input = [
+        r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+        r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+        &open,
+        r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/semanticTokens/full","params":{"textDocument":{"uri":"file:///a.bkt"}}}"#,
+        r#"{"jsonrpc":"2.0","id":3,"method":"shutdown"}"#,
+        r#"{"jsonrpc":"2.0","method":"exit"}"#,
+    ]
+    .map(frame)
+    .concat();
+    let mut output = Vec::new();
+    assert!(serve(input.as_bytes(), &mut output, false).unwrap());
+
+    let messages = messages(&String::from_utf8(output).unwrap());
+    // nothing answers the notification `initialized`
+    assert_eq!(messages.len(), 4);
+    assert_eq!(messages[0].get("id"), &Json::Number(1.0));
+    assert_eq!(messages[0].get("result").get("serverInfo").get("name").str(), Some("bracket-lsp"));
+
+    let diagnostics = &messages[1];
+    assert_eq!(diagnostics.get("method").str(), Some("textDocument/publishDiagnostics"));
+    assert_eq!(diagnostics.get("params").get("uri").str(), Some("file:///a.bkt"));
+    let Json::Array(diagnostics) = diagnostics.get("params").get("diagnostics") else { panic!("no diagnostics") };
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].get("message").str(), Some("Function (()) expects 1 arguments, got 2"));
+    assert_eq!(diagnostics[0].get("severity"), &Json::Number(1.0));
+    assert_eq!(diagnostics[0].get("range").get("start").get("line"), &Json::Number(1.0));
+
+    // relative positions, which are never negative, even around the comment
+    // spanning two lines
+    let Json::Array(data) = messages[2].get("result").get("data") else { panic!("no tokens") };
+    assert_eq!(data.len() % 5, 0);
+    assert!(data.iter().all(|num| num.number().is_some_and(|num| num >= 0.0)));
+    assert_eq!(messages[3].get("id"), &Json::Number(3.0));
+    assert_eq!(messages[3].get("result"), &Json::Null);
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use eso_lang::testing::{self, Outcome};
+use eso_lang::Options;
+
+// Names: f is (()), g is (()()) and their argument is ().
+const PURE: &str = "{(())}(()) [&&{()}[]] <{(())}[[()]]> <{(())}[[()]]>";
+const PRINTS: &str = "{(())}(()) [<&{()}[]>] {(())}[[()]] {(())}[[()]]";
+const CALLS_IMPURE: &str = "{(()())}(()) [<{()}[]>] {(())}(()) [&{(()())}[{()}[]]] {(())}[[()]] {(())}[[()]]";
+const DEFINES: &str = "{(())}(()) [ {(()())}(()) [{()}[]] &{()}[] ] <{(())}[[()]]> <{(())}[[()]]>";
+const REDEFINED: &str = "{(())}(()) [&{()}[]] <{(())}[[()]]> {(())}(()) [&&{()}[]] <{(())}[[()]]> <{(())}[[()]]>";
+const MANY_ARGUMENTS: &str = "{(())}(()) [&&{()}[]]
+    <{(())}[[()]]> <{(())}[[()()]]> <{(())}[[()()()]]> <{(())}[[()]]> <{(())}[[()()]]> <{(())}[[()()()]]>";
+
+// Runs a program with and without memoize, checks that both print and return
+// the same and returns the cache hits and evictions of the memoized run.
+fn compare(name: &str, code: &str, memo_capacity: u32) -> (u64, u64) {
+    let run = |memoize| {
+        let (mut interp, output) = testing::capture(Options { memoize, memo_capacity, ..common::options() });
+        let result = eso_lang::run_program(code, &mut interp);
+        let outcome = Outcome { output: output.borrow().clone(), result };
+        (common::summary(&outcome), interp.memo.hits, interp.memo.evictions)
+    };
+    let (memoized, hits, evictions) = run(true);
+    let (plain, ..) = run(false);
+    assert_eq!(memoized, plain, "{}", name);
+    (hits, evictions)
+}
+
+#[test]
+fn memoize_keeps_results() {
+    common::on_big_stack(|| {
+        for (name, code) in common::programs() {
+            compare(&name, &code, 100_000);
+            compare(&name, &code, 1);
+        }
+    });
+}
+
+#[test]
+fn pure_functions_are_remembered() {
+    assert_eq!(compare("pure", PURE, 100_000), (1, 0));
+}
+
+#[test]
+fn impure_functions_run_every_time() {
+    assert_eq!(testing::run(PRINTS, Options { memoize: true, ..common::options() }).output, "2\n2\n");
+    assert_eq!(compare("prints", PRINTS, 100_000).0, 0);
+    assert_eq!(testing::run(CALLS_IMPURE, Options { memoize: true, ..common::options() }).output, "1\n1\n");
+    assert_eq!(compare("calls impure", CALLS_IMPURE, 100_000).0, 0);
+    assert_eq!(compare("defines a function", DEFINES, 100_000).0, 0);
+}
+
+#[test]
+fn redefined_functions_are_not_remembered() {
+    assert_eq!(testing::run(REDEFINED, Options { memoize: true, ..common::options() }).output, "2\n3\n3\n");
+    assert_eq!(compare("redefined", REDEFINED, 100_000).0, 0);
+}
+
+#[test]
+fn full_cache_is_emptied() {
+    assert_eq!(compare("many arguments", MANY_ARGUMENTS, 100_000), (3, 0));
+    let (hits, evictions) = compare("many arguments", MANY_ARGUMENTS, 2);
+    assert_eq!(hits, 0);
+    assert!(evictions > 0);
+    assert_eq!(compare("many arguments", MANY_ARGUMENTS, 0), (0, 0));
+}
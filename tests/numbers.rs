@@ -0,0 +1,69 @@
+mod common;
+
+use eso_lang::{assembler, printer, testing, Options};
+
+fn value(code: &str, compact_numbers: bool) -> Result<u32, String> {
+    testing::run(code, Options { compact_numbers, ..common::options() }).result.map_err(|err| err.to_string())
+}
+
+// The classic `[()()()]` and the compact notation of the same number are the
+// same value.
+#[test]
+fn compact_and_plain_numbers_agree() {
+    for num in [0, 1, 2, 3, 4, 7, 64, 65, 1000, 65535, 65536, 100_000] {
+        let (plain, compact) = (printer::number(num, false), printer::number(num, true));
+        assert_eq!(value(&plain, false), Ok(num));
+        assert_eq!(value(&plain, true), Ok(num), "{}", plain);
+        assert_eq!(value(&compact, true), Ok(num), "{}", compact);
+    }
+    // groups may be split any way
+    assert_eq!(value("[((())) ((())) (()) () ()]", true), Ok(12));
+    assert_eq!(value("[(() ()) ((()))]", true), Ok(8));
+    // and the compact notation is refused without the option
+    assert!(value("[(())]", false).unwrap_err().contains("Expected ) after ( in number"));
+}
+
+// Written classically u32::MAX would take 8 GB, so large values are checked
+// against sums of powers of two and the step after them.
+#[test]
+fn large_compact_numbers() {
+    let power = |exponent: usize| format!("{}{}", "(".repeat(exponent + 1), ")".repeat(exponent + 1));
+    let powers = |exponents: &[usize]| format!("[{}]", exponents.iter().map(|exponent| power(*exponent)).collect::<Vec<_>>().join(" "));
+    assert_eq!(value(&powers(&[31]), true), Ok(1 << 31));
+    assert_eq!(value(&powers(&[31, 0]), true), Ok((1 << 31) + 1));
+    let all: Vec<usize> = (0..32).collect();
+    assert_eq!(value(&powers(&all), true), Ok(u32::MAX));
+    for num in [u32::MAX - 1, u32::MAX, 1 << 31, 123_456_789] {
+        assert_eq!(value(&printer::number(num, true), true), Ok(num));
+    }
+    // one more does not fit
+    assert!(value(&powers(&[32]), true).unwrap_err().contains("Number too large"));
+    let mut more = all.clone();
+    more.push(0);
+    assert!(value(&powers(&more), true).unwrap_err().contains("Number too large"));
+}
+
+// --shortest picks `&` chains only when they are shorter, in both notations,
+// and never changes what the program does.
+#[test]
+fn shortest_is_never_longer() {
+    // in the plain notation --shortest always picks `&` chains, one nesting
+    // level per unit, so those stay well below the depth limit
+    common::on_big_stack(|| {
+        let numbers = (0..=300).chain([1000, 4096, 65535, 1 << 20, u32::MAX]);
+        for num in numbers {
+            let source = format!("print {};", num);
+            for compact in [false, true] {
+                if !compact && num > 300 {
+                    continue;
+                }
+                let plain = assembler::to_bracket(&source, false, compact).unwrap();
+                let shortest = assembler::to_bracket(&source, true, compact).unwrap();
+                assert!(shortest.len() <= plain.len(), "{} {}: {} is longer than {}", num, compact, shortest, plain);
+                let options = Options { compact_numbers: compact, ..common::options() };
+                assert_eq!(testing::run(&shortest, options).output, format!("{}\n", num), "{}", num);
+                assert_eq!(testing::run(&plain, options).output, format!("{}\n", num), "{}", num);
+            }
+        }
+    });
+}
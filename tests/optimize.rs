@@ -0,0 +1,95 @@
+mod common;
+
+use eso_lang::testing::{self, Outcome};
+use eso_lang::{Engine, Options};
+
+// Names: x is (), y is (()), acc is ((())), the function is (()()) and the
+// adder a multiplication calls is (()()()).
+const ADD: &str = "{(()())}(() (())) [ [{(())}[]] | [{(()())}[&{()}[] *{(())}[]]] | [{()}[]] ]";
+const ADD_OUTER: &str = "{(()())}(() (())) [ [{(())}[]] | [&{(()())}[{()}[] *{(())}[]]] | [{()}[]] ]";
+const SUB: &str = "{(()())}(() (())) [ [{(())}[]] | [{(()())}[*{()}[] *{(())}[]]] | [{()}[]] ]";
+const SUB_OUTER: &str = "{(()())}(() (())) [ [{(())}[]] | [*{(()())}[{()}[] *{(())}[]]] | [{()}[]] ]";
+const MUL: &str = "{(()()())}(() (())) [ [{(())}[]] | [{(()()())}[&{()}[] *{(())}[]]] | [{()}[]] ]
+    {(()())}(() (()) ((()))) [ [{(())}[]] | [{(()())}[{()}[] *{(())}[] {(()()())}[{((()))}[] {()}[]]]] | [{((()))}[]] ]";
+
+// Shapes that print, which optimize must leave alone.
+const PRINTS_RESULT: &str = "{(()())}(() (())) [ [{(())}[]] | [{(()())}[&{()}[] *{(())}[]]] | [<{()}[]>] ]";
+const PRINTS_CONDITION: &str = "{(()())}(() (())) [ [<{(())}[]>] | [{(()())}[&{()}[] *{(())}[]]] | [{()}[]] ]";
+const PRINTS_ARGUMENT: &str = "{(()())}(() (())) [ [{(())}[]] | [{(()())}[<&{()}[]> *{(())}[]]] | [{()}[]] ]";
+
+const PAIRS: &[(u32, u32)] = &[(0, 0), (0, 3), (3, 0), (1, 1), (2, 5), (5, 2), (4, 4)];
+
+fn num(n: u32) -> String {
+    format!("[{}]", "()".repeat(n as usize))
+}
+
+// Runs a program with and without optimize on both engines, checks that
+// nothing but the number of steps changes and returns the steps taken with
+// and without it on the tree engine.
+fn compare(name: &str, code: &str) -> (u64, u64) {
+    let mut steps = (0, 0);
+    for engine in [Engine::Tree, Engine::Bytecode] {
+        let run = |optimize| {
+            let (mut interp, output) = testing::capture(Options { engine, optimize, ..common::options() });
+            let result = eso_lang::run_program(code, &mut interp);
+            let outcome = Outcome { output: output.borrow().clone(), result };
+            (common::summary(&outcome), interp.steps)
+        };
+        let (optimized, optimized_steps) = run(true);
+        let (plain, plain_steps) = run(false);
+        assert_eq!(optimized, plain, "{} on the {} engine", name, if engine == Engine::Tree { "tree" } else { "bytecode" });
+        if engine == Engine::Tree {
+            steps = (optimized_steps, plain_steps);
+        }
+    }
+    steps
+}
+
+// Calls the function of `definition` on each pair, with the arguments printed
+// on the way in and the result printed.
+fn check(name: &str, definition: &str, rewritten: bool) {
+    for &(x, y) in PAIRS {
+        let mul = if definition == MUL { " []" } else { "" };
+        let code = format!("{} <{{(()())}}[<{}> <{}>{}]>", definition, num(x), num(y), mul);
+        let (optimized, plain) = compare(&format!("{}({}, {})", name, x, y), &code);
+        if rewritten && y > 1 {
+            assert!(optimized < plain, "{}({}, {}) was not rewritten", name, x, y);
+        } else if !rewritten {
+            assert_eq!(optimized, plain, "{}({}, {}) was rewritten", name, x, y);
+        }
+    }
+}
+
+#[test]
+fn addition() {
+    check("add", ADD, true);
+    check("add outer", ADD_OUTER, true);
+}
+
+// Covers decrementing zero too: sub(x, y) with y > x.
+#[test]
+fn subtraction() {
+    check("sub", SUB, true);
+    check("sub outer", SUB_OUTER, true);
+}
+
+#[test]
+fn multiplication() {
+    check("mul", MUL, true);
+}
+
+#[test]
+fn printing_bodies_are_kept() {
+    check("prints result", PRINTS_RESULT, false);
+    check("prints condition", PRINTS_CONDITION, false);
+    check("prints argument", PRINTS_ARGUMENT, false);
+}
+
+#[test]
+fn examples_agree() {
+    common::on_big_stack(|| {
+        for (name, code) in common::programs() {
+            compare(&name, &code);
+        }
+    });
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use eso_lang::parse::{self, Expression, Var};
+use eso_lang::{printer, testing};
+
+fn parse(code: &str) -> Vec<Expression> {
+    let (statements, error) = parse::parse_statements(&parse::tokenize(code).unwrap(), true);
+    assert_eq!(error, None, "{}", code);
+    statements
+}
+
+// Printing a parsed program and parsing it again gives the same expressions,
+// and the printed program runs like the original. Numbers are printed in
+// both notations.
+#[test]
+fn printed_programs_parse_back() {
+    common::on_big_stack(|| {
+        for (name, code) in common::programs() {
+            let statements = parse(&code);
+            for compact in [false, true] {
+                let printed = printer::program(&statements, compact).unwrap();
+                assert!(parse(&printed) == statements, "{}: {}", name, printed);
+                let options = eso_lang::Options { compact_numbers: compact, ..common::options() };
+                let (printed, code) = (testing::run(&printed, options), testing::run(&code, common::options()));
+                // failed assertions say where they are, which printing changes
+                let (printed, code) = (common::summary(&printed), common::summary(&code));
+                match (&printed.1, &code.1) {
+                    (Err((Some(7), _)), Err((Some(7), _))) => assert_eq!(printed.0, code.0, "{}", name),
+                    _ => assert_eq!(printed, code, "{}", name),
+                }
+            }
+        }
+    });
+}
+
+// A try block ending in a group would be read as a conditional, the printer
+// wraps it in `[[()]] | [...] | []`. That is a different tree with the same
+// behaviour, printed the same way again.
+#[test]
+fn guarded_try_blocks() {
+    let list = |items: Vec<Expression>| Expression::ListExp(items);
+    let call = |name: &str, items: Vec<Expression>| Expression::FunctionCall(Var(name.to_string()), Box::new(list(items)));
+    let print = |exp: Expression| Expression::PrintNum(Box::new(exp));
+    let catch = |try_block: Expression| Expression::TryCatch(Box::new(try_block), Some(Var("()".to_string())), Box::new(print(call("()", vec![]))));
+    let statements = vec![
+        // a try block that succeeds with the value of a group
+        print(catch(list(vec![print(Expression::Val(3)), list(vec![Expression::Val(2)])]))),
+        // one that fails in a group
+        print(catch(list(vec![print(Expression::Val(4)), list(vec![call("*", vec![list(vec![])])])]))),
+        // and &[...] ending in one
+        print(catch(call("&", vec![list(vec![Expression::Val(5)])]))),
+        Expression::PrintChar(Box::new(list(vec![Expression::Val(65)]))),
+    ];
+    let printed = printer::program(&statements, false).unwrap();
+    assert_eq!(printed.matches("[[()]] | [").count(), 4, "{}", printed);
+    assert_eq!(printer::program(&parse(&printed), false).unwrap(), printed);
+    let outcome = testing::run(&printed, common::options());
+    assert_eq!(outcome.output, "3\n2\n4\n1\n1\n6\nA");
+    assert_eq!(outcome.result.unwrap(), 65);
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use eso_lang::testing::{self, Outcome};
+use eso_lang::{Options, ProgramError, Session, Status};
+
+// The session evaluates the resolved nodes from a task stack instead of
+// recursing, so every program has to print, return and fail exactly like it
+// does with run_program, down to the step a limit stops it at.
+fn compare(options: Options) {
+    for (name, code) in common::programs() {
+        let expected = testing::run(&code, options);
+        let (interp, output) = testing::capture(options);
+        let mut session = Session::with_interpreter(&code, interp);
+        let result = match session.run(u64::MAX).clone() {
+            Status::Finished(val) => Ok(val),
+            Status::Failed(err) => Err(session.error().cloned().map_or(ProgramError::Other(err), ProgramError::Runtime)),
+            Status::Running => panic!("{}: still running", name),
+        };
+        let steps = session.interpreter().steps;
+        let actual = Outcome { output: output.borrow().clone(), result };
+        assert_eq!(common::summary(&actual), common::summary(&expected), "{}", name);
+
+        let (mut interp, _) = testing::capture(options);
+        let _ = eso_lang::run_program(&code, &mut interp);
+        assert_eq!(steps, interp.steps, "{}: steps", name);
+    }
+}
+
+#[test]
+fn session_matches_evaluator() {
+    common::on_big_stack(|| compare(common::options()));
+}
+
+#[test]
+fn session_matches_evaluator_without_passes() {
+    common::on_big_stack(|| compare(Options { simplify: false, optimize: false, ..common::options() }));
+}
+
+#[test]
+fn session_matches_evaluator_with_memoize() {
+    common::on_big_stack(|| compare(Options { memoize: true, memo_capacity: 3, ..common::options() }));
+}
+
+#[test]
+fn session_matches_evaluator_on_step_limit() {
+    common::on_big_stack(|| {
+        for max_steps in [1, 10, 100, 1000] {
+            compare(Options { max_steps: Some(max_steps), ..common::options() });
+        }
+    });
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use eso_lang::{testing, Options, ProgramError};
+
+fn run(code: &str) -> (String, Result<u32, (Option<u32>, String)>) {
+    common::summary(&testing::run(code, Options::default()))
+}
+
+fn refusal(code: &str) -> String {
+    match testing::run(code, Options::default()).result {
+        Err(ProgramError::Other(message)) => message,
+        result => panic!("{} was not refused: {:?}", code, result),
+    }
+}
+
+#[test]
+fn certain_decrements_of_zero_are_refused() {
+    assert_eq!(refusal("*[]"), "Cannot decrement zero: * is applied to the constant 0");
+    assert_eq!(refusal("<[()]> {()}()[&*&[]] *[[]]"), "Cannot decrement zero: * is applied to the constant 0");
+    assert_eq!(refusal("<&*[]>"), "Cannot decrement zero: * is applied to the constant 0");
+}
+
+// Errors that may come first are left to happen when the program runs.
+#[test]
+fn earlier_errors_come_first() {
+    // the callee is looked up before its arguments run
+    assert_eq!(run("{()}[*[]]"), (String::new(), Err((Some(3), "Function () not defined".to_string()))));
+    assert_eq!(run("<[()]> {()}[[] *[]]"), ("1\n".to_string(), Err((Some(3), "Function () not defined".to_string()))));
+    assert_eq!(run("{()}[] *[]"), (String::new(), Err((Some(2), "Variable () not defined".to_string()))));
+    assert_eq!(run(&format!("<|[{}]> *[]", "()".repeat(16))), (String::new(), Err((Some(16), "Uncaught error with code 16".to_string()))));
+    // and once they cannot happen, the decrement is the error
+    assert_eq!(run("{()}(()) [{()}[]] {()}[*[]]").1.unwrap_err().0, Some(1));
+}
+
+#[test]
+fn uncertain_decrements_of_zero_run() {
+    assert_eq!(run("< *[] | (()) | <{(())}[]> >"), ("1\n".to_string(), Ok(1)));
+    assert_eq!(run("{()}(()) [*[]] <[()]>"), ("1\n".to_string(), Ok(1)));
+}
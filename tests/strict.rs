@@ -0,0 +1,43 @@
+mod common;
+
+use eso_lang::testing;
+use eso_lang::{Options, ProgramError};
+
+fn strict() -> Options {
+    Options { strict: true, ..common::options() }
+}
+
+fn refusal(code: &str) -> String {
+    match testing::run(code, strict()).result {
+        Err(ProgramError::Other(message)) => message,
+        result => panic!("{} was not refused: {:?}", code, result),
+    }
+}
+
+#[test]
+fn strict_refuses_undefined_calls() {
+    assert_eq!(refusal("<[()]> {((()))}[[()]]"), "1:8: Function ((())) is not defined anywhere in the program");
+    // even where it would never run
+    let code = "[[]] | [{((()))}[[()]]] | [[()]]";
+    assert_eq!(refusal(code), "1:9: Function ((())) is not defined anywhere in the program");
+    assert_eq!(testing::run(code, common::options()).result.unwrap(), 1);
+}
+
+#[test]
+fn strict_refuses_wrong_arity() {
+    assert_eq!(refusal("{()}(() (())) [{()}[]] {()}[[()]]"), "1:24: Function () expects 2 arguments, got 1");
+}
+
+#[test]
+fn strict_accepts_faults_inside_try() {
+    let outcome = testing::run("< {((()))}[[()]] | (()) | <{(())}[]> >", strict());
+    assert_eq!(outcome.output, "3\n");
+    assert_eq!(outcome.result.unwrap(), 3);
+}
+
+#[test]
+fn strict_accepts_examples() {
+    for (name, code) in common::examples() {
+        assert!(testing::run(&code, strict()).result.is_ok(), "{}", name);
+    }
+}